@@ -66,16 +66,8 @@
                 ref = "main";
               };
 
-              ########################## IMPORTANT: Update this hash value ###################################
-              #  NOTE: This hash value may be different when you first attempt to build this package         #
-              #  You can get the correct hash value by looking at the nix build output:                      #
-              #  error: hash mismatch in fixed-output derivation '/nix/store/...-nix-incognito-v0.2.1.drv'   #
-              #    specified: sha256:......                                                                  #
-              #    got:       sha256:......                                                                  #
-              #  The value you need is the one that is "got:"                                                #
-              ################################################################################################
-
-              cargoSha256 = "sha256-/mZr9w66tcEUBk977qF5xbLcgCYzUzc6sR/Tv6PAe1E=";
+              # Dependencies are pinned by the committed Cargo.lock, so there is no vendor hash to update
+              cargoLock.lockFile = ./Cargo.lock;
 
               preInstall = ''
                 mkdir -p $out/share/themes
@@ -0,0 +1,12 @@
+//! Parses arbitrary text as a GVariant value, and every value it accepts must print and parse back
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use nix_incognito_fuzz::utils::gvariant;
+
+fuzz_target!(|text: &str| {
+    if let Ok(value) = gvariant::parse(text) {
+        let printed = gvariant::print(&value);
+        assert_eq!(gvariant::parse(&printed), Ok(value), "{}", printed);
+    }
+});
@@ -0,0 +1,12 @@
+//! Parses arbitrary text as a dconf keyfile, and every keyfile it accepts must write back unchanged
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use nix_incognito_fuzz::utils::keyfile::Keyfile;
+
+fuzz_target!(|text: &str| {
+    if let Ok(keyfile) = Keyfile::parse("/", text) {
+        assert_eq!(keyfile.to_string(), text);
+        let _ = keyfile.values();
+    }
+});
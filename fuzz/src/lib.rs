@@ -0,0 +1,10 @@
+//! # Fuzzing Harness
+//! The tool is a binary crate, so the parsers under test are compiled in here from its sources, under the
+//! same module paths they have in the tool.
+//!
+//! Run a target with `cargo fuzz run gvariant` or `cargo fuzz run keyfile` from the repository root.
+
+// A module loaded through `path` resolves the paths of its own modules from its directory
+#[allow(dead_code)]
+#[path = "utils.rs"]
+pub mod utils;
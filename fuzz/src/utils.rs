@@ -0,0 +1,8 @@
+//! The modules of the tool the fuzz targets exercise
+
+#[path = "../../src/utils/error.rs"]
+pub mod error;
+#[path = "../../src/utils/gvariant.rs"]
+pub mod gvariant;
+#[path = "../../src/utils/keyfile.rs"]
+pub mod keyfile;
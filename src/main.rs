@@ -7,37 +7,30 @@
 //!
 //! The tool is implemented as a command-line application using the `clap` crate for argument parsing and the `colored` crate for colored output.
 //! The main function parses arguments using the `Args` struct, which it then executes the appropriate actions based on the provided arguments, such as enabling incognito mode, restoring previous system settings, or displaying help information.
+//! Everything those actions have to say goes through the `Reporter` selected with `--output` and `--silent`.
 //!
-//! The `BANNER`, `OVERVIEW`, and `AFTER_HELP` constants are used to display formatted text in the command-line interface.
+//! The `OVERVIEW` and `AFTER_HELP` constants are used to display formatted text in the command-line interface.
 
 mod utils;
 
-use clap::Parser;
+use clap::error::ErrorKind;
+use clap::parser::ValueSource;
+use clap::{ CommandFactory, FromArgMatches, Parser, Subcommand };
 use colored::Colorize;
 use dirs::*;
-use std::thread::sleep;
-use std::time::Duration;
-use utils::{ incognito, cli };
+use std::path::PathBuf;
+use utils::backend::{ self, BackendKind, SettingsBackend };
+use utils::diff::DiffFormat;
+use utils::error::{ Error, Result };
+use utils::incognito::ConflictPolicy;
+use utils::output::OutputFormat;
+use utils::profile::{ Profile, DEFAULT_PROFILE };
+use utils::reporter::{ self, Event, Operation, Reporter, Silent };
+use utils::snapshot::{ Retention, Store };
+use utils::status::{ Disguise, Status };
+use utils::{ cli, doctor, incognito };
 
-const BANNER: &'static str = color_print::cstr!(
-    r#"<bold><red>
-    
-    
-    ███╗   ██╗██╗██╗  ██╗    ██╗███╗   ██╗ ██████╗ ██████╗  ██████╗ ███╗   ██╗██╗████████╗ ██████╗ 
-    ████╗  ██║██║╚██╗██╔╝    ██║████╗  ██║██╔════╝██╔═══██╗██╔════╝ ████╗  ██║██║╚══██╔══╝██╔═══██╗
-    ██╔██╗ ██║██║ ╚███╔╝     ██║██╔██╗ ██║██║     ██║   ██║██║  ███╗██╔██╗ ██║██║   ██║   ██║   ██║
-    ██║╚██╗██║██║ ██╔██╗     ██║██║╚██╗██║██║     ██║   ██║██║   ██║██║╚██╗██║██║   ██║   ██║   ██║
-    ██║ ╚████║██║██╔╝ ██╗    ██║██║ ╚████║╚██████╗╚██████╔╝╚██████╔╝██║ ╚████║██║   ██║   ╚██████╔╝
-    ╚═╝  ╚═══╝╚═╝╚═╝  ╚═╝    ╚═╝╚═╝  ╚═══╝ ╚═════╝ ╚═════╝  ╚═════╝ ╚═╝  ╚═══╝╚═╝   ╚═╝    ╚═════╝    
-</red>     
-          =============================================================================== 
-          ||  🪪  Created by: dedsyn4ps3       ✨ Inspiration from: Kali (of course!)  ||
-          ===============================================================================
-
-    "#
-);
-
-const OVERVIEW: &'static str = color_print::cstr!(
+const OVERVIEW: &str = color_print::cstr!(
     r#"<bold><red>
 
 
@@ -62,55 +55,368 @@ others to see...
     "#
 );
 
-const AFTER_HELP: &'static str = color_print::cstr!(
+const AFTER_HELP: &str = color_print::cstr!(
     r#"<bold><blue>Examples:</blue></bold>
-  <dim>$</dim> <bold><green>nix-incognito</green> <yellow>--silent --config</yellow> "path/to/use"</bold>        <dim># Enable with no output and custom config path</dim>
-  <dim>$</dim> <bold><green>nix-incognito</green> <yellow>-s -w</yellow> /path/to/wallpaper.jpg</bold>           <dim># Enable with no output using custom wallpaper</dim>
-  <dim>$</dim> <bold><green>nix-incognito</green> <yellow>-i</yellow> "Icon Pack" <yellow>-t</yellow> "Theme"</bold>              <dim># Enable using different theme and icons</dim>
+  <dim>$</dim> <bold><green>nix-incognito</green> enable</bold>                                    <dim># Enable using the defaults</dim>
+  <dim>$</dim> <bold><green>nix-incognito</green> enable <yellow>--silent --config</yellow> "path/to/use"</bold>    <dim># Enable with no output and custom config path</dim>
+  <dim>$</dim> <bold><green>nix-incognito</green> enable <yellow>-i</yellow> "Icon Pack" <yellow>-t</yellow> "Theme"</bold>          <dim># Enable using different theme and icons</dim>
+  <dim>$</dim> <bold><green>nix-incognito</green> diff <yellow>--restore</yellow></bold>                            <dim># Preview what a restore would change</dim>
+  <dim>$</dim> <bold><green>nix-incognito</green> restore</bold>                                   <dim># Put your own desktop back</dim>
+  <dim>$</dim> <bold><green>nix-incognito</green> toggle</bold>                                    <dim># Switch between the disguise and your desktop</dim>
+  <dim>$</dim> <bold><green>nix-incognito</green> snapshot list</bold>                             <dim># List the saved snapshots</dim>
+  <dim>$</dim> <bold><green>nix-incognito</green> restore <yellow>--snapshot</yellow> 20240501-093000-417</bold>    <dim># Restore an older snapshot</dim>
 "#
 );
 
+/// Help heading of the deprecated flag form, also used to tell its flags apart from the global ones
+const LEGACY_HEADING: &str = "Deprecated options (use the subcommands instead)";
+
 #[derive(Parser, Debug)]
-#[command(version, about = OVERVIEW, after_help = AFTER_HELP, styles = cli::get_styles())]
+#[command(
+    version,
+    about = OVERVIEW,
+    after_help = AFTER_HELP,
+    styles = cli::get_styles()
+)]
 struct Args {
     /// Run without any output [Default: false]
-    #[arg(short, long, default_value_t = false)]
+    #[arg(short, long, global = true, default_value_t = false)]
     silent: bool,
 
-    /// Custom config path
-    #[arg(
-        short,
-        long,
-        default_value_t = String::from(
-            format!(
-                "{}/.config/incognito/current_system_config.txt",
-                home_dir().unwrap().to_str().unwrap()
-            )
-        )
-    )]
-    config: String,
+    /// Custom config path [default: ~/.config/incognito/current_system_config.toml]
+    #[arg(short, long, global = true)]
+    config: Option<String>,
+
+    /// Settings backend used to read and write the desktop configuration
+    #[arg(short, long, global = true, value_enum, default_value_t = BackendKind::Command)]
+    backend: BackendKind,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// Deprecated flag form, kept so existing scripts keep working
+    #[command(flatten, next_help_heading = LEGACY_HEADING)]
+    legacy: LegacyArgs,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Snapshot the current desktop and apply the incognito look
+    Enable {
+        #[command(flatten)]
+        look: LookArgs,
+
+        #[command(flatten)]
+        snapshot: SnapshotArgs,
+
+        #[command(flatten)]
+        write: WriteArgs,
+
+        /// Show what would change without writing anything, optionally as JSON
+        #[arg(
+            long,
+            value_name = "FORMAT",
+            value_enum,
+            num_args = 0..=1,
+            default_missing_value = "human"
+        )]
+        dry_run: Option<DiffFormat>,
+
+        /// Output format: colored text, plain text lines, or a single JSON document for scripts
+        #[arg(long, value_enum, default_value = "human")]
+        output: OutputFormat,
+    },
+    /// Disable incognito and restore previous system settings
+    Restore {
+        #[command(flatten)]
+        restore: RestoreArgs,
+
+        #[command(flatten)]
+        write: WriteArgs,
+
+        /// Show what would change without writing anything, optionally as JSON
+        #[arg(
+            long,
+            value_name = "FORMAT",
+            value_enum,
+            num_args = 0..=1,
+            default_missing_value = "human"
+        )]
+        dry_run: Option<DiffFormat>,
+
+        /// Output format: colored text, plain text lines, or a single JSON document for scripts
+        #[arg(long, value_enum, default_value = "human")]
+        output: OutputFormat,
+    },
+    /// Enable incognito, or restore the previous settings if the desktop is already disguised
+    Toggle {
+        #[command(flatten)]
+        look: LookArgs,
+
+        #[command(flatten)]
+        snapshot: SnapshotArgs,
+
+        #[command(flatten)]
+        restore: RestoreArgs,
+
+        #[command(flatten)]
+        write: WriteArgs,
+
+        /// Output format: colored text, plain text lines, or a single JSON document for scripts
+        #[arg(long, value_enum, default_value = "human")]
+        output: OutputFormat,
+    },
+    /// Show whether the desktop is disguised right now, by comparing its settings with the managed keys
+    Status {
+        /// Print only `active`, `partial` or `inactive`, e.g. for a shell prompt
+        #[arg(long, default_value_t = false)]
+        short: bool,
+
+        /// Output format: colored text, plain text lines, or a single JSON document for scripts
+        #[arg(long, value_enum, default_value = "human")]
+        output: OutputFormat,
+    },
+    /// Show what enabling (or restoring) would change, without writing anything
+    Diff {
+        /// Preview a restore instead of an enable
+        #[arg(long, default_value_t = false)]
+        restore: bool,
+
+        /// Format of the printed diff; with --output json the diff is part of the JSON document instead
+        #[arg(long, value_enum, default_value = "human")]
+        format: DiffFormat,
+
+        #[command(flatten)]
+        look: LookArgs,
+
+        #[command(flatten)]
+        restore_args: RestoreArgs,
 
-    /// Custom wallpaper path
+        /// Output format: colored text, plain text lines, or a single JSON document for scripts
+        #[arg(long, value_enum, default_value = "human")]
+        output: OutputFormat,
+    },
+    /// Inspect the saved snapshots
+    #[command(visible_alias = "snapshots")]
+    Snapshot {
+        #[command(subcommand)]
+        action: SnapshotAction,
+
+        /// Output format: colored text, plain text lines, or a single JSON document for scripts
+        #[arg(long, value_enum, default_value = "human", global = true)]
+        output: OutputFormat,
+    },
+    /// Inspect the looks incognito can apply
+    Profile {
+        #[command(subcommand)]
+        action: ProfileAction,
+    },
+    /// Check that this session can run incognito
+    Doctor {
+        #[command(flatten)]
+        look: LookArgs,
+
+        /// Output format: colored text, plain text lines, or a single JSON document for scripts
+        #[arg(long, value_enum, default_value = "human")]
+        output: OutputFormat,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum SnapshotAction {
+    /// List every saved snapshot, newest first
+    List,
+    /// Show the metadata and settings stored in a snapshot
+    Show {
+        /// Id of the snapshot, as printed by `snapshot list`
+        id: String,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum ProfileAction {
+    /// List the available profiles
+    List {
+        /// Output format: colored text, plain text lines, or a single JSON document for scripts
+        #[arg(long, value_enum, default_value = "human")]
+        output: OutputFormat,
+    },
+    /// Show the assets and every setting a profile applies
+    Show {
+        #[command(flatten)]
+        look: LookArgs,
+
+        /// Output format: colored text, plain text lines, or a single JSON document for scripts
+        #[arg(long, value_enum, default_value = "human")]
+        output: OutputFormat,
+    },
+    /// Print the merged settings of layered profiles and the profile each one comes from
+    Resolve {
+        #[command(flatten)]
+        look: LookArgs,
+
+        /// Output format: colored text, plain text lines, or a single JSON document for scripts
+        #[arg(long, value_enum, default_value = "human")]
+        output: OutputFormat,
+    },
+    /// Start recording a new profile from the tweaks you make to your desktop
+    Record {
+        /// Name of the profile to record
+        name: String,
+
+        /// Start over if a recording is already in progress
+        #[arg(long)]
+        force: bool,
+    },
+    /// Stop recording and save every setting you changed as a profile
+    Finish {
+        /// Profile file to write [Default: ~/.config/incognito/profiles/<name>.toml]
+        #[arg(short, long, value_name = "FILE")]
+        output: Option<PathBuf>,
+
+        /// Overwrite the profile file if it already exists
+        #[arg(long)]
+        force: bool,
+    },
+    /// Stop recording without saving anything
+    Cancel,
+}
+
+/// Options describing the incognito look
+#[derive(clap::Args, Debug, Clone)]
+struct LookArgs {
+    /// Profile to apply, by name (e.g. win10, macos) or path to a profile file; repeat or separate with
+    /// commas to layer overlays in order (e.g. win11,single-workspace)
     #[arg(
         short,
         long,
-        default_value_t = String::from(
-            "/run/current-system/sw/share/backgrounds/incognito/win11.jpg"
-        )
+        visible_alias = "look",
+        value_delimiter = ',',
+        default_values_t = [String::from(DEFAULT_PROFILE)]
     )]
-    wallpaper: String,
+    profile: Vec<String>,
+
+    /// Profile file to apply instead of --profile, e.g. one written by `profile finish`
+    #[arg(long, value_name = "FILE", conflicts_with = "profile")]
+    from: Option<PathBuf>,
+
+    /// Custom wallpaper path [Default: from the profile]
+    #[arg(short, long)]
+    wallpaper: Option<String>,
+
+    /// User theme to implement [Default: from the profile]
+    #[arg(short, long)]
+    theme: Option<String>,
+
+    /// Icon theme to implement [Default: from the profile]
+    #[arg(short, long)]
+    icons: Option<String>,
+}
+
+impl LookArgs {
+    /// Loads and merges the selected profiles with the assets given on the command line filled in
+    fn resolve(self) -> Result<Profile> {
+        let profile = match &self.from {
+            Some(file) => Profile::from_file(file)?,
+            None => Profile::compose(&self.profile)?,
+        };
+
+        Ok(profile.with_assets(self.wallpaper, self.theme, self.icons))
+    }
+}
+
+/// Options controlling how snapshots are taken and kept
+#[derive(clap::Args, Debug, Clone)]
+struct SnapshotArgs {
+    /// Take a new snapshot even if incognito is already active
+    #[arg(long, default_value_t = false)]
+    force_resnapshot: bool,
+
+    /// Number of saved snapshots to keep
+    #[arg(long, value_name = "N", default_value_t = 10)]
+    keep_snapshots: usize,
+
+    /// Remove saved snapshots older than this many days
+    #[arg(long, value_name = "DAYS")]
+    prune_older_than: Option<u32>,
+}
 
-    /// User theme to implement
-    #[arg(short, long, default_value_t = String::from("Fluent-Round-Dark"))]
-    theme: String,
+/// Options controlling what a restore puts back
+#[derive(clap::Args, Debug, Clone)]
+struct RestoreArgs {
+    /// Restore every saved setting instead of only the ones incognito changed
+    #[arg(long, default_value_t = false)]
+    full: bool,
 
-    /// Icon theme to implement
-    #[arg(short, long, default_value_t = String::from("Windows-Eleven"))]
-    icons: String,
+    /// What to do with settings changed while incognito was active
+    #[arg(long, value_enum, default_value_t = ConflictPolicy::Prompt)]
+    on_conflict: ConflictPolicy,
 
+    /// Restore the saved snapshot with this id instead of the config file
+    #[arg(long = "snapshot", value_name = "ID")]
+    snapshot_id: Option<String>,
+}
+
+/// Options controlling how settings are written
+#[derive(clap::Args, Debug, Clone, Copy)]
+struct WriteArgs {
+    /// If the backend rejects the settings as a whole, write them one at a time and keep the ones it
+    /// accepts, instead of leaving the desktop unchanged
+    #[arg(long, default_value_t = false)]
+    per_key: bool,
+}
+
+/// The original single-command form: enable by default, restore with `-r`
+#[derive(clap::Args, Debug)]
+struct LegacyArgs {
     /// Disable incognito and restore previous system settings
     #[arg(short, long, default_value_t = false)]
     restore: bool,
+
+    /// Show what would change without writing anything, optionally as JSON
+    #[arg(
+        long,
+        value_name = "FORMAT",
+        value_enum,
+        num_args = 0..=1,
+        default_missing_value = "human"
+    )]
+    dry_run: Option<DiffFormat>,
+
+    #[command(flatten)]
+    look: LookArgs,
+
+    #[command(flatten)]
+    snapshot: SnapshotArgs,
+
+    #[command(flatten)]
+    restore_args: RestoreArgs,
+
+    #[command(flatten)]
+    write: WriteArgs,
+}
+
+impl LegacyArgs {
+    /// Translates the flag form into the equivalent subcommand
+    fn into_command(self) -> Command {
+        match self.restore {
+            true =>
+                Command::Restore {
+                    restore: self.restore_args,
+                    write: self.write,
+                    dry_run: self.dry_run,
+                    output: OutputFormat::Human,
+                },
+            false =>
+                Command::Enable {
+                    look: self.look,
+                    snapshot: self.snapshot,
+                    write: self.write,
+                    dry_run: self.dry_run,
+                    output: OutputFormat::Human,
+                },
+        }
+    }
 }
 
 /// ## Primary entry-point of the tool
@@ -118,89 +424,338 @@ struct Args {
 /// This function is the entry point of the application. It parses the command-line arguments using the `Args` struct,
 /// performs the necessary actions based on the provided arguments, and prints output to the console.
 ///
+/// Running the tool without a subcommand keeps the original flag form working: it enables incognito,
+/// or restores the previous settings when `-r` is given, after printing a deprecation notice.
+///
 /// ## Example:
 /// ```
 /// let args = Args::parse();
 ///
-/// match args.silent {
-///     true => {
-///         incognito::save_current_system(true, args.config);
-///         incognito::enable_incognito(args.wallpaper, args.theme, args.icons, true);
-///     }
-///     false => {
-///         println!();
-///         println!("{}", BANNER);
-///         print!("\n\n");
-///         println!("💬 {}", "Running in Verbose Mode".cyan().bold());
-///
-///         incognito::save_current_system(false, args.config);
-///         incognito::enable_incognito(args.wallpaper, args.theme, args.icons, false);
-///     }
+/// match args.command {
+///     Some(Command::Enable { look, snapshot, dry_run: None, .. }) =>
+///         enable(args.backend, reporter.as_mut(), &config, &look.resolve()?, snapshot),
+///     Some(Command::Restore { restore, dry_run: None, .. }) =>
+///         restore(args.backend, reporter.as_mut(), &config, restore),
+///     ...
 /// }
 /// ```
 ///
 fn main() {
-    let args = Args::parse();
-
-    match args.restore {
-        true => {
-            match args.silent {
-                true => {
-                    incognito::load_previous_system(args.config);
-                }
-                false => {
-                    // Clear terminal screen
-                    print!("{esc}c", esc = 27 as char);
-                    println!();
-                    println!("{}", BANNER);
-                    println!();
-
-                    println!(
-                        "         ┏━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━ {} ━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━┓",
-                        "Updating System".yellow().bold()
-                    );
-                    println!();
-                    println!(
-                        "            🗃️ {}",
-                        "Restoring previous system settings...".magenta().bold()
-                    );
-
-                    sleep(Duration::from_secs(1));
-                    incognito::load_previous_system(args.config);
-
-                    println!(
-                        "\n         ┗━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━┛"
-                    );
-                }
-            }
+    let matches = Args::command().get_matches();
+    let args = Args::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+
+    if args.command.is_some() {
+        let legacy = Args::command()
+            .get_arguments()
+            .filter(|arg| arg.get_help_heading() == Some(LEGACY_HEADING))
+            .find(|arg| matches.value_source(arg.get_id().as_str()) == Some(ValueSource::CommandLine))
+            .and_then(|arg| arg.get_long().map(String::from));
+
+        if let Some(flag) = legacy {
+            Args::command()
+                .error(
+                    ErrorKind::ArgumentConflict,
+                    format!("--{} belongs to the deprecated flag form and can't be used with a subcommand", flag)
+                )
+                .exit();
         }
-        false => {
-            match args.silent {
-                true => {
-                    incognito::save_current_system(true, args.config);
-                    incognito::enable_incognito(args.wallpaper, args.theme, args.icons, true);
-                }
-                false => {
-                    // Clear terminal screen
-                    print!("{esc}c", esc = 27 as char);
-                    println!();
-                    println!("{}", BANNER);
-                    println!();
-
-                    println!(
-                        "         ┏━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━ {} ━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━┓",
-                        "Updating System".yellow().bold()
-                    );
-
-                    sleep(Duration::from_secs(1));
-                    incognito::save_current_system(false, args.config);
-                    incognito::enable_incognito(args.wallpaper, args.theme, args.icons, false);
-
-                    println!(
-                        "\n         ┗━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━┛"
-                    );
-                }
+    }
+
+    let command = match args.command {
+        Some(command) => command,
+        None => {
+            if !args.silent {
+                eprintln!(
+                    "⚠️ {} use `nix-incognito enable` or `nix-incognito restore` instead\n",
+                    "The flag form is deprecated:".yellow().bold()
+                );
             }
+            args.legacy.into_command()
+        }
+    };
+
+    let mut reporter = reporter::new(command.output(), args.silent, command.name());
+    let reporter = reporter.as_mut();
+
+    let result = config_path(args.config, home_dir()).and_then(|config| match command {
+        | Command::Enable { look, dry_run: Some(format), .. }
+        | Command::Diff { restore: false, format, look, .. } =>
+            look.resolve().and_then(|profile| {
+                let backend = connect_to_preview(args.backend, format, reporter);
+                incognito::preview_enable(backend.as_ref(), &profile, format, reporter)
+            }),
+        Command::Enable { look, snapshot, write, dry_run: None, .. } =>
+            look.resolve().and_then(|profile| enable(args.backend, reporter, &config, &profile, snapshot, write)),
+        | Command::Restore { restore: options, dry_run: Some(format), .. }
+        | Command::Diff { restore: true, format, restore_args: options, .. } => {
+            let backend = connect_to_preview(args.backend, format, reporter);
+            incognito::preview_restore(
+                backend.as_ref(),
+                &config,
+                options.snapshot_id.as_deref(),
+                options.full,
+                options.on_conflict,
+                format,
+                reporter
+            )
+        }
+        Command::Restore { restore: options, write, dry_run: None, .. } =>
+            restore(args.backend, reporter, &config, options, write),
+        Command::Toggle { look, snapshot, restore: options, write, .. } =>
+            toggle(args.backend, reporter, &config, look, snapshot, options, write),
+        Command::Status { short, .. } => {
+            // A fallback warning would end up in the middle of a shell prompt
+            let backend = match short {
+                true => connect(args.backend, &mut Silent),
+                false => connect(args.backend, reporter),
+            };
+            incognito::report_status(backend.as_ref(), &config, short, reporter)
+        }
+        Command::Snapshot { action: SnapshotAction::List, .. } => incognito::list_snapshots(&config, reporter),
+        Command::Snapshot { action: SnapshotAction::Show { id }, .. } => incognito::show_snapshot(&id, reporter),
+        Command::Profile { action: ProfileAction::List { .. } } => {
+            incognito::list_profiles(reporter);
+            Ok(())
+        }
+        Command::Profile { action: ProfileAction::Show { look, .. } } =>
+            look.resolve().and_then(|profile| incognito::show_profile(&profile, reporter)),
+        Command::Profile { action: ProfileAction::Resolve { look, .. } } =>
+            look.resolve().and_then(|profile| incognito::resolve_profile(&profile, reporter)),
+        Command::Profile { action: ProfileAction::Record { name, force } } => {
+            let backend = connect(args.backend, reporter);
+            incognito::start_recording(backend.as_ref(), &name, force, reporter)
+        }
+        Command::Profile { action: ProfileAction::Finish { output, force } } => {
+            let backend = connect(args.backend, reporter);
+            incognito::finish_recording(backend.as_ref(), output, force, reporter)
+        }
+        Command::Profile { action: ProfileAction::Cancel } => incognito::cancel_recording(reporter),
+        Command::Doctor { look, .. } => look.resolve().and_then(|profile| doctor::run(&config, &profile, reporter)),
+    });
+
+    std::process::exit(reporter.finish(result));
+}
+
+impl Command {
+    /// Name of the command, as reported in JSON output
+    fn name(&self) -> &'static str {
+        match self {
+            Command::Enable { .. } => "enable",
+            Command::Restore { .. } => "restore",
+            Command::Toggle { .. } => "toggle",
+            Command::Status { .. } => "status",
+            Command::Diff { .. } => "diff",
+            Command::Snapshot { .. } => "snapshot",
+            Command::Profile { .. } => "profile",
+            Command::Doctor { .. } => "doctor",
+        }
+    }
+
+    /// Output format selected for the command, `Human` for commands without `--output`
+    fn output(&self) -> OutputFormat {
+        match self {
+            | Command::Enable { output, .. }
+            | Command::Restore { output, .. }
+            | Command::Toggle { output, .. }
+            | Command::Status { output, .. }
+            | Command::Diff { output, .. }
+            | Command::Snapshot { output, .. }
+            | Command::Doctor { output, .. }
+            | Command::Profile { action: ProfileAction::List { output }, .. }
+            | Command::Profile { action: ProfileAction::Show { output, .. }, .. }
+            | Command::Profile { action: ProfileAction::Resolve { output, .. }, .. } => *output,
+            _ => OutputFormat::Human,
+        }
+    }
+}
+
+/// **Path of the config snapshot**: `config` when given with `--config`, or its default place in `home`
+fn config_path(config: Option<String>, home: Option<PathBuf>) -> Result<String> {
+    if let Some(config) = config {
+        return Ok(config);
+    }
+
+    let home = home.ok_or_else(|| Error::Other("Couldn't find your home directory: pass --config".into()))?;
+    home
+        .join(".config/incognito/current_system_config.toml")
+        .into_os_string()
+        .into_string()
+        .map_err(|path| {
+            Error::Other(format!("{} isn't valid UTF-8: pass --config", PathBuf::from(path).display()))
+        })
+}
+
+/// Connects to the selected backend, reporting it and whether it had to fall back
+fn connect(kind: BackendKind, reporter: &mut dyn Reporter) -> Box<dyn SettingsBackend> {
+    let (backend, fallback) = backend::connect(kind);
+    announce(backend.as_ref(), fallback, reporter);
+
+    backend
+}
+
+/// Connects to the backend for a preview in `format`, without a word when the preview is a JSON document
+/// that a warning on stdout would break
+fn connect_to_preview(
+    kind: BackendKind,
+    format: DiffFormat,
+    reporter: &mut dyn Reporter
+) -> Box<dyn SettingsBackend> {
+    match format {
+        DiffFormat::Human => connect(kind, reporter),
+        DiffFormat::Json => connect(kind, &mut Silent),
+    }
+}
+
+/// Reports the backend in use, and why it was used instead of the selected one
+fn announce(backend: &dyn SettingsBackend, fallback: Option<Error>, reporter: &mut dyn Reporter) {
+    if let Some(why) = fallback {
+        reporter.emit(Event::Warning { message: "Falling back to the dconf command:", detail: why.to_string() });
+    }
+    reporter.emit(Event::Backend(backend.name()));
+}
+
+/// Snapshot the current desktop and apply the incognito look
+fn enable(
+    kind: BackendKind,
+    reporter: &mut dyn Reporter,
+    config: &str,
+    profile: &Profile,
+    snapshot: SnapshotArgs,
+    write: WriteArgs
+) -> Result<()> {
+    let retention = Retention {
+        keep: snapshot.keep_snapshots,
+        max_age_days: snapshot.prune_older_than,
+    };
+
+    reporter.emit(Event::Begin { operation: Operation::Enable, config });
+    let backend = connect(kind, reporter);
+    let result = engage(backend.as_ref(), reporter, config, profile, snapshot.force_resnapshot, retention, write);
+    reporter.emit(Event::End);
+
+    result
+}
+
+/// Checks the profile, snapshots the desktop into the user's snapshot store (unless incognito is active and
+/// `force` isn't set) and applies the profile
+fn engage(
+    backend: &dyn SettingsBackend,
+    reporter: &mut dyn Reporter,
+    config: &str,
+    profile: &Profile,
+    force: bool,
+    retention: Retention,
+    write: WriteArgs
+) -> Result<()> {
+    incognito
+        ::validate_profile(profile, reporter)
+        .and_then(|_| Store::open())
+        .and_then(|store| incognito::snapshot_current_system(backend, &store, reporter, config, force, retention))
+        .and_then(|_| incognito::enable_incognito(backend, config, profile, write.per_key, reporter))
+        .and_then(|report| report.into_result())
+}
+
+/// Disable incognito and restore previous system settings
+fn restore(
+    kind: BackendKind,
+    reporter: &mut dyn Reporter,
+    config: &str,
+    options: RestoreArgs,
+    write: WriteArgs
+) -> Result<()> {
+    reporter.emit(Event::Begin { operation: Operation::Restore, config });
+    let backend = connect(kind, reporter);
+    let result = incognito
+        ::load_previous_system(
+            backend.as_ref(),
+            config,
+            options.snapshot_id.as_deref(),
+            options.full,
+            options.on_conflict,
+            write.per_key,
+            reporter
+        )
+        .and_then(|report| report.into_result());
+    reporter.emit(Event::End);
+
+    result
+}
+
+/// **Enable incognito, or restore the previous settings if the desktop is already disguised**
+///
+/// Whether it is disguised is read from the managed keys of the live desktop rather than from the saved
+/// state, which may be stale. When it isn't, the desktop holds the user's real settings, so they're
+/// snapshotted before enabling even if the state says incognito is active, unless they're exactly the
+/// settings of the saved snapshot, which is then used again. When it is (even partially), the snapshot incognito was
+/// enabled from is restored, from the archive if the config file no longer holds it.
+fn toggle(
+    kind: BackendKind,
+    reporter: &mut dyn Reporter,
+    config: &str,
+    look: LookArgs,
+    snapshot: SnapshotArgs,
+    options: RestoreArgs,
+    write: WriteArgs
+) -> Result<()> {
+    let (backend, fallback) = backend::connect(kind);
+    let status = Status::check(backend.as_ref(), config)?;
+
+    match status.disguise {
+        Disguise::Inactive => {
+            let profile = look.resolve()?;
+            let retention = Retention {
+                keep: snapshot.keep_snapshots,
+                max_age_days: snapshot.prune_older_than,
+            };
+
+            reporter.emit(Event::Begin { operation: Operation::Enable, config });
+            announce(backend.as_ref(), fallback, reporter);
+            let result = engage(backend.as_ref(), reporter, config, &profile, true, retention, write);
+            reporter.emit(Event::End);
+
+            result
+        }
+        Disguise::Active | Disguise::Partial => {
+            reporter.emit(Event::Begin { operation: Operation::Restore, config });
+            announce(backend.as_ref(), fallback, reporter);
+            let picked = match options.snapshot_id {
+                Some(id) => Ok(Some(id)),
+                None => incognito::pick_snapshot(config, status.state.as_ref(), reporter),
+            };
+            let result = picked
+                .and_then(|snapshot_id| {
+                    incognito::load_previous_system(
+                        backend.as_ref(),
+                        config,
+                        snapshot_id.as_deref(),
+                        options.full,
+                        options.on_conflict,
+                        write.per_key,
+                        reporter
+                    )
+                })
+                .and_then(|report| report.into_result());
+            reporter.emit(Event::End);
+
+            result
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn config_path_needs_a_home_only_without_config() {
+        assert_eq!(config_path(Some("custom.toml".into()), None).unwrap(), "custom.toml");
+        assert_eq!(
+            config_path(None, Some(PathBuf::from("/home/user"))).unwrap(),
+            "/home/user/.config/incognito/current_system_config.toml"
+        );
+        assert!(matches!(config_path(None, None), Err(Error::Other(_))));
+
+        let home = <std::ffi::OsString as std::os::unix::ffi::OsStringExt>::from_vec(b"/home/\xff".to_vec());
+        assert!(matches!(config_path(None, Some(home.into())), Err(Error::Other(_))));
+    }
+}
@@ -0,0 +1,286 @@
+//! # Apply Module
+//! This module writes a changeset to a `SettingsBackend` and reports what happened to every key in an
+//! **`ApplyReport`**: applied, already holding the value, failed, or skipped.
+//!
+//! Keys that already hold their new value aren't written at all. The remaining ones are committed as a
+//! single transaction: when the backend rejects it, every one of them is reported as failed and the
+//! desktop is left as it was. Only in **per-key** mode (`--per-key`) are they then written one at a time,
+//! so a single bad key can't keep the others from being applied, and the report tells exactly which keys
+//! failed.
+//!
+//! Every written key is read back before it counts as applied, and its outcome is emitted to the
+//! `Reporter` as soon as it's known, so progress follows the backend rather than a timer.
+//!
+//! A report that isn't a full success turns into an `Error::Apply`, whose exit code tells a partial
+//! failure apart from one where nothing could be applied. A rejected transaction is always the latter, even
+//! if some keys already held their new value: none of the ones that needed writing were written.
+use crate::utils::backend::SettingsBackend;
+use crate::utils::error::{ Error, Result };
+use crate::utils::gvariant::Variant;
+use crate::utils::reporter::{ Event, Reporter };
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+/// **What happened to a single key**, serialized as `{ "status": "failed", "reason": "..." }`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "status", content = "reason", rename_all = "lowercase")]
+pub enum Outcome {
+    /// The key was written
+    Applied,
+    /// The key already held its new value, so it wasn't written
+    Unchanged,
+    /// The backend refused the key, with the reason it gave
+    Failed(String),
+    /// The key wasn't attempted, because an earlier failure made it pointless
+    Skipped(String),
+}
+
+impl Outcome {
+    /// Whether the key holds its new value once the changeset has been applied
+    pub fn is_ok(&self) -> bool {
+        matches!(self, Outcome::Applied | Outcome::Unchanged)
+    }
+}
+
+/// **Outcome of every key of a changeset**, ordered by key
+#[derive(Debug, Clone, Default)]
+pub struct ApplyReport {
+    pub keys: BTreeMap<String, Outcome>,
+    /// Whether the backend rejected the changeset as a whole, so nothing was written
+    pub rejected: bool,
+}
+
+impl ApplyReport {
+    /// Keys that hold their new value, whether they were written or already did
+    pub fn succeeded(&self) -> impl Iterator<Item = &String> {
+        self.keys
+            .iter()
+            .filter(|(_, outcome)| outcome.is_ok())
+            .map(|(key, _)| key)
+    }
+
+    /// Whether every key holds its new value
+    pub fn is_complete(&self) -> bool {
+        self.keys.values().all(Outcome::is_ok)
+    }
+
+    /// **Turns the report into a `Result`**
+    ///
+    /// ## Returns:
+    ///
+    /// `Ok` if every key holds its new value, or an `Error::Apply` counting the keys that don't, all of
+    /// them when the changeset was rejected.
+    pub fn into_result(self) -> Result<()> {
+        let total = self.keys.len();
+
+        match (self.is_complete(), self.rejected) {
+            (true, _) => Ok(()),
+            (false, true) => Err(Error::Apply { failed: total, total }),
+            (false, false) => Err(Error::Apply { failed: total - self.succeeded().count(), total }),
+        }
+    }
+}
+
+/// **Applies `changes` to `backend` as a single transaction**
+///
+/// ## Args:
+///
+/// * `backend` - The backend to write to
+/// * `changes` - New value of every key, `None` to reset it
+/// * `per_key` - Whether to write the keys one at a time if the backend rejects the transaction
+/// * `reporter` - Where the outcome of every key is emitted, as soon as it's known
+///
+/// ## Returns:
+///
+/// The outcome of every key in `changes`. Failures are reported per key rather than as an `Error`.
+pub fn apply(
+    backend: &dyn SettingsBackend,
+    changes: &BTreeMap<String, Option<Variant>>,
+    per_key: bool,
+    reporter: &mut dyn Reporter
+) -> ApplyReport {
+    let mut report = ApplyReport::default();
+    reporter.emit(Event::Writing { total: changes.len() });
+
+    // A key that can't be read is written anyway, writing it will tell whether it's really broken
+    let pending: BTreeMap<String, Option<Variant>> = changes
+        .iter()
+        .filter(|(key, value)| {
+            let unchanged = backend
+                .read(key)
+                .map(|current| current == **value)
+                .unwrap_or(false);
+            if unchanged {
+                reporter.emit(Event::Key { key, outcome: &Outcome::Unchanged });
+                report.keys.insert(key.to_string(), Outcome::Unchanged);
+            }
+            !unchanged
+        })
+        .map(|(key, value)| (key.clone(), value.clone()))
+        .collect();
+
+    let rejected = match pending.is_empty() {
+        true => None,
+        false => backend.apply(&pending).err(),
+    };
+    match rejected {
+        None => {
+            for (key, value) in pending {
+                let outcome = verify(backend, &key, &value);
+                reporter.emit(Event::Key { key: &key, outcome: &outcome });
+                report.keys.insert(key, outcome);
+            }
+            return report;
+        }
+        // The backend doesn't tell which key it rejected, so none of them count as written
+        Some(why) if !per_key => {
+            let outcome = Outcome::Failed(format!("the changeset was rejected: {}", why));
+            for key in pending.into_keys() {
+                reporter.emit(Event::Key { key: &key, outcome: &outcome });
+                report.keys.insert(key, outcome.clone());
+            }
+            report.rejected = true;
+            return report;
+        }
+        Some(_) => (),
+    }
+
+    let mut abort: Option<String> = None;
+    for (key, value) in pending {
+        if let Some(why) = &abort {
+            let outcome = Outcome::Skipped(why.clone());
+            reporter.emit(Event::Key { key: &key, outcome: &outcome });
+            report.keys.insert(key, outcome);
+            continue;
+        }
+
+        let written = match &value {
+            Some(value) => backend.write(&key, value),
+            None => backend.reset(&key),
+        };
+        let outcome = match written {
+            Ok(_) => verify(backend, &key, &value),
+            // Without the tool nothing else can be written either
+            Err(why @ Error::MissingTool(_)) => {
+                abort = Some(why.to_string());
+                Outcome::Failed(why.to_string())
+            }
+            Err(why) => Outcome::Failed(why.to_string()),
+        };
+        reporter.emit(Event::Key { key: &key, outcome: &outcome });
+        report.keys.insert(key, outcome);
+    }
+
+    report
+}
+
+/// **Reads a written key back to check it holds `value`**
+///
+/// A key that can't be read back is trusted to have been written, since the backend accepted it.
+fn verify(backend: &dyn SettingsBackend, key: &str, value: &Option<Variant>) -> Outcome {
+    match backend.read(key) {
+        Ok(current) if current != *value => {
+            let current = current.map_or_else(|| "nothing".to_string(), |current| current.to_string());
+            Outcome::Failed(format!("reads back {} after writing", current))
+        }
+        _ => Outcome::Applied,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::backend::fixtures::{ desktop, string, ICONS, THEME, WALLPAPER };
+    use crate::utils::backend::MemoryBackend;
+    use crate::utils::reporter::Silent;
+
+    /// Records every event along with what the backend holds at that moment
+    struct Progress<'a> {
+        backend: &'a MemoryBackend,
+        events: Vec<String>,
+    }
+
+    impl Reporter for Progress<'_> {
+        fn emit(&mut self, event: Event) {
+            match event {
+                Event::Writing { total } => self.events.push(format!("writing {}", total)),
+                Event::Key { key, outcome } => {
+                    let current = self.backend.read(key).unwrap().unwrap();
+                    self.events.push(format!("{} {:?} holds {}", key, outcome, current));
+                }
+                _ => (),
+            }
+        }
+
+        fn finish(&mut self, result: Result<()>) -> i32 {
+            result.err().map_or(0, |e| e.exit_code())
+        }
+    }
+
+    fn changes(values: &[(&str, &str)]) -> BTreeMap<String, Option<Variant>> {
+        values
+            .iter()
+            .map(|(key, value)| (key.to_string(), Some(string(value))))
+            .collect()
+    }
+
+    #[test]
+    fn a_rejected_changeset_changes_nothing() {
+        let backend = desktop().rejecting(ICONS);
+        let before = backend.values.borrow().clone();
+
+        let report = apply(&backend, &changes(&[(THEME, "Fluent"), (ICONS, "Windows-Eleven")]), false, &mut Silent);
+        assert!(report.keys.values().all(|outcome| matches!(outcome, Outcome::Failed(_))));
+        assert_eq!(*backend.values.borrow(), before);
+        assert_eq!(report.into_result().unwrap_err().exit_code(), 10);
+
+        // Keys already holding their value didn't need writing, so nothing was applied all the same
+        let report = apply(&backend, &changes(&[(THEME, "Yaru"), (ICONS, "Windows-Eleven")]), false, &mut Silent);
+        assert_eq!(report.keys[THEME], Outcome::Unchanged);
+        assert_eq!(*backend.values.borrow(), before);
+        assert_eq!(report.into_result().unwrap_err().exit_code(), 10);
+
+        // Writing key by key is what makes a failure partial
+        let report = apply(&backend, &changes(&[(THEME, "Yaru"), (ICONS, "Windows-Eleven")]), true, &mut Silent);
+        assert!(!report.rejected);
+        assert_eq!(report.into_result().unwrap_err().exit_code(), 9);
+    }
+
+    #[test]
+    fn per_key_writes_every_key_the_backend_accepts() {
+        let backend = desktop().rejecting(ICONS);
+
+        let changeset = changes(&[(THEME, "Fluent"), (ICONS, "Windows-Eleven"), (WALLPAPER, "win11.jpg")]);
+        let report = apply(&backend, &changeset, true, &mut Silent);
+        assert_eq!(report.keys[THEME], Outcome::Applied);
+        assert_eq!(report.keys[WALLPAPER], Outcome::Applied);
+        assert!(matches!(report.keys[ICONS], Outcome::Failed(_)));
+        assert_eq!(backend.read(THEME).unwrap(), Some(string("Fluent")));
+        assert_eq!(backend.read(ICONS).unwrap(), Some(string("Yaru")));
+        assert_eq!(report.into_result().unwrap_err().exit_code(), 9);
+    }
+
+    #[test]
+    fn an_accepted_changeset_is_read_back() {
+        let backend = desktop();
+
+        let report = apply(&backend, &changes(&[(THEME, "Fluent"), (ICONS, "Yaru")]), false, &mut Silent);
+        assert_eq!(report.keys[THEME], Outcome::Applied);
+        assert_eq!(report.keys[ICONS], Outcome::Unchanged);
+        assert!(report.into_result().is_ok());
+    }
+
+    #[test]
+    fn every_key_is_reported_once_it_holds_its_value() {
+        let backend = desktop();
+        let mut progress = Progress { backend: &backend, events: Vec::new() };
+
+        apply(&backend, &changes(&[(THEME, "Fluent"), (ICONS, "Yaru"), (WALLPAPER, "win11.jpg")]), false, &mut progress);
+        assert_eq!(progress.events, [
+            "writing 3".to_string(),
+            format!("{} Unchanged holds 'Yaru'", ICONS),
+            format!("{} Applied holds 'win11.jpg'", WALLPAPER),
+            format!("{} Applied holds 'Fluent'", THEME),
+        ]);
+    }
+}
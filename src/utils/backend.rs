@@ -0,0 +1,241 @@
+//! # Settings Backend Module
+//! This module defines the **`SettingsBackend`** trait, which is the single point through which the tool
+//! reads and writes desktop settings, along with the **`CommandBackend`** implementation that drives the
+//! `dconf` command-line utility. The native D-Bus implementation lives in the `dconf` module.
+//!
+//! Keeping the incognito logic behind this trait allows it to target other desktops (or an in-memory
+//! fake) without a live GNOME session.
+//!
+//! Keys are always addressed by their full dconf path (e.g. `/org/gnome/desktop/background/picture-uri`),
+//! directories by a path ending in `/`, and values are passed around as typed `Variant`s. Text only
+//! appears at the edges: the `dconf` command and the keyfiles produced by `dump`.
+use crate::utils::dconf::DconfBackend;
+use crate::utils::error::{ Error, Result };
+use crate::utils::gsettings;
+use crate::utils::gvariant::{ self, Variant };
+use crate::utils::keyfile::Keyfile;
+use std::collections::BTreeMap;
+
+/// **Common interface implemented by every settings store the tool can drive**
+pub trait SettingsBackend {
+    /// Short name of the backend, used in user-facing messages
+    fn name(&self) -> &'static str;
+
+    /// Reads the current value of `key`, returning `None` if the key is unset
+    fn read(&self, key: &str) -> Result<Option<Variant>>;
+
+    /// Writes `value` to `key`
+    fn write(&self, key: &str, value: &Variant) -> Result<()>;
+
+    /// Resets `key` to its default value, or every key below it when `key` is a directory
+    fn reset(&self, key: &str) -> Result<()>;
+
+    /// Dumps every key below `dir` set in the user database, in the dconf keyfile format
+    fn dump(&self, dir: &str) -> Result<String>;
+
+    /// Writes every key in `changes` as a single transaction, so either all of them are applied or none are.
+    /// Keys mapped to `None` are reset to their default value.
+    fn apply(&self, changes: &BTreeMap<String, Option<Variant>>) -> Result<()>;
+}
+
+/// **Settings backends selectable from the command line**
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BackendKind {
+    /// Talk to dconf over D-Bus, falling back to `command` if the session bus is unavailable
+    Dbus,
+    /// Spawn the `dconf` command-line utility for every operation
+    Command,
+}
+
+/// **Creates the backend selected on the command line**
+///
+/// ## Returns:
+///
+/// The backend, along with the `Error` that made the requested backend unavailable if the
+/// `dconf` command had to be used instead.
+pub fn connect(kind: BackendKind) -> (Box<dyn SettingsBackend>, Option<Error>) {
+    match kind {
+        BackendKind::Dbus =>
+            match DconfBackend::new() {
+                Ok(backend) => (Box::new(backend), None),
+                Err(why) => (Box::new(CommandBackend), Some(why)),
+            }
+        BackendKind::Command => (Box::new(CommandBackend), None),
+    }
+}
+
+/// **Backend that spawns a `dconf` process for every operation**
+///
+/// This is the tool's original behavior and works anywhere the `dconf` binary is installed.
+#[derive(Debug, Default)]
+pub struct CommandBackend;
+
+impl SettingsBackend for CommandBackend {
+    fn name(&self) -> &'static str {
+        "dconf (command)"
+    }
+
+    fn read(&self, key: &str) -> Result<Option<Variant>> {
+        gsettings
+            ::get_dconf(key)?
+            .map(|value| {
+                gvariant::parse(&value).map_err(|why| Error::Tool {
+                    tool: "dconf",
+                    message: format!("returned an invalid value for {}: {}", key, why),
+                })
+            })
+            .transpose()
+    }
+
+    fn write(&self, key: &str, value: &Variant) -> Result<()> {
+        gsettings::set_dconf(key, &gvariant::print(value))
+    }
+
+    fn reset(&self, key: &str) -> Result<()> {
+        gsettings::reset_dconf(key)
+    }
+
+    fn dump(&self, dir: &str) -> Result<String> {
+        gsettings::dump_dconf(dir)
+    }
+
+    /// `dconf load` commits a whole keyfile in one write, so the new values are sent as a partial keyfile.
+    /// The command has no way to reset keys in the same write, so resets follow one `dconf reset` at a time,
+    /// and the keys are put back to their previous values if one of those fails.
+    fn apply(&self, changes: &BTreeMap<String, Option<Variant>>) -> Result<()> {
+        let previous = changes
+            .keys()
+            .map(|key| Ok((key.clone(), self.read(key)?)))
+            .collect::<Result<BTreeMap<_, _>>>()?;
+
+        match self.commit(changes) {
+            Ok(()) => Ok(()),
+            Err(why) =>
+                match self.commit(&previous) {
+                    Ok(()) => Err(why),
+                    Err(rollback) =>
+                        Err(Error::Tool {
+                            tool: "dconf",
+                            message: format!("left some keys changed: {} (putting them back failed: {})", why, rollback),
+                        }),
+                }
+        }
+    }
+}
+
+impl CommandBackend {
+    /// Loads the new values of `changes`, then resets the keys it maps to `None`
+    fn commit(&self, changes: &BTreeMap<String, Option<Variant>>) -> Result<()> {
+        let writes = changes
+            .iter()
+            .filter_map(|(key, value)| value.as_ref().map(|value| (key.as_str(), value)));
+        let keyfile = Keyfile::from_entries("/", writes);
+        if !keyfile.is_empty() {
+            gsettings::load_dconf("/", &keyfile.to_string())?;
+        }
+
+        for (key, _) in changes.iter().filter(|(_, value)| value.is_none()) {
+            gsettings::reset_dconf(key)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// **In-memory backend for tests**, which can be told to reject some keys
+///
+/// Like dconf, a changeset holding a rejected key fails as a whole and leaves every value as it was.
+#[cfg(test)]
+#[derive(Debug, Default)]
+pub struct MemoryBackend {
+    pub values: std::cell::RefCell<BTreeMap<String, Variant>>,
+    /// Keys that can't be written or reset
+    pub rejected: std::collections::BTreeSet<String>,
+}
+
+#[cfg(test)]
+impl MemoryBackend {
+    /// A backend holding `values`
+    pub fn with<'a>(values: impl IntoIterator<Item = (&'a str, Variant)>) -> Self {
+        let values = values
+            .into_iter()
+            .map(|(key, value)| (key.to_string(), value))
+            .collect();
+
+        MemoryBackend { values: std::cell::RefCell::new(values), rejected: Default::default() }
+    }
+
+    /// The same backend, refusing to write or reset `key`
+    pub fn rejecting(mut self, key: &str) -> Self {
+        self.rejected.insert(key.to_string());
+        self
+    }
+
+    fn check(&self, key: &str) -> Result<()> {
+        match self.rejected.contains(key) {
+            true => Err(Error::Tool { tool: "dconf", message: format!("rejected {}", key) }),
+            false => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+impl SettingsBackend for MemoryBackend {
+    fn name(&self) -> &'static str {
+        "memory"
+    }
+
+    fn read(&self, key: &str) -> Result<Option<Variant>> {
+        Ok(self.values.borrow().get(key).cloned())
+    }
+
+    fn write(&self, key: &str, value: &Variant) -> Result<()> {
+        self.check(key)?;
+        self.values.borrow_mut().insert(key.to_string(), value.clone());
+        Ok(())
+    }
+
+    fn reset(&self, key: &str) -> Result<()> {
+        self.check(key)?;
+        self.values.borrow_mut().retain(|entry, _| entry != key && !(key.ends_with('/') && entry.starts_with(key)));
+        Ok(())
+    }
+
+    fn dump(&self, dir: &str) -> Result<String> {
+        let values = self.values.borrow();
+        let below = values.iter().filter(|(key, _)| key.starts_with(dir));
+
+        Ok(Keyfile::from_entries(dir, below.map(|(key, value)| (key.as_str(), value))).to_string())
+    }
+
+    fn apply(&self, changes: &BTreeMap<String, Option<Variant>>) -> Result<()> {
+        changes.keys().try_for_each(|key| self.check(key))?;
+        for (key, value) in changes {
+            match value {
+                Some(value) => self.write(key, value)?,
+                None => self.reset(key)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// **Settings the tests of every module share**
+#[cfg(test)]
+pub mod fixtures {
+    use super::MemoryBackend;
+    use crate::utils::gvariant::Variant;
+
+    pub const THEME: &str = "/org/gnome/desktop/interface/gtk-theme";
+    pub const ICONS: &str = "/org/gnome/desktop/interface/icon-theme";
+    pub const WALLPAPER: &str = "/org/gnome/desktop/background/picture-uri";
+
+    pub fn string(text: &str) -> Variant {
+        Variant::String(text.into())
+    }
+
+    /// A stock desktop: the `Yaru` theme and icons over the default wallpaper
+    pub fn desktop() -> MemoryBackend {
+        MemoryBackend::with([(THEME, string("Yaru")), (ICONS, string("Yaru")), (WALLPAPER, string("blobs.svg"))])
+    }
+}
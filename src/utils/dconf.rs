@@ -0,0 +1,775 @@
+//! # Native DCONF Backend Module
+//! This module provides the **`DconfBackend`** implementation of the `SettingsBackend` trait, which talks to
+//! dconf without spawning any processes:
+//!
+//! * Writes are sent to the `ca.desrt.dconf.Writer` service on the session bus as a single changeset
+//!   per operation, so applying a whole changeset is one D-Bus call.
+//! * Reads go straight to the database files listed in the dconf profile: the user database
+//!   (`$XDG_CONFIG_HOME/dconf/user`) followed by any system databases (`/etc/dconf/db/<name>`), so values
+//!   set or locked by the administrator are read the same way `dconf read` reads them. The files are
+//!   stored in the GVDB format, parsed by the small reader in this module once, and read again only
+//!   after one of them changes.
+//! * Dumps only read the user database, like `dconf dump`, so a snapshot never captures the system
+//!   defaults as if the user had set them.
+//!
+//! The bus (`DBUS_SESSION_BUS_ADDRESS`), the profile (`DCONF_PROFILE`) and the user database location
+//! (`XDG_CONFIG_HOME`) come from the environment, so the backend can be pointed at a private `dbus-daemon`
+//! running a stand-in writer service.
+use crate::utils::backend::SettingsBackend;
+use crate::utils::error::{ Error, Result };
+use crate::utils::gvariant::{ self, Variant };
+use crate::utils::keyfile::Keyfile;
+use std::cell::RefCell;
+use std::collections::{ BTreeMap, BTreeSet };
+use std::env;
+use std::fs;
+use std::path::{ Path, PathBuf };
+use std::rc::Rc;
+use std::time::SystemTime;
+use zbus::blocking::Connection;
+
+/// Well-known bus name of the dconf writer service
+const WRITER_SERVICE: &str = "ca.desrt.dconf";
+
+/// Object path of the writers, followed by the name of the user database they write to
+const WRITER_PATH: &str = "/ca/desrt/dconf/Writer/";
+
+/// Interface implemented by the writer service
+const WRITER_INTERFACE: &str = "ca.desrt.dconf.Writer";
+
+/// Directory of the compiled system databases
+const SYSTEM_DATABASES: &str = "/etc/dconf/db";
+
+/// **The databases dconf reads settings from, in order of precedence**
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Profile {
+    /// Name of the writable user database, if the profile starts with one
+    user: Option<String>,
+    /// Every database file, the user database first
+    databases: Vec<PathBuf>,
+}
+
+/// **Settings read from one database file**
+#[derive(Debug, Default, PartialEq)]
+struct Table {
+    values: BTreeMap<String, Variant>,
+    /// Keys the database locks, which hides their values in the databases before it
+    locks: BTreeSet<String>,
+}
+
+/// Full name, type and value bounds of an item in a GVDB hash table
+type Item = (String, u8, usize, usize);
+
+/// Merged settings of the profile, along with the modification times of the files they were read from
+type Cache = (Vec<Option<SystemTime>>, Rc<BTreeMap<String, Variant>>);
+
+/// **Backend that writes over D-Bus and reads the dconf database files directly**
+pub struct DconfBackend {
+    connection: Connection,
+    profile: Profile,
+    cache: RefCell<Option<Cache>>,
+}
+
+impl DconfBackend {
+    /// **Connects to the session bus and reads the dconf profile**
+    ///
+    /// ## Returns:
+    ///
+    /// The backend, or an `Error::Bus` if the session bus is unavailable.
+    pub fn new() -> Result<Self> {
+        let connection = Connection::session().map_err(|why|
+            Error::Bus(format!("Failed to connect to the session bus: {}", why))
+        )?;
+        let config = dirs
+            ::config_dir()
+            .ok_or(Error::NotFound("Unable to locate the user config directory".into()))?
+            .join("dconf");
+        let profile = match locate_profile() {
+            Some(path) => {
+                let contents = fs::read_to_string(&path).map_err(|why| Error::io("read", &path, why))?;
+                Profile::parse(&contents, &config)
+            }
+            None => Profile::parse("user-db:user", &config),
+        };
+
+        Ok(DconfBackend { connection, profile, cache: RefCell::new(None) })
+    }
+
+    /// **Reads every key set in the profile's databases**
+    ///
+    /// The databases are only parsed again if one of them changed since the last call, so the keys of an
+    /// operation are all read from a single parse.
+    fn entries(&self) -> Result<Rc<BTreeMap<String, Variant>>> {
+        let stamps: Vec<Option<SystemTime>> = self.profile.databases
+            .iter()
+            .map(|path| fs::metadata(path).and_then(|metadata| metadata.modified()).ok())
+            .collect();
+
+        if let Some((cached, entries)) = self.cache.borrow().as_ref() {
+            if *cached == stamps {
+                return Ok(Rc::clone(entries));
+            }
+        }
+
+        let tables = self.profile.databases
+            .iter()
+            .map(|path| read_file(path))
+            .collect::<Result<Vec<Table>>>()?;
+        let entries = Rc::new(merge(tables));
+        *self.cache.borrow_mut() = Some((stamps, Rc::clone(&entries)));
+
+        Ok(entries)
+    }
+
+    /// **Reads every key set in the user database**, leaving out the system databases
+    fn user_entries(&self) -> Result<BTreeMap<String, Variant>> {
+        match (&self.profile.user, self.profile.databases.first()) {
+            (Some(_), Some(path)) => Ok(read_file(path)?.values),
+            _ => Ok(BTreeMap::new()),
+        }
+    }
+
+    /// **Sends a changeset to the writer service**
+    ///
+    /// Each entry maps a key (or a directory ending in `/`) to its new value, with `None` resetting it.
+    /// The changeset is serialized as an `a{smv}` GVariant and applied by dconf in a single transaction.
+    fn change(&self, changes: BTreeMap<String, Option<Variant>>) -> Result<String> {
+        let user = self.profile.user
+            .as_deref()
+            .ok_or(Error::Bus("The dconf profile has no writable user database".into()))?;
+        let entries = changes
+            .into_iter()
+            .map(|(key, value)| {
+                Variant::DictEntry(
+                    Box::new(Variant::String(key)),
+                    Box::new(
+                        Variant::Maybe(
+                            "v".into(),
+                            value.map(|value| Box::new(Variant::Variant(Box::new(value))))
+                        )
+                    )
+                )
+            })
+            .collect();
+        let blob = gvariant::encode(&Variant::Array("{smv}".into(), entries));
+
+        // Whatever the outcome, the database may have changed
+        self.cache.replace(None);
+        let reply = self.connection
+            .call_method(
+                Some(WRITER_SERVICE),
+                format!("{}{}", WRITER_PATH, user).as_str(),
+                Some(WRITER_INTERFACE),
+                "Change",
+                &(blob.as_slice(),)
+            )
+            .map_err(|why| Error::Bus(format!("dconf writer call failed: {}", why)))?;
+
+        reply
+            .body()
+            .deserialize::<String>()
+            .map_err(|why| Error::Bus(format!("Unexpected reply from dconf writer: {}", why)))
+    }
+}
+
+impl SettingsBackend for DconfBackend {
+    fn name(&self) -> &'static str {
+        "dconf (D-Bus)"
+    }
+
+    fn read(&self, key: &str) -> Result<Option<Variant>> {
+        Ok(self.entries()?.get(key).cloned())
+    }
+
+    fn write(&self, key: &str, value: &Variant) -> Result<()> {
+        self.change(BTreeMap::from([(key.to_string(), Some(value.clone()))]))?;
+        Ok(())
+    }
+
+    fn reset(&self, key: &str) -> Result<()> {
+        self.change(BTreeMap::from([(key.to_string(), None)]))?;
+        Ok(())
+    }
+
+    fn dump(&self, dir: &str) -> Result<String> {
+        let entries = self.user_entries()?;
+        let below = entries.iter().filter(|(key, _)| key.starts_with(dir));
+
+        Ok(Keyfile::from_entries(dir, below.map(|(key, value)| (key.as_str(), value))).to_string())
+    }
+
+    fn apply(&self, changes: &BTreeMap<String, Option<Variant>>) -> Result<()> {
+        self.change(changes.clone())?;
+        Ok(())
+    }
+}
+
+impl Profile {
+    /// **Parses a dconf profile**, one `user-db:`, `system-db:` or `file-db:` database per line
+    ///
+    /// User databases are looked up in `config` (the `dconf` directory of the user config directory) and
+    /// system databases in `/etc/dconf/db`. Service databases can't be read as files, so they're skipped.
+    fn parse(contents: &str, config: &Path) -> Self {
+        let mut profile = Profile { user: None, databases: Vec::new() };
+
+        for line in contents.lines() {
+            let line = line.split('#').next().unwrap_or_default().trim();
+            let path = match line.split_once(':') {
+                Some(("user-db", name)) => {
+                    if profile.databases.is_empty() {
+                        profile.user = Some(name.to_string());
+                    }
+                    config.join(name)
+                }
+                Some(("system-db", name)) => Path::new(SYSTEM_DATABASES).join(name),
+                Some(("file-db", path)) => PathBuf::from(path),
+                _ => {
+                    continue;
+                }
+            };
+            profile.databases.push(path);
+        }
+
+        profile
+    }
+}
+
+/// **Finds the dconf profile file**
+///
+/// The profile is named by `DCONF_PROFILE` (`user` if unset), or given as an absolute path. Names are
+/// looked up in `/etc/dconf/profile`, then in the `dconf/profile` directory of every `XDG_DATA_DIRS` entry.
+///
+/// ## Returns:
+///
+/// The path of the profile, or `None` if there is none, in which case dconf only uses the `user` database.
+fn locate_profile() -> Option<PathBuf> {
+    let name = env::var("DCONF_PROFILE").unwrap_or_else(|_| "user".into());
+    if name.starts_with('/') {
+        return Some(PathBuf::from(name));
+    }
+
+    let data_dirs = env::var("XDG_DATA_DIRS").unwrap_or_else(|_| "/usr/local/share:/usr/share".into());
+    std::iter
+        ::once(PathBuf::from("/etc/dconf/profile"))
+        .chain(
+            data_dirs
+                .split(':')
+                .filter(|dir| !dir.is_empty())
+                .map(|dir| Path::new(dir).join("dconf").join("profile"))
+        )
+        .map(|dir| dir.join(&name))
+        .find(|path| path.is_file())
+}
+
+/// Reads one database file of the profile, which dconf treats as empty if it doesn't exist
+fn read_file(path: &Path) -> Result<Table> {
+    match fs::read(path) {
+        Ok(data) => read_database(&data).map_err(|why| Error::corrupt(path, why)),
+        // dconf only creates the user database on the first write
+        Err(why) if why.kind() == std::io::ErrorKind::NotFound => Ok(Table::default()),
+        Err(why) => Err(Error::io("read", path, why)),
+    }
+}
+
+/// **Merges the databases of a profile**, as `dconf read` does
+///
+/// A key takes its value from the first database that sets it, unless a later database locks it: then
+/// only that database and the ones after it are considered.
+fn merge(tables: Vec<Table>) -> BTreeMap<String, Variant> {
+    let keys: BTreeSet<&String> = tables
+        .iter()
+        .flat_map(|table| table.values.keys())
+        .collect();
+    let mut entries = BTreeMap::new();
+
+    for key in keys {
+        let locked = tables
+            .iter()
+            .rposition(|table| table.locks.contains(key))
+            .unwrap_or(0);
+
+        if let Some(value) = tables[locked..].iter().find_map(|table| table.values.get(key)) {
+            entries.insert(key.clone(), value.clone());
+        }
+    }
+
+    entries
+}
+
+/// **Reads every value and lock from a dconf database in the GVDB format**
+///
+/// A GVDB file starts with a 24 byte header (`GVariant` signature, version, options and a pointer to the
+/// root hash table). Values are stored as serialized GVariant `v` values in items of type `v`. Locks are
+/// the names of a nested hash table stored in the `.locks` item, of type `H`.
+fn read_database(data: &[u8]) -> std::result::Result<Table, String> {
+    match data.get(0..8) {
+        Some(b"GVariant") => (),
+        Some(b"raVGtnai") => {
+            return Err("Big-endian dconf databases are not supported".into());
+        }
+        _ => {
+            return Err(corrupt());
+        }
+    }
+
+    let mut table = Table::default();
+    for (name, kind, start, end) in read_table(data, u32_at(data, 16)?, u32_at(data, 20)?)? {
+        match (kind, name.as_str()) {
+            (b'v', _) => {
+                let value = data.get(start..end).ok_or_else(corrupt)?;
+                match gvariant::decode("v", value)? {
+                    Variant::Variant(value) => {
+                        table.values.insert(name, *value);
+                    }
+                    _ => {
+                        return Err(corrupt());
+                    }
+                }
+            }
+            (b'H', ".locks") => {
+                let locks = read_table(data, start, end)?;
+                table.locks.extend(locks.into_iter().filter(|(_, kind, ..)| *kind == b'v').map(|(name, ..)| name));
+            }
+            _ => (),
+        }
+    }
+
+    Ok(table)
+}
+
+/// **Reads the items of a GVDB hash table** stored between `start` and `end`
+///
+/// A table starts with its bloom filter and hash buckets, followed by one 24 byte item per name. Each
+/// item points at its parent, so full names are built by joining the name segments along that chain.
+///
+/// ## Returns:
+///
+/// The full name, type and value bounds of every item.
+fn read_table(data: &[u8], start: usize, end: usize) -> std::result::Result<Vec<Item>, String> {
+    let bloom_words = u32_at(data, start)? & ((1 << 27) - 1);
+    let buckets = u32_at(data, start + 4)?;
+    let items_start = start + 8 + 4 * (bloom_words + buckets);
+    if end > data.len() || items_start > end {
+        return Err(corrupt());
+    }
+
+    let item_count = (end - items_start) / 24;
+    let mut items = Vec::with_capacity(item_count);
+    let mut parents = Vec::with_capacity(item_count);
+
+    for i in 0..item_count {
+        let item = items_start + 24 * i;
+        let key_start = u32_at(data, item + 8)?;
+        let key_size = u32_at(data, item + 12)? & 0xffff;
+        let name = data.get(key_start..key_start + key_size).ok_or_else(corrupt)?;
+
+        parents.push(u32_at(data, item + 4)?);
+        items.push((
+            String::from_utf8(name.to_vec()).map_err(|_| corrupt())?,
+            data[item + 14],
+            u32_at(data, item + 16)?,
+            u32_at(data, item + 20)?,
+        ));
+    }
+
+    let names: Vec<String> = items
+        .iter()
+        .map(|(name, ..)| name.clone())
+        .collect();
+    for (i, item) in items.iter_mut().enumerate() {
+        let mut parent = parents[i];
+        // Guard against parent cycles in a damaged file
+        for _ in 0..item_count {
+            if parent == 0xffff_ffff {
+                break;
+            }
+            item.0.insert_str(0, names.get(parent).ok_or_else(corrupt)?);
+            parent = parents[parent];
+        }
+    }
+
+    Ok(items)
+}
+
+fn u32_at(data: &[u8], offset: usize) -> std::result::Result<usize, String> {
+    let bytes = data.get(offset..offset + 4).ok_or_else(corrupt)?;
+    Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as usize)
+}
+
+fn corrupt() -> String {
+    "The dconf database is corrupt".to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::backend::fixtures::{ string, THEME };
+    use std::io::{ BufRead, BufReader };
+    use std::path::Path;
+    use std::process::{ Child, Command, Stdio };
+    use zbus::blocking::connection::Builder;
+
+    const SCALING: &str = "/org/gnome/desktop/interface/text-scaling-factor";
+    const FAVORITES: &str = "/org/gnome/shell/favorite-apps";
+
+    /// A private `dbus-daemon`, killed when dropped
+    struct Bus {
+        daemon: Child,
+        address: String,
+    }
+
+    impl Bus {
+        /// Starts a bus listening in `dir`, or returns `None` if `dbus-daemon` isn't installed
+        fn start(dir: &Path) -> Option<Bus> {
+            let config = dir.join("bus.conf");
+            fs::write(
+                &config,
+                format!(
+                    r#"<busconfig>
+  <type>session</type>
+  <listen>unix:path={}</listen>
+  <auth>EXTERNAL</auth>
+  <policy context="default">
+    <allow send_destination="*" eavesdrop="true"/>
+    <allow eavesdrop="true"/>
+    <allow own="*"/>
+  </policy>
+</busconfig>"#,
+                    dir.join("bus").display()
+                )
+            ).unwrap();
+
+            let mut daemon = Command::new("dbus-daemon")
+                .arg(format!("--config-file={}", config.display()))
+                .args(["--nofork", "--print-address"])
+                .stdout(Stdio::piped())
+                .stderr(Stdio::null())
+                .spawn()
+                .ok()?;
+            let mut address = String::new();
+            BufReader::new(daemon.stdout.take().unwrap()).read_line(&mut address).unwrap();
+
+            Some(Bus { daemon, address: address.trim().to_string() })
+        }
+
+        fn connect(&self) -> Connection {
+            Builder::address(self.address.as_str()).unwrap().build().unwrap()
+        }
+    }
+
+    impl Drop for Bus {
+        fn drop(&mut self) {
+            let _ = self.daemon.kill();
+            let _ = self.daemon.wait();
+        }
+    }
+
+    /// Stand-in for the dconf writer service, applying changesets to a database file the way dconf does
+    struct Writer {
+        database: PathBuf,
+        entries: BTreeMap<String, Variant>,
+    }
+
+    #[zbus::interface(name = "ca.desrt.dconf.Writer")]
+    impl Writer {
+        fn change(&mut self, blob: Vec<u8>) -> zbus::fdo::Result<String> {
+            let invalid = |why: String| zbus::fdo::Error::InvalidArgs(why);
+            let Variant::Array(_, changes) = gvariant::decode("a{smv}", &blob).map_err(invalid)? else {
+                return Err(invalid("not a changeset".into()));
+            };
+
+            // Like dconf, reject the whole changeset if any of its paths is invalid
+            let mut entries = self.entries.clone();
+            for change in changes {
+                let Variant::DictEntry(key, value) = change else {
+                    return Err(invalid("not a changeset".into()));
+                };
+                let (Variant::String(key), Variant::Maybe(_, value)) = (*key, *value) else {
+                    return Err(invalid("not a changeset".into()));
+                };
+                if !key.starts_with('/') || key.contains("//") {
+                    return Err(invalid(format!("{} is not a valid path", key)));
+                }
+
+                match value.map(|value| *value) {
+                    Some(Variant::Variant(value)) => {
+                        entries.insert(key, *value);
+                    }
+                    Some(_) => {
+                        return Err(invalid("not a changeset".into()));
+                    }
+                    None if key.ends_with('/') => entries.retain(|entry, _| !entry.starts_with(&key)),
+                    None => {
+                        entries.remove(&key);
+                    }
+                }
+            }
+
+            let table = Table { values: entries.clone(), locks: BTreeSet::new() };
+            fs::write(&self.database, write_database(&table)).map_err(|why|
+                zbus::fdo::Error::Failed(why.to_string())
+            )?;
+            self.entries = entries;
+
+            Ok("tag".into())
+        }
+    }
+
+    /// Writes `table` in the GVDB format
+    fn write_database(table: &Table) -> Vec<u8> {
+        let mut data = vec![0; 24];
+        data[0..8].copy_from_slice(b"GVariant");
+
+        let (start, end) = write_table(&mut data, &table.values, &table.locks);
+        data[16..20].copy_from_slice(&start.to_le_bytes());
+        data[20..24].copy_from_slice(&end.to_le_bytes());
+
+        data
+    }
+
+    /// Appends a hash table with an item for every directory and value and no hash buckets, along with a
+    /// nested `.locks` table if there are `locks`, returning the bounds of the table
+    fn write_table(
+        data: &mut Vec<u8>,
+        values: &BTreeMap<String, Variant>,
+        locks: &BTreeSet<String>
+    ) -> (u32, u32) {
+        let mut items: Vec<(String, u32, Option<&Variant>)> = Vec::new();
+        let mut directories: BTreeMap<&str, u32> = BTreeMap::new();
+
+        for (key, value) in values {
+            let mut parent = 0xffff_ffff;
+            let mut start = 0;
+            for (end, _) in key.match_indices('/') {
+                parent = match directories.get(&key[..=end]) {
+                    Some(&item) => item,
+                    None => {
+                        items.push((key[start..=end].to_string(), parent, None));
+                        directories.insert(&key[..=end], (items.len() - 1) as u32);
+                        (items.len() - 1) as u32
+                    }
+                };
+                start = end + 1;
+            }
+            items.push((key[start..].to_string(), parent, Some(value)));
+        }
+        if !locks.is_empty() {
+            items.push((".locks".into(), 0xffff_ffff, None));
+        }
+
+        data.resize(data.len().next_multiple_of(4), 0);
+        let table_start = data.len();
+        let items_start = table_start + 8;
+        data.resize(items_start + 24 * items.len(), 0);
+        let table_end = data.len();
+
+        for (i, (name, parent, value)) in items.iter().enumerate() {
+            let item = items_start + 24 * i;
+            let key_start = data.len() as u32;
+            data.extend_from_slice(name.as_bytes());
+
+            let (kind, value_start, value_end) = match value {
+                Some(value) => {
+                    data.resize(data.len().next_multiple_of(8), 0);
+                    let start = data.len() as u32;
+                    data.extend(gvariant::encode(&Variant::Variant(Box::new((*value).clone()))));
+                    (b'v', start, data.len() as u32)
+                }
+                None if name == ".locks" => {
+                    let locked = locks
+                        .iter()
+                        .map(|key| (key.clone(), Variant::String(String::new())))
+                        .collect();
+                    let (start, end) = write_table(data, &locked, &BTreeSet::new());
+                    (b'H', start, end)
+                }
+                None => (b'L', 0, 0),
+            };
+
+            data[item + 4..item + 8].copy_from_slice(&parent.to_le_bytes());
+            data[item + 8..item + 12].copy_from_slice(&key_start.to_le_bytes());
+            data[item + 12..item + 14].copy_from_slice(&(name.len() as u16).to_le_bytes());
+            data[item + 14] = kind;
+            data[item + 16..item + 20].copy_from_slice(&value_start.to_le_bytes());
+            data[item + 20..item + 24].copy_from_slice(&value_end.to_le_bytes());
+        }
+
+        (table_start as u32, table_end as u32)
+    }
+
+    /// Starts a private bus running the stand-in writer, and a backend connected to it
+    fn private_session(dir: &Path, system: &[PathBuf]) -> Option<(Bus, Connection, DconfBackend)> {
+        let bus = Bus::start(dir)?;
+        let database = dir.join("user");
+        let writer = Builder::address(bus.address.as_str())
+            .unwrap()
+            .name(WRITER_SERVICE)
+            .unwrap()
+            .serve_at(format!("{}user", WRITER_PATH), Writer { database: database.clone(), entries: BTreeMap::new() })
+            .unwrap()
+            .build()
+            .unwrap();
+        let databases = std::iter::once(database).chain(system.iter().cloned()).collect();
+        let profile = Profile { user: Some("user".into()), databases };
+        let backend = DconfBackend { connection: bus.connect(), profile, cache: RefCell::new(None) };
+
+        Some((bus, writer, backend))
+    }
+
+    fn favorites() -> Variant {
+        Variant::Array(
+            "s".into(),
+            vec![string("firefox.desktop"), string("org.gnome.Nautilus.desktop")]
+        )
+    }
+
+    #[test]
+    fn reads_back_every_value_type_from_the_database() {
+        let entries = BTreeMap::from([
+            (THEME.to_string(), string("Windows-10")),
+            (SCALING.to_string(), Variant::Double(1.25)),
+            ("/org/gnome/desktop/wm/preferences/num-workspaces".to_string(), Variant::Int32(1)),
+            ("/org/gnome/mutter/dynamic-workspaces".to_string(), Variant::Bool(false)),
+            (FAVORITES.to_string(), favorites()),
+        ]);
+
+        let table = Table { values: entries, locks: BTreeSet::from([THEME.to_string()]) };
+
+        assert_eq!(read_database(&write_database(&table)), Ok(table));
+        assert!(read_database(b"GVariant").is_err());
+        assert!(read_database(b"not a database at all").is_err());
+    }
+
+    #[test]
+    fn applies_changesets_through_the_writer_service() {
+        let dir = tempfile::tempdir().unwrap();
+        let Some((_bus, _writer, backend)) = private_session(dir.path(), &[]) else {
+            eprintln!("dbus-daemon is not installed, skipping");
+            return;
+        };
+
+        assert_eq!(backend.read(THEME).unwrap(), None);
+
+        let changes = BTreeMap::from([
+            (THEME.to_string(), Some(string("Windows-10"))),
+            (SCALING.to_string(), Some(Variant::Double(1.25))),
+            (FAVORITES.to_string(), Some(favorites())),
+        ]);
+        backend.apply(&changes).unwrap();
+        assert_eq!(backend.read(THEME).unwrap(), Some(string("Windows-10")));
+        assert_eq!(backend.read(SCALING).unwrap(), Some(Variant::Double(1.25)));
+        assert_eq!(backend.read(FAVORITES).unwrap(), Some(favorites()));
+
+        let dump = backend.dump("/org/gnome/desktop/").unwrap();
+        assert_eq!(
+            Keyfile::parse("/org/gnome/desktop/", &dump).unwrap().values().unwrap(),
+            BTreeMap::from([
+                (THEME.to_string(), string("Windows-10")),
+                (SCALING.to_string(), Variant::Double(1.25)),
+            ])
+        );
+
+        backend.reset(THEME).unwrap();
+        backend.reset("/org/gnome/shell/").unwrap();
+        assert_eq!(backend.read(THEME).unwrap(), None);
+        assert_eq!(backend.read(FAVORITES).unwrap(), None);
+        assert_eq!(backend.read(SCALING).unwrap(), Some(Variant::Double(1.25)));
+    }
+
+    #[test]
+    fn rejected_changesets_change_nothing() {
+        let dir = tempfile::tempdir().unwrap();
+        let Some((_bus, _writer, backend)) = private_session(dir.path(), &[]) else {
+            eprintln!("dbus-daemon is not installed, skipping");
+            return;
+        };
+
+        let changes = BTreeMap::from([
+            (THEME.to_string(), Some(string("Windows-10"))),
+            ("org/gnome/relative".to_string(), Some(Variant::Bool(true))),
+        ]);
+
+        assert!(matches!(backend.apply(&changes), Err(Error::Bus(_))));
+        assert_eq!(backend.read(THEME).unwrap(), None);
+    }
+
+    #[test]
+    fn reads_through_the_profile_database_stack() {
+        let dir = tempfile::tempdir().unwrap();
+        let system = dir.path().join("local");
+        let defaults = Table {
+            values: BTreeMap::from([
+                (THEME.to_string(), string("Corporate")),
+                (SCALING.to_string(), Variant::Double(1.5)),
+                (FAVORITES.to_string(), favorites()),
+            ]),
+            locks: BTreeSet::from([THEME.to_string()]),
+        };
+        fs::write(&system, write_database(&defaults)).unwrap();
+        let Some((_bus, _writer, backend)) = private_session(dir.path(), &[system]) else {
+            eprintln!("dbus-daemon is not installed, skipping");
+            return;
+        };
+
+        let changes = BTreeMap::from([
+            (THEME.to_string(), Some(string("Windows-10"))),
+            (SCALING.to_string(), Some(Variant::Double(1.25))),
+        ]);
+        backend.apply(&changes).unwrap();
+
+        // The locked theme keeps the administrator's value, the others fall through to the user's
+        assert_eq!(backend.read(THEME).unwrap(), Some(string("Corporate")));
+        assert_eq!(backend.read(SCALING).unwrap(), Some(Variant::Double(1.25)));
+        assert_eq!(backend.read(FAVORITES).unwrap(), Some(favorites()));
+
+        // Reads between writes share a single parse of the databases
+        assert!(Rc::ptr_eq(&backend.entries().unwrap(), &backend.entries().unwrap()));
+        let before = backend.entries().unwrap();
+        backend.reset(SCALING).unwrap();
+        assert!(!Rc::ptr_eq(&before, &backend.entries().unwrap()));
+        assert_eq!(backend.read(SCALING).unwrap(), Some(Variant::Double(1.5)));
+    }
+
+    #[test]
+    fn dumps_only_the_user_database() {
+        let dir = tempfile::tempdir().unwrap();
+        let system = dir.path().join("local");
+        let defaults = Table {
+            values: BTreeMap::from([(FAVORITES.to_string(), favorites())]),
+            locks: BTreeSet::new(),
+        };
+        fs::write(&system, write_database(&defaults)).unwrap();
+        let Some((_bus, _writer, backend)) = private_session(dir.path(), &[system]) else {
+            eprintln!("dbus-daemon is not installed, skipping");
+            return;
+        };
+
+        backend.write(THEME, &string("Windows-10")).unwrap();
+
+        // The system default is read, but it isn't the user's to snapshot
+        assert_eq!(backend.read(FAVORITES).unwrap(), Some(favorites()));
+        assert_eq!(
+            Keyfile::parse("/", &backend.dump("/").unwrap()).unwrap().values().unwrap(),
+            BTreeMap::from([(THEME.to_string(), string("Windows-10"))])
+        );
+    }
+
+    #[test]
+    fn parses_profiles() {
+        let config = Path::new("/home/user/.config/dconf");
+        let contents = concat!(
+            "# Managed by the administrator\n",
+            "user-db:user\n",
+            "system-db:local # site defaults\n",
+            "service-db:remote\n",
+            "file-db:/opt/site.db\n"
+        );
+        let profile = Profile::parse(contents, config);
+
+        assert_eq!(profile, Profile {
+            user: Some("user".into()),
+            databases: vec![config.join("user"), PathBuf::from("/etc/dconf/db/local"), PathBuf::from("/opt/site.db")],
+        });
+        assert_eq!(Profile::parse("system-db:local\nuser-db:user\n", config).user, None);
+    }
+}
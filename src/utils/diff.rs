@@ -0,0 +1,115 @@
+//! # Diff Module
+//! This module previews what enabling or restoring incognito would change, without writing anything.
+//!
+//! A planned changeset (full dconf path mapped to the new value, `None` for a reset) is compared against
+//! the values currently stored in the backend, and the result is printed either as a human readable
+//! per-key `before → after` list or as JSON for scripts. Values are compared as typed `Variant`s, so a key
+//! whose value is only spelled differently (e.g. `"on"` instead of `'on'`) counts as up to date.
+use crate::utils::backend::SettingsBackend;
+use crate::utils::error::{ Error, Result };
+use crate::utils::gvariant::Variant;
+use colored::Colorize;
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+/// **Output format of a dry run**
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DiffFormat {
+    /// Colored per-key `before → after` list
+    Human,
+    /// A single JSON document on stdout
+    Json,
+}
+
+/// **A key whose value would change**
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Change {
+    /// Full dconf path of the key
+    pub key: String,
+    /// Current value, written in GVariant text format, `null` if the key is unset
+    pub before: Option<Variant>,
+    /// Value after the operation, `null` if the key would be reset
+    pub after: Option<Variant>,
+}
+
+/// **Result of comparing a changeset against the backend**
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Diff {
+    /// Keys whose value would change
+    pub changes: Vec<Change>,
+    /// Keys that already hold their target value
+    pub unchanged: Vec<String>,
+}
+
+/// **Compares `changes` against the values currently stored in `backend`**
+///
+/// ## Returns:
+///
+/// The diff, or an `Error` if a key can't be read.
+pub fn compare(
+    backend: &dyn SettingsBackend,
+    changes: &BTreeMap<String, Option<Variant>>
+) -> Result<Diff> {
+    let mut diff = Diff { changes: Vec::new(), unchanged: Vec::new() };
+
+    for (key, after) in changes {
+        let before = backend.read(key)?;
+        match &before == after {
+            true => diff.unchanged.push(key.clone()),
+            false =>
+                diff.changes.push(Change {
+                    key: key.clone(),
+                    before,
+                    after: after.clone(),
+                }),
+        }
+    }
+
+    Ok(diff)
+}
+
+/// **Prints `diff` in the requested format**
+///
+/// ## Args:
+///
+/// * `title` - What the diff previews (e.g. `Enable incognito`), only shown in the human format
+/// * `diff` - The diff to print
+/// * `format` - Human readable or JSON output
+pub fn print(title: &str, diff: &Diff, format: DiffFormat) -> Result<()> {
+    match format {
+        DiffFormat::Json => {
+            let json = serde_json
+                ::to_string_pretty(diff)
+                .map_err(|why| Error::Other(format!("Couldn't serialize the diff: {}", why)))?;
+            println!("{}", json);
+        }
+        DiffFormat::Human => {
+            let text = |value: &Option<Variant>, missing: &str| {
+                value.as_ref().map_or_else(|| missing.to_string(), Variant::to_string)
+            };
+
+            println!("🔍 {} {}", title.magenta().bold(), "(dry run, nothing was written)".dimmed());
+            println!();
+            for change in &diff.changes {
+                println!("   {} {}", "~".yellow().bold(), change.key.bold());
+                println!(
+                    "       {} {} {}",
+                    text(&change.before, "(unset)").red(),
+                    "→".dimmed(),
+                    text(&change.after, "(reset)").green()
+                );
+            }
+            if !diff.changes.is_empty() {
+                println!();
+            }
+            println!(
+                "   {} {} to change, {} already up to date",
+                "📋".bold(),
+                diff.changes.len().to_string().bold(),
+                diff.unchanged.len()
+            );
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,270 @@
+//! # Doctor Module
+//! This module checks whether the current session can run incognito mode, and explains what to fix when
+//! it can't: the settings backends, the dconf database, the saved snapshot and state, and the assets the
+//! incognito look depends on.
+//!
+//! Every check produces a **`Check`** with a status and a short explanation, and `run` reports them all
+//! so a single broken check never hides the others.
+use crate::utils::dconf::DconfBackend;
+use crate::utils::error::{ Error, Result };
+use crate::utils::profile::{ self, Profile };
+use crate::utils::reporter::{ Event, Reporter };
+use crate::utils::schema::{ Schemas, Severity };
+use crate::utils::snapshot::{ self, Snapshot, Store };
+use crate::utils::state::{ self, State };
+use serde::Serialize;
+use std::env;
+use std::fs;
+use std::path::{ Path, PathBuf };
+
+/// **Outcome of a single check**
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Status {
+    /// Everything is in order
+    Ok,
+    /// Incognito works, but something may not behave as expected
+    Warn,
+    /// Incognito can't work until this is fixed
+    Fail,
+}
+
+impl Status {
+    /// Name of the status, as printed by `doctor --output plain`
+    pub fn name(&self) -> &'static str {
+        match self {
+            Status::Ok => "ok",
+            Status::Warn => "warn",
+            Status::Fail => "fail",
+        }
+    }
+}
+
+/// **A named check and what it found**
+#[derive(Debug, Clone, Serialize)]
+pub struct Check {
+    pub name: &'static str,
+    pub status: Status,
+    pub detail: String,
+}
+
+impl Check {
+    fn new(name: &'static str, status: Status, detail: impl Into<String>) -> Self {
+        Check { name, status, detail: detail.into() }
+    }
+}
+
+/// **Runs every check and reports the results**
+///
+/// ## Args:
+///
+/// * `config` - Path of the config snapshot
+/// * `profile` - The profile incognito would apply
+/// * `reporter` - Where the results of the checks are reported
+///
+/// ## Returns:
+///
+/// `Ok` if no check failed, or an `Error::Other` counting the failed checks.
+pub fn run(config: &str, profile: &Profile, reporter: &mut dyn Reporter) -> Result<()> {
+    let mut checks = vec![
+        check_session_bus(),
+        check_dconf_command(),
+        check_database(),
+        check_snapshot(config),
+        check_state(config),
+        check_store(),
+        check_profile(profile),
+        check_wallpaper(&profile.assets.wallpaper),
+        check_asset("Theme", "themes", &profile.assets.theme, "--theme"),
+        check_asset("Icon theme", "icons", &profile.assets.icons, "--icons"),
+    ];
+    checks.extend(profile.extensions.iter().map(|uuid| check_extension(uuid)));
+    checks.extend(check_schemas(profile));
+
+    // Either backend is enough, so only a session without both of them can't apply anything
+    if checks[0].status == Status::Warn && checks[1].status == Status::Warn {
+        checks[1].status = Status::Fail;
+    }
+
+    reporter.emit(Event::Checks(&checks));
+
+    let failed = checks
+        .iter()
+        .filter(|check| check.status == Status::Fail)
+        .count();
+    match failed {
+        0 => Ok(()),
+        _ => Err(Error::Other(format!("{} doctor check(s) failed", failed))),
+    }
+}
+
+fn check_session_bus() -> Check {
+    match DconfBackend::new() {
+        Ok(_) => Check::new("Session bus", Status::Ok, "reachable, the dbus backend is available"),
+        Err(why) => Check::new("Session bus", Status::Warn, format!("{} (the command backend will be used)", why)),
+    }
+}
+
+fn check_dconf_command() -> Check {
+    match find_in_path("dconf") {
+        Some(path) => Check::new("dconf command", Status::Ok, path.display().to_string()),
+        None => Check::new("dconf command", Status::Warn, "not found in PATH (only the dbus backend will work)"),
+    }
+}
+
+fn check_database() -> Check {
+    let database = match dirs::config_dir() {
+        Some(dir) => dir.join("dconf").join("user"),
+        None => {
+            return Check::new("dconf database", Status::Fail, "unable to locate the user config directory");
+        }
+    };
+
+    match fs::metadata(&database) {
+        Ok(_) => Check::new("dconf database", Status::Ok, database.display().to_string()),
+        Err(_) =>
+            Check::new(
+                "dconf database",
+                Status::Warn,
+                format!("{} doesn't exist yet (created on the first write)", database.display())
+            ),
+    }
+}
+
+fn check_snapshot(config: &str) -> Check {
+    match snapshot::locate(config) {
+        Some(path) =>
+            match Snapshot::load(&path) {
+                Ok(snapshot) if snapshot.format == 0 =>
+                    Check::new(
+                        "Snapshot",
+                        Status::Ok,
+                        format!("{} (legacy format, {} keys)", path.display(), snapshot.entries.len())
+                    ),
+                Ok(snapshot) =>
+                    Check::new(
+                        "Snapshot",
+                        Status::Ok,
+                        format!("{} ({} keys)", snapshot.id, snapshot.entries.len())
+                    ),
+                Err(why) => Check::new("Snapshot", Status::Fail, why.to_string()),
+            }
+        None => Check::new("Snapshot", Status::Ok, "none yet, one is taken when incognito is enabled"),
+    }
+}
+
+fn check_state(config: &str) -> Check {
+    match State::load(&state::path_for(config)) {
+        Ok(Some(state)) if state.active =>
+            Check::new("State", Status::Ok, format!("incognito active since {}", state.timestamp)),
+        Ok(_) => Check::new("State", Status::Ok, "incognito not active"),
+        Err(why) => Check::new("State", Status::Fail, why.to_string()),
+    }
+}
+
+fn check_store() -> Check {
+    match Store::open().and_then(|store| store.list()) {
+        Ok((snapshots, skipped)) if skipped.is_empty() =>
+            Check::new("Snapshot store", Status::Ok, format!("{} saved", snapshots.len())),
+        Ok((snapshots, skipped)) =>
+            Check::new(
+                "Snapshot store",
+                Status::Warn,
+                format!("{} saved, {} unreadable: {}", snapshots.len(), skipped.len(), skipped[0])
+            ),
+        Err(why) => Check::new("Snapshot store", Status::Warn, why.to_string()),
+    }
+}
+
+fn check_profile(profile: &Profile) -> Check {
+    match profile.changes() {
+        Ok(changes) => Check::new("Profile", Status::Ok, format!("{} ({} keys)", profile.name, changes.len())),
+        Err(why) => Check::new("Profile", Status::Fail, why.to_string()),
+    }
+}
+
+/// Checks every key of the profile against the installed GSettings schemas, one check per issue
+fn check_schemas(profile: &Profile) -> Vec<Check> {
+    let schemas = Schemas::load();
+    if schemas.is_empty() {
+        return vec![Check::new("Schemas", Status::Warn, "no GSettings schemas found, values can't be checked")];
+    }
+
+    match schemas.validate(profile) {
+        Ok(issues) if issues.is_empty() =>
+            vec![Check::new("Schemas", Status::Ok, "every value matches the installed schemas")],
+        Ok(issues) =>
+            issues
+                .into_iter()
+                .map(|issue| {
+                    let status = match issue.severity {
+                        Severity::Error => Status::Fail,
+                        Severity::Warning => Status::Warn,
+                    };
+                    Check::new("Schemas", status, format!("{}: {}", issue.key, issue.message))
+                })
+                .collect(),
+        // The profile check already reports values that can't be built
+        Err(_) => Vec::new(),
+    }
+}
+
+/// Looks for an installed GNOME Shell extension in the user and system extension directories
+fn check_extension(uuid: &str) -> Check {
+    let installed = data_dirs()
+        .into_iter()
+        .map(|dir| dir.join("gnome-shell").join("extensions").join(uuid))
+        .any(|path| path.is_dir());
+
+    match installed {
+        true => Check::new("Extension", Status::Ok, uuid),
+        false => Check::new("Extension", Status::Warn, format!("{} is not installed", uuid)),
+    }
+}
+
+fn check_wallpaper(wallpaper: &str) -> Check {
+    if wallpaper.is_empty() {
+        return Check::new("Wallpaper", Status::Ok, "none, the profile uses a plain background");
+    }
+
+    match Path::new(wallpaper).is_file() {
+        true => Check::new("Wallpaper", Status::Ok, wallpaper),
+        false => Check::new("Wallpaper", Status::Warn, format!("{} not found", wallpaper)),
+    }
+}
+
+/// Looks for a theme or icon theme in the same places GNOME does
+fn check_asset(name: &'static str, kind: &str, asset: &str, option: &str) -> Check {
+    if asset.is_empty() {
+        return Check::new(name, Status::Ok, "not set by the profile");
+    }
+
+    match profile::asset_dirs(kind).iter().map(|dir| dir.join(asset)).find(|path| path.is_dir()) {
+        Some(path) => Check::new(name, Status::Ok, path.display().to_string()),
+        None =>
+            Check::new(
+                name,
+                Status::Warn,
+                format!("{} is not installed, install it or pick another one with {}", asset, option)
+            ),
+    }
+}
+
+/// **The user data directory followed by `XDG_DATA_DIRS`**
+pub fn data_dirs() -> Vec<PathBuf> {
+    let system = env::var("XDG_DATA_DIRS").unwrap_or_else(|_| "/usr/local/share:/usr/share".into());
+
+    dirs::data_dir()
+        .into_iter()
+        .chain(system.split(':').filter(|dir| !dir.is_empty()).map(PathBuf::from))
+        .collect()
+}
+
+/// Finds an executable in `PATH`
+fn find_in_path(command: &str) -> Option<PathBuf> {
+    env::var_os("PATH").and_then(|paths| {
+        env::split_paths(&paths)
+            .map(|dir| dir.join(command))
+            .find(|path| path.is_file())
+    })
+}
@@ -0,0 +1,140 @@
+//! # Error Module
+//! This module defines **`Error`**, the error type every fallible operation of the tool returns, and the
+//! process exit code each kind of error maps to. Errors are propagated up to `main`, which prints them and
+//! exits with **`Error::exit_code`**:
+//!
+//! | Code | Meaning                                                                  |
+//! |------|--------------------------------------------------------------------------|
+//! | 0    | Success                                                                  |
+//! | 1    | Any other failure (e.g. a recording already in progress, failed doctor checks) |
+//! | 2    | Invalid command line                                                     |
+//! | 3    | A required tool (e.g. `dconf`) is not installed                          |
+//! | 4    | The settings backend failed: a D-Bus error or a failing `dconf` command  |
+//! | 5    | Invalid key, value or profile                                            |
+//! | 6    | A file couldn't be read or written                                       |
+//! | 7    | A snapshot, the incognito state or the dconf database is corrupt         |
+//! | 8    | A snapshot, config file or profile doesn't exist                         |
+//! | 9    | Partial failure: some settings were applied, others couldn't be written  |
+//! | 10   | Total failure: none of the settings could be applied                     |
+use serde::ser::{ Serialize, SerializeStruct, Serializer };
+use std::fmt::{ self, Display };
+use std::io;
+use std::path::{ Path, PathBuf };
+
+/// Result type used throughout the tool
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// **Everything that can go wrong while running the tool**
+#[derive(Debug)]
+pub enum Error {
+    /// A command-line tool the operation needs isn't installed
+    MissingTool(&'static str),
+    /// A command-line tool ran but reported a failure
+    Tool { tool: &'static str, message: String },
+    /// The session bus or the dconf writer service failed
+    Bus(String),
+    /// A malformed key path, schema id, key name or value
+    InvalidKey(String),
+    /// A profile that can't be applied: unknown layers, cycles or values the installed schemas reject
+    InvalidProfile(String),
+    /// A file or directory couldn't be accessed
+    Io { action: &'static str, path: PathBuf, source: io::Error },
+    /// A file the tool wrote (or dconf's database) can't be understood anymore
+    Corrupt { path: PathBuf, message: String },
+    /// A snapshot, config file or profile that doesn't exist
+    NotFound(String),
+    /// Some (or all) keys of a changeset couldn't be written, as detailed in its `ApplyReport`
+    Apply { failed: usize, total: usize },
+    /// Any other failure, described for the user
+    Other(String),
+}
+
+impl Error {
+    /// **Wraps an I/O failure**, `action` describing what was attempted (e.g. `read`, `write to`)
+    pub fn io(action: &'static str, path: impl AsRef<Path>, source: io::Error) -> Self {
+        Error::Io { action, path: path.as_ref().to_path_buf(), source }
+    }
+
+    /// **Reports a file that can't be parsed**
+    pub fn corrupt(path: impl AsRef<Path>, message: impl Display) -> Self {
+        Error::Corrupt { path: path.as_ref().to_path_buf(), message: message.to_string() }
+    }
+
+    /// **Wraps a failure to run `tool`**, telling a missing binary apart from other spawn errors
+    pub fn spawn(tool: &'static str, source: io::Error) -> Self {
+        match source.kind() {
+            io::ErrorKind::NotFound => Error::MissingTool(tool),
+            _ => Error::Tool { tool, message: format!("couldn't be run: {}", source) },
+        }
+    }
+
+    /// **Short machine-readable name of the kind of error**, as reported in JSON output
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Error::MissingTool(_) => "missing_tool",
+            Error::Tool { .. } => "tool",
+            Error::Bus(_) => "bus",
+            Error::InvalidKey(_) => "invalid_key",
+            Error::InvalidProfile(_) => "invalid_profile",
+            Error::Io { .. } => "io",
+            Error::Corrupt { .. } => "corrupt",
+            Error::NotFound(_) => "not_found",
+            Error::Apply { .. } => "apply",
+            Error::Other(_) => "other",
+        }
+    }
+
+    /// **Process exit code for this error**, as documented in the module overview
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Error::Other(_) => 1,
+            Error::MissingTool(_) => 3,
+            Error::Tool { .. } | Error::Bus(_) => 4,
+            Error::InvalidKey(_) | Error::InvalidProfile(_) => 5,
+            Error::Io { .. } => 6,
+            Error::Corrupt { .. } => 7,
+            Error::NotFound(_) => 8,
+            Error::Apply { failed, total } if failed < total => 9,
+            Error::Apply { .. } => 10,
+        }
+    }
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::MissingTool(tool) => write!(f, "{} is not installed or not in PATH", tool),
+            Error::Tool { tool, message } => write!(f, "{} {}", tool, message),
+            Error::Io { action, path, source } => write!(f, "Couldn't {} {}: {}", action, path.display(), source),
+            Error::Corrupt { path, message } => write!(f, "Couldn't parse {}: {}", path.display(), message),
+            Error::Apply { failed, total } if failed < total =>
+                write!(f, "{} of {} settings couldn't be applied", failed, total),
+            Error::Apply { .. } => f.write_str("None of the settings could be applied"),
+            Error::Bus(message) |
+            Error::InvalidKey(message) |
+            Error::InvalidProfile(message) |
+            Error::NotFound(message) |
+            Error::Other(message) => f.write_str(message),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+/// Errors are serialized as `{ "code": 5, "kind": "invalid_key", "message": "..." }`
+impl Serialize for Error {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        let mut error = serializer.serialize_struct("Error", 3)?;
+        error.serialize_field("code", &self.exit_code())?;
+        error.serialize_field("kind", self.kind())?;
+        error.serialize_field("message", &self.to_string())?;
+        error.end()
+    }
+}
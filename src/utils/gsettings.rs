@@ -1,9 +1,17 @@
 //! # GSetting Utility Module
 //! This module provides functions for interacting with the **`gsettings`** command-line utility.
 //!
-//! This module contains two functions: **`get`** and **`set`**, which allow you to retrieve and update
-//! fields in the GSettings configuration database using the `gsettings` command.
-use std::process::Command;
+//! Its **`get`** function retrieves fields from the GSettings configuration database using the
+//! `gsettings` command.
+//!
+//! It also wraps the **`dconf`** command-line utility (`read`, `write`, `reset`, `dump` and `load`),
+//! which backs the `CommandBackend` implementation of the `SettingsBackend` trait.
+//!
+//! A missing binary is reported as `Error::MissingTool`, and a command that exits with a failure as
+//! `Error::Tool` along with what it printed on stderr.
+use crate::utils::error::{ Error, Result };
+use std::io::Write;
+use std::process::{ Command, Output, Stdio };
 
 /// Retrieves the field of a given key from the GSettings configuration database.
 ///
@@ -14,78 +22,154 @@ use std::process::Command;
 ///
 /// ## Returns:
 ///
-/// The output of the `gsettings` command as a `Result` containing a `String` if successful,
-/// or an error message as a `String` if unsuccessful.
+/// The output of the `gsettings` command as a `String` if successful, or an `Error` if `gsettings` is
+/// missing or fails.
 ///
 /// > **Note:** This function is currently unused in the project, but is included for possible
 /// > future use.
 #[allow(dead_code)]
-pub fn get(key: &str, field: &str) -> String {
+pub fn get(key: &str, field: &str) -> Result<String> {
     let output = Command::new("gsettings")
         .arg("get")
         .arg(key)
         .arg(field)
         .output()
-        .expect("Failed to get data");
+        .map_err(|why| Error::spawn("gsettings", why))?;
 
-    if output.status.success() {
-        format!("{}", String::from_utf8_lossy(&output.stdout))
-    } else {
-        "Error retrieving field".into()
-    }
+    check("gsettings", &output, &format!("couldn't read {} {}", key, field))?;
+    Ok(String::from_utf8_lossy(&output.stdout).to_string())
 }
 
-/// **Sets the field of a given key in the GSettings configuration database**
+/// **Sets the field of a given key in the DCONF configuration database**
 ///
 /// ## Args:
 ///
-/// * `key` - The key for the `gsettings` command
-/// * `field` - The field to look for of the provided `key`
-/// * `value` - The value to be set for the specified `field`
+/// * `key` - The full path of the key
+/// * `value` - The value to be set, in GVariant text format
 ///
 /// ## Returns:
 ///
-/// The output of the `gsettings` command as a `Result` containing a success message as a `String`
-/// if the key field is updated successfully, or an error message as a `String` if unsuccessful.
-pub fn set(key: &str, field: &str, value: &str) -> Result<String, String> {
-    let output = Command::new("gsettings")
-        .arg("set")
+/// `Ok` if the key was updated, or an `Error` if `dconf` is missing or fails.
+pub fn set_dconf(key: &str, value: &str) -> Result<()> {
+    let output = Command::new("dconf")
+        .arg("write")
         .arg(key)
-        .arg(field)
         .arg(value)
         .output()
-        .expect("Failed to set gsetting data");
+        .map_err(|why| Error::spawn("dconf", why))?;
 
-    if output.status.success() {
-        Ok(String::from(format!("{} updated!", field)))
-    } else {
-        Err("Error setting field value".into())
-    }
+    check("dconf", &output, &format!("couldn't write {}", key))
 }
 
-/// **Sets the field of a given key in the DCONF configuration database**
+/// **Reads the value of a key from the DCONF configuration database**
 ///
 /// ## Args:
 ///
-/// * `key` - The key for the `dconf` command
-/// * `field` - The field to look for of the provided `key`
-/// * `value` - The value to be set for the specified `field`
+/// * `key` - The full path of the key to read
 ///
 /// ## Returns:
 ///
-/// The output of the `dconf` command as a `Result` containing a success message as a `String`
-/// if the key field is updated successfully, or an error message as a `String` if unsuccessful.
-pub fn set_dconf(key: &str, value: &str) -> Result<String, String> {
+/// The value in GVariant text format, `None` if the key is unset, or an `Error` if `dconf` is missing
+/// or fails.
+pub fn get_dconf(key: &str) -> Result<Option<String>> {
     let output = Command::new("dconf")
-        .arg("write")
+        .arg("read")
         .arg(key)
-        .arg(value)
         .output()
-        .expect("Failed to set dconf data");
+        .map_err(|why| Error::spawn("dconf", why))?;
 
+    check("dconf", &output, &format!("couldn't read {}", key))?;
+    let value = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    Ok(if value.is_empty() { None } else { Some(value) })
+}
+
+/// **Resets a key (or a whole directory) in the DCONF configuration database**
+///
+/// ## Args:
+///
+/// * `key` - The full path of the key, or a directory path ending in `/`
+///
+/// ## Returns:
+///
+/// `Ok` if the key was reset, or an `Error` if `dconf` is missing or fails.
+pub fn reset_dconf(key: &str) -> Result<()> {
+    let mut command = Command::new("dconf");
+    command.arg("reset");
+
+    if key.ends_with('/') {
+        command.arg("-f");
+    }
+
+    let output = command
+        .arg(key)
+        .output()
+        .map_err(|why| Error::spawn("dconf", why))?;
+
+    check("dconf", &output, &format!("couldn't reset {}", key))
+}
+
+/// **Dumps a directory of the DCONF configuration database in keyfile format**
+///
+/// ## Args:
+///
+/// * `dir` - The directory to dump (e.g. `/`)
+///
+/// ## Returns:
+///
+/// The output of `dconf dump` as a `String`, or an `Error` if `dconf` is missing or fails.
+pub fn dump_dconf(dir: &str) -> Result<String> {
+    let output = Command::new("dconf")
+        .arg("dump")
+        .arg(dir)
+        .output()
+        .map_err(|why| Error::spawn("dconf", why))?;
+
+    check("dconf", &output, &format!("couldn't dump {}", dir))?;
+    Ok(String::from_utf8_lossy(&output.stdout).to_string())
+}
+
+/// **Loads keyfile data into a directory of the DCONF configuration database**
+///
+/// ## Args:
+///
+/// * `dir` - The directory to load into (e.g. `/`)
+/// * `keyfile` - Keyfile data, as produced by `dump_dconf`
+///
+/// ## Returns:
+///
+/// `Ok` if the data was loaded, or an `Error` if `dconf` is missing or fails.
+pub fn load_dconf(dir: &str, keyfile: &str) -> Result<()> {
+    let mut child = Command::new("dconf")
+        .arg("load")
+        .arg(dir)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|why| Error::spawn("dconf", why))?;
+
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin
+            .write_all(keyfile.as_bytes())
+            .map_err(|why| Error::Tool { tool: "dconf", message: format!("couldn't be sent the keyfile: {}", why) })?;
+    }
+
+    let output = child.wait_with_output().map_err(|why| Error::spawn("dconf", why))?;
+    check("dconf", &output, &format!("couldn't load {}", dir))
+}
+
+/// Turns a failed exit status into an `Error::Tool`, with what the command printed on stderr
+fn check(tool: &'static str, output: &Output, what: &str) -> Result<()> {
     if output.status.success() {
-        Ok(String::from(format!("{} updated!", key)))
-    } else {
-        Err("Error setting field value".into())
+        return Ok(());
     }
+
+    let stderr = String::from_utf8_lossy(&output.stderr).trim().to_string();
+    Err(Error::Tool {
+        tool,
+        message: match stderr.is_empty() {
+            true => what.to_string(),
+            false => format!("{}: {}", what, stderr),
+        },
+    })
 }
@@ -0,0 +1,1428 @@
+//! # GVariant Value Module
+//! This module provides the **`Variant`** type, a typed representation of the values stored in the dconf
+//! database, along with the conversions the tool needs to move those values around:
+//!
+//! * **`parse`** and **`print`** read and write the GVariant text format used by `dconf` and `gsettings`
+//!   (e.g. `'stretched'`, `uint32 5` or `[('xkb', 'us')]`), printing type annotations the same way `dconf dump` does.
+//! * **`decode`** and **`encode`** read and write the little-endian GVariant serialization format used by
+//!   the dconf database file and the changesets sent to the dconf writer service.
+//!
+//! Bare numbers in an array take a common type as in GLib, so `[1, 2.5]` is an array of doubles (only the
+//! array's own elements are unified, not numbers nested deeper). A one-element tuple may be written `(1)`
+//! as well as `(1,)`, and strings accept `\xNN` escapes besides the ones GLib prints.
+//!
+//! Values compare by content rather than by spelling, so `'on'` equals `"on"` and `1.0` equals `1.00`; a
+//! `nan` double equals another `nan`, so a value always equals itself after a round trip through text.
+//! They are (de)serialized with serde as their text format, which keeps snapshots, state files and JSON
+//! output readable.
+use serde::de::{ self, Deserializer };
+use serde::{ Deserialize, Serialize, Serializer };
+use std::fmt::{ self, Display, Write };
+
+/// Type keywords that may prefix a value, along with the type code they stand for
+const TYPE_KEYWORDS: [(&str, &str); 13] = [
+    ("boolean", "b"),
+    ("byte", "y"),
+    ("int16", "n"),
+    ("uint16", "q"),
+    ("int32", "i"),
+    ("uint32", "u"),
+    ("int64", "x"),
+    ("uint64", "t"),
+    ("handle", "h"),
+    ("double", "d"),
+    ("string", "s"),
+    ("objectpath", "o"),
+    ("signature", "g"),
+];
+
+/// Deepest nesting of containers accepted in type strings, text and serialized data, as in GLib
+const MAX_DEPTH: usize = 128;
+
+/// **A typed GVariant value**
+///
+/// Containers keep the type of their elements so empty arrays and `nothing` maybes can still be
+/// printed and serialized. Dictionaries are arrays of `DictEntry` values, as in GVariant itself.
+/// The `Variant` variant mirrors GVariant's own `v` type, a value boxed along with its type.
+#[derive(Debug, Clone)]
+#[allow(clippy::enum_variant_names)]
+pub enum Variant {
+    Bool(bool),
+    Byte(u8),
+    Int16(i16),
+    Uint16(u16),
+    Int32(i32),
+    Uint32(u32),
+    Int64(i64),
+    Uint64(u64),
+    Handle(i32),
+    Double(f64),
+    String(String),
+    ObjectPath(String),
+    Signature(String),
+    Variant(Box<Variant>),
+    Maybe(String, Option<Box<Variant>>),
+    Array(String, Vec<Variant>),
+    Tuple(Vec<Variant>),
+    DictEntry(Box<Variant>, Box<Variant>),
+}
+
+impl Variant {
+    /// GVariant type string of the value (e.g. `s`, `as` or `a{sv}`)
+    pub fn type_string(&self) -> String {
+        match self {
+            Variant::Bool(_) => "b".into(),
+            Variant::Byte(_) => "y".into(),
+            Variant::Int16(_) => "n".into(),
+            Variant::Uint16(_) => "q".into(),
+            Variant::Int32(_) => "i".into(),
+            Variant::Uint32(_) => "u".into(),
+            Variant::Int64(_) => "x".into(),
+            Variant::Uint64(_) => "t".into(),
+            Variant::Handle(_) => "h".into(),
+            Variant::Double(_) => "d".into(),
+            Variant::String(_) => "s".into(),
+            Variant::ObjectPath(_) => "o".into(),
+            Variant::Signature(_) => "g".into(),
+            Variant::Variant(_) => "v".into(),
+            Variant::Maybe(element, _) => format!("m{}", element),
+            Variant::Array(element, _) => format!("a{}", element),
+            Variant::Tuple(fields) => {
+                format!("({})", fields.iter().map(Variant::type_string).collect::<String>())
+            }
+            Variant::DictEntry(key, value) => {
+                format!("{{{}{}}}", key.type_string(), value.type_string())
+            }
+        }
+    }
+}
+
+impl PartialEq for Variant {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Variant::Bool(a), Variant::Bool(b)) => a == b,
+            (Variant::Byte(a), Variant::Byte(b)) => a == b,
+            (Variant::Int16(a), Variant::Int16(b)) => a == b,
+            (Variant::Uint16(a), Variant::Uint16(b)) => a == b,
+            (Variant::Int32(a), Variant::Int32(b)) | (Variant::Handle(a), Variant::Handle(b)) => a == b,
+            (Variant::Uint32(a), Variant::Uint32(b)) => a == b,
+            (Variant::Int64(a), Variant::Int64(b)) => a == b,
+            (Variant::Uint64(a), Variant::Uint64(b)) => a == b,
+            (Variant::Double(a), Variant::Double(b)) => a == b || (a.is_nan() && b.is_nan()),
+            | (Variant::String(a), Variant::String(b))
+            | (Variant::ObjectPath(a), Variant::ObjectPath(b))
+            | (Variant::Signature(a), Variant::Signature(b)) => a == b,
+            (Variant::Variant(a), Variant::Variant(b)) => a == b,
+            (Variant::Maybe(a, child), Variant::Maybe(b, other)) => a == b && child == other,
+            (Variant::Array(a, elements), Variant::Array(b, others)) => a == b && elements == others,
+            (Variant::Tuple(fields), Variant::Tuple(others)) => fields == others,
+            (Variant::DictEntry(key, value), Variant::DictEntry(other_key, other_value)) =>
+                key == other_key && value == other_value,
+            _ => false,
+        }
+    }
+}
+
+impl Display for Variant {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut out = String::new();
+        print_value(self, &mut out, true);
+        f.write_str(&out)
+    }
+}
+
+impl Serialize for Variant {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&print(self))
+    }
+}
+
+impl<'de> Deserialize<'de> for Variant {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        parse(&String::deserialize(deserializer)?).map_err(de::Error::custom)
+    }
+}
+
+/// **Parses a value written in GVariant text format**
+///
+/// ## Args:
+///
+/// * `text` - The value to parse (e.g. `'Fluent-Round-Dark'` or `@as []`)
+///
+/// ## Returns:
+///
+/// The typed value, or an error message as a `String` if the text is malformed.
+pub fn parse(text: &str) -> Result<Variant, String> {
+    let mut parser = Parser { text, pos: 0, depth: 0 };
+    let value = parser.value(None)?;
+
+    parser.skip_whitespace();
+    match parser.peek() {
+        None => Ok(value),
+        Some(c) => Err(parser.error(&format!("unexpected '{}' after value", c))),
+    }
+}
+
+/// **Parses a value written in GVariant text format as the given type**
+///
+/// Unlike **`parse`**, bare literals take the expected type, so `1` is accepted for a `d` or `u` key just
+/// like `gsettings set` accepts it.
+///
+/// ## Returns:
+///
+/// The typed value, or an error message as a `String` if the text is malformed or of another type.
+pub fn parse_as(text: &str, type_string: &str) -> Result<Variant, String> {
+    check_type(type_string)?;
+    let mut parser = Parser { text, pos: 0, depth: 0 };
+    let value = parser.value(Some(type_string))?;
+
+    parser.skip_whitespace();
+    match parser.peek() {
+        None => Ok(value),
+        Some(c) => Err(parser.error(&format!("unexpected '{}' after value", c))),
+    }
+}
+
+/// **Prints a value in GVariant text format**
+///
+/// Values are annotated with their type wherever it can't be inferred from the text alone,
+/// matching the output of `dconf dump`.
+pub fn print(value: &Variant) -> String {
+    value.to_string()
+}
+
+/// **Decodes a value from the GVariant serialization format**
+///
+/// ## Args:
+///
+/// * `type_string` - The type of the serialized value (e.g. `v` for dconf database entries)
+/// * `data` - The little-endian serialized data
+///
+/// ## Returns:
+///
+/// The typed value, or an error message as a `String` if the data doesn't match the type.
+pub fn decode(type_string: &str, data: &[u8]) -> Result<Variant, String> {
+    check_type(type_string)?;
+    decode_nested(type_string, data, 0)
+}
+
+/// Decodes a value nested in `depth` containers
+fn decode_nested(type_string: &str, data: &[u8], depth: usize) -> Result<Variant, String> {
+    let malformed = || format!("Malformed serialized data for type '{}'", type_string);
+    if depth > MAX_DEPTH {
+        return Err(format!("Serialized data for type '{}' is nested too deeply", type_string));
+    }
+
+    fn fixed<const N: usize>(data: &[u8]) -> Option<[u8; N]> {
+        data.try_into().ok()
+    }
+
+    Ok(match type_string {
+        "b" =>
+            match data {
+                [byte] => Variant::Bool(*byte != 0),
+                _ => {
+                    return Err(malformed());
+                }
+            }
+        "y" => Variant::Byte(u8::from_le_bytes(fixed(data).ok_or_else(malformed)?)),
+        "n" => Variant::Int16(i16::from_le_bytes(fixed(data).ok_or_else(malformed)?)),
+        "q" => Variant::Uint16(u16::from_le_bytes(fixed(data).ok_or_else(malformed)?)),
+        "i" => Variant::Int32(i32::from_le_bytes(fixed(data).ok_or_else(malformed)?)),
+        "u" => Variant::Uint32(u32::from_le_bytes(fixed(data).ok_or_else(malformed)?)),
+        "h" => Variant::Handle(i32::from_le_bytes(fixed(data).ok_or_else(malformed)?)),
+        "x" => Variant::Int64(i64::from_le_bytes(fixed(data).ok_or_else(malformed)?)),
+        "t" => Variant::Uint64(u64::from_le_bytes(fixed(data).ok_or_else(malformed)?)),
+        "d" => Variant::Double(f64::from_le_bytes(fixed(data).ok_or_else(malformed)?)),
+        "s" | "o" | "g" => {
+            let string = match data.split_last() {
+                Some((0, bytes)) => String::from_utf8(bytes.to_vec()).map_err(|_| malformed())?,
+                _ => {
+                    return Err(malformed());
+                }
+            };
+            match type_string {
+                "o" => Variant::ObjectPath(string),
+                "g" => Variant::Signature(string),
+                _ => Variant::String(string),
+            }
+        }
+        "v" => {
+            let separator = data
+                .iter()
+                .rposition(|byte| *byte == 0)
+                .ok_or_else(malformed)?;
+            let child_type = std::str::from_utf8(&data[separator + 1..]).map_err(|_| malformed())?;
+            check_type(child_type)?;
+            Variant::Variant(Box::new(decode_nested(child_type, &data[..separator], depth + 1)?))
+        }
+        _ if type_string.starts_with('m') => {
+            let element = &type_string[1..];
+            let child = match (data, fixed_size(element)?) {
+                ([], _) => None,
+                (_, Some(_)) => Some(decode_nested(element, data, depth + 1)?),
+                (_, None) => Some(decode_nested(element, &data[..data.len() - 1], depth + 1)?),
+            };
+            Variant::Maybe(element.to_string(), child.map(Box::new))
+        }
+        _ if type_string.starts_with('a') => {
+            let element = &type_string[1..];
+            let mut elements = Vec::new();
+
+            match fixed_size(element)? {
+                Some(size) => {
+                    if !data.len().is_multiple_of(size) {
+                        return Err(malformed());
+                    }
+                    for chunk in data.chunks(size) {
+                        elements.push(decode_nested(element, chunk, depth + 1)?);
+                    }
+                }
+                None if data.is_empty() => (),
+                None => {
+                    let offset_size = offset_size(data.len());
+                    let offsets_start = read_offset(data, data.len() - offset_size, offset_size)?;
+                    if
+                        offsets_start > data.len() ||
+                        !(data.len() - offsets_start).is_multiple_of(offset_size)
+                    {
+                        return Err(malformed());
+                    }
+
+                    let alignment = alignment(element)?;
+                    let mut start = 0;
+                    for offset in (offsets_start..data.len()).step_by(offset_size) {
+                        let end = read_offset(data, offset, offset_size)?;
+                        start = align(start, alignment);
+                        if start > end || end > offsets_start {
+                            return Err(malformed());
+                        }
+                        elements.push(decode_nested(element, &data[start..end], depth + 1)?);
+                        start = end;
+                    }
+                }
+            }
+            Variant::Array(element.to_string(), elements)
+        }
+        _ if type_string.starts_with('(') || type_string.starts_with('{') => {
+            let members = member_types(type_string)?;
+            let offset_size = offset_size(data.len());
+            let mut frame_end = data.len();
+            let mut start = 0;
+            let mut fields = Vec::new();
+
+            for (i, member) in members.iter().enumerate() {
+                start = align(start, alignment(member)?);
+                let end = match fixed_size(member)? {
+                    Some(size) => start + size,
+                    None if i == members.len() - 1 => frame_end,
+                    None => {
+                        frame_end = frame_end.checked_sub(offset_size).ok_or_else(malformed)?;
+                        read_offset(data, frame_end, offset_size)?
+                    }
+                };
+                if start > end || end > frame_end {
+                    return Err(malformed());
+                }
+                fields.push(decode_nested(member, &data[start..end], depth + 1)?);
+                start = end;
+            }
+
+            match (type_string.starts_with('{'), fields.len()) {
+                (true, 2) => {
+                    let value = fields.pop().unwrap_or(Variant::Bool(false));
+                    let key = fields.pop().unwrap_or(Variant::Bool(false));
+                    Variant::DictEntry(Box::new(key), Box::new(value))
+                }
+                (true, _) => {
+                    return Err(malformed());
+                }
+                _ => Variant::Tuple(fields),
+            }
+        }
+        _ => {
+            return Err(format!("Invalid type string '{}'", type_string));
+        }
+    })
+}
+
+/// **Encodes a value in the GVariant serialization format**
+///
+/// The output is little-endian and can be read back with `decode` using the value's `type_string`.
+pub fn encode(value: &Variant) -> Vec<u8> {
+    match value {
+        Variant::Bool(boolean) => vec![u8::from(*boolean)],
+        Variant::Byte(number) => vec![*number],
+        Variant::Int16(number) => number.to_le_bytes().to_vec(),
+        Variant::Uint16(number) => number.to_le_bytes().to_vec(),
+        Variant::Int32(number) | Variant::Handle(number) => number.to_le_bytes().to_vec(),
+        Variant::Uint32(number) => number.to_le_bytes().to_vec(),
+        Variant::Int64(number) => number.to_le_bytes().to_vec(),
+        Variant::Uint64(number) => number.to_le_bytes().to_vec(),
+        Variant::Double(number) => number.to_le_bytes().to_vec(),
+        Variant::String(string) | Variant::ObjectPath(string) | Variant::Signature(string) => {
+            let mut data = string.as_bytes().to_vec();
+            data.push(0);
+            data
+        }
+        Variant::Variant(child) => {
+            let mut data = encode(child);
+            data.push(0);
+            data.extend_from_slice(child.type_string().as_bytes());
+            data
+        }
+        Variant::Maybe(_, None) => Vec::new(),
+        Variant::Maybe(element, Some(child)) => {
+            let mut data = encode(child);
+            if !matches!(fixed_size(element), Ok(Some(_))) {
+                data.push(0);
+            }
+            data
+        }
+        Variant::Array(element, elements) => {
+            let alignment = alignment(element).unwrap_or(1);
+            let is_fixed = matches!(fixed_size(element), Ok(Some(_)));
+            let mut data = Vec::new();
+            let mut offsets = Vec::new();
+
+            for element in elements {
+                pad(&mut data, alignment);
+                data.extend(encode(element));
+                if !is_fixed {
+                    offsets.push(data.len());
+                }
+            }
+            append_offsets(&mut data, &offsets);
+            data
+        }
+        Variant::Tuple(_) | Variant::DictEntry(..) => {
+            let members: Vec<&Variant> = match value {
+                Variant::DictEntry(key, value) => vec![key, value],
+                Variant::Tuple(fields) => fields.iter().collect(),
+                _ => Vec::new(),
+            };
+            let type_string = value.type_string();
+            let mut data = Vec::new();
+            let mut offsets = Vec::new();
+
+            if members.is_empty() {
+                return vec![0];
+            }
+
+            for (i, member) in members.iter().enumerate() {
+                let member_type = member.type_string();
+                pad(&mut data, alignment(&member_type).unwrap_or(1));
+                data.extend(encode(member));
+                if i < members.len() - 1 && !matches!(fixed_size(&member_type), Ok(Some(_))) {
+                    offsets.push(data.len());
+                }
+            }
+
+            match fixed_size(&type_string) {
+                Ok(Some(size)) => data.resize(size, 0),
+                _ => {
+                    offsets.reverse();
+                    append_offsets(&mut data, &offsets);
+                }
+            }
+            data
+        }
+    }
+}
+
+/// **Checks that a type string holds a single valid type**, nested no deeper than `MAX_DEPTH`
+fn check_type(type_string: &str) -> Result<(), String> {
+    match split_type(type_string)? {
+        // Computing the alignment visits the members of every tuple and dict entry
+        (_, "") => alignment(type_string).map(|_| ()),
+        _ => Err(format!("Invalid type string '{}': expected a single type", type_string)),
+    }
+}
+
+/// Splits the first complete type off a type string (e.g. `a{ss}i` becomes `a{ss}` and `i`)
+fn split_type(signature: &str) -> Result<(&str, &str), String> {
+    // The containers the current character is nested in: open brackets, and `a` and `m` prefixes
+    // waiting for their element type
+    let mut open = Vec::new();
+
+    for (i, c) in signature.char_indices() {
+        match c {
+            'a' | 'm' | '(' | '{' => {
+                open.push(c);
+                if open.len() > MAX_DEPTH {
+                    return Err(format!("Type string '{}' is nested too deeply", signature));
+                }
+                continue;
+            }
+            ')' if open.last() == Some(&'(') => {
+                open.pop();
+            }
+            '}' if open.last() == Some(&'{') => {
+                open.pop();
+            }
+            'b' | 'y' | 'n' | 'q' | 'i' | 'u' | 'x' | 't' | 'h' | 'd' | 's' | 'o' | 'g' | 'v' => (),
+            _ => {
+                return Err(format!("Invalid type string '{}'", signature));
+            }
+        }
+
+        // A complete type also completes the arrays and maybes it is the element of
+        while matches!(open.last(), Some('a' | 'm')) {
+            open.pop();
+        }
+        if open.is_empty() {
+            return Ok(signature.split_at(i + 1));
+        }
+    }
+
+    Err(format!("Incomplete type string '{}'", signature))
+}
+
+/// Lists the member types of a tuple or dict entry type, which holds exactly a key and a value
+fn member_types(type_string: &str) -> Result<Vec<&str>, String> {
+    let invalid = || format!("Invalid type string '{}'", type_string);
+    let (mut rest, is_entry) = match type_string.strip_prefix('(').and_then(|rest| rest.strip_suffix(')')) {
+        Some(rest) => (rest, false),
+        None => (type_string.strip_prefix('{').and_then(|rest| rest.strip_suffix('}')).ok_or_else(invalid)?, true),
+    };
+    let mut members = Vec::new();
+
+    while !rest.is_empty() {
+        let (member, remaining) = split_type(rest)?;
+        members.push(member);
+        rest = remaining;
+    }
+
+    match is_entry && members.len() != 2 {
+        true => Err(invalid()),
+        false => Ok(members),
+    }
+}
+
+/// Alignment of a type in the serialization format
+fn alignment(type_string: &str) -> Result<usize, String> {
+    Ok(match type_string.chars().next() {
+        Some('b' | 'y' | 's' | 'o' | 'g') => 1,
+        Some('n' | 'q') => 2,
+        Some('i' | 'u' | 'h') => 4,
+        Some('x' | 't' | 'd' | 'v') => 8,
+        Some('a' | 'm') => alignment(&type_string[1..])?,
+        Some('(' | '{') => {
+            let mut largest = 1;
+            for member in member_types(type_string)? {
+                largest = largest.max(alignment(member)?);
+            }
+            largest
+        }
+        _ => {
+            return Err(format!("Invalid type string '{}'", type_string));
+        }
+    })
+}
+
+/// Size of a type in the serialization format, or `None` if it has a variable size
+fn fixed_size(type_string: &str) -> Result<Option<usize>, String> {
+    Ok(match type_string.chars().next() {
+        Some('b' | 'y') => Some(1),
+        Some('n' | 'q') => Some(2),
+        Some('i' | 'u' | 'h') => Some(4),
+        Some('x' | 't' | 'd') => Some(8),
+        Some('s' | 'o' | 'g' | 'v' | 'a' | 'm') => None,
+        Some('(' | '{') => {
+            let members = member_types(type_string)?;
+            if members.is_empty() {
+                return Ok(Some(1));
+            }
+
+            let mut size = 0;
+            for member in members {
+                match fixed_size(member)? {
+                    Some(member_size) => {
+                        size = align(size, alignment(member)?) + member_size;
+                    }
+                    None => {
+                        return Ok(None);
+                    }
+                }
+            }
+            Some(align(size, alignment(type_string)?))
+        }
+        _ => {
+            return Err(format!("Invalid type string '{}'", type_string));
+        }
+    })
+}
+
+fn align(offset: usize, alignment: usize) -> usize {
+    offset.div_ceil(alignment) * alignment
+}
+
+/// Size of the framing offsets used by a container of the given size
+fn offset_size(container_size: usize) -> usize {
+    match container_size {
+        0..=0xff => 1,
+        0x100..=0xffff => 2,
+        0x10000..=0xffff_ffff => 4,
+        _ => 8,
+    }
+}
+
+fn pad(data: &mut Vec<u8>, alignment: usize) {
+    data.resize(align(data.len(), alignment), 0);
+}
+
+/// Appends framing offsets using the smallest offset size that can address the whole container
+fn append_offsets(data: &mut Vec<u8>, offsets: &[usize]) {
+    if offsets.is_empty() {
+        return;
+    }
+
+    let mut size = 1;
+    while offset_size(data.len() + offsets.len() * size) != size {
+        size *= 2;
+    }
+    for offset in offsets {
+        data.extend_from_slice(&(*offset as u64).to_le_bytes()[..size]);
+    }
+}
+
+fn read_offset(data: &[u8], at: usize, size: usize) -> Result<usize, String> {
+    let bytes = data.get(at..at + size).ok_or("Framing offset out of bounds")?;
+    let mut buffer = [0u8; 8];
+    buffer[..size].copy_from_slice(bytes);
+    Ok(u64::from_le_bytes(buffer) as usize)
+}
+
+struct Parser<'a> {
+    text: &'a str,
+    pos: usize,
+    /// Number of containers the value being parsed is nested in
+    depth: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> Option<char> {
+        self.text[self.pos..].chars().next()
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.pos += c.len_utf8();
+        Some(c)
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.peek().is_some_and(char::is_whitespace) {
+            self.bump();
+        }
+    }
+
+    fn eat(&mut self, expected: char) -> bool {
+        self.skip_whitespace();
+        if self.peek() == Some(expected) {
+            self.bump();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, expected: char) -> Result<(), String> {
+        match self.eat(expected) {
+            true => Ok(()),
+            false => Err(self.error(&format!("expected '{}'", expected))),
+        }
+    }
+
+    fn error(&self, message: &str) -> String {
+        format!("Invalid value '{}' at offset {}: {}", self.text, self.pos, message)
+    }
+
+    /// Consumes a run of characters that can make up a keyword or number
+    fn word(&mut self) -> &'a str {
+        let start = self.pos;
+        while
+            self
+                .peek()
+                .is_some_and(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '.' | '+' | '-'))
+        {
+            self.bump();
+        }
+        &self.text[start..self.pos]
+    }
+
+    /// Whether the next value is a bare number, whose type depends on the numbers around it
+    fn at_number(&mut self) -> bool {
+        self.skip_whitespace();
+        let rest = &self.text[self.pos..];
+        rest.starts_with(|c: char| c.is_ascii_digit() || matches!(c, '-' | '+' | '.')) ||
+            rest.starts_with("inf") ||
+            rest.starts_with("nan")
+    }
+
+    /// Parses the next value, checking it against `expected` when its type is already known
+    fn value(&mut self, expected: Option<&str>) -> Result<Variant, String> {
+        if self.depth == MAX_DEPTH {
+            return Err(self.error("value nested too deeply"));
+        }
+
+        self.depth += 1;
+        let value = self.nested_value(expected);
+        self.depth -= 1;
+        value
+    }
+
+    fn nested_value(&mut self, expected: Option<&str>) -> Result<Variant, String> {
+        self.skip_whitespace();
+
+        // A maybe value may be written as its bare child, without the `just` keyword
+        if let Some(element) = expected.and_then(|signature| signature.strip_prefix('m')) {
+            let rest = &self.text[self.pos..];
+            if !(rest.starts_with('@') || rest.starts_with("just") || rest.starts_with("nothing")) {
+                let child = self.value(Some(element))?;
+                return Ok(Variant::Maybe(element.to_string(), Some(Box::new(child))));
+            }
+        }
+
+        let value = match self.peek() {
+            Some('@') => {
+                self.bump();
+                let start = self.pos;
+                while self.peek().is_some_and(|c| !c.is_whitespace()) {
+                    self.bump();
+                }
+                let annotation = &self.text[start..self.pos];
+                check_type(annotation).map_err(|why| self.error(&why))?;
+                self.value(Some(annotation))?
+            }
+            Some('[') => self.array(expected)?,
+            Some('{') => self.dict(expected)?,
+            Some('(') => self.tuple(expected)?,
+            Some('<') => {
+                self.bump();
+                let child = self.value(None)?;
+                self.expect('>')?;
+                Variant::Variant(Box::new(child))
+            }
+            Some('b') if self.text[self.pos + 1..].starts_with(['\'', '"']) => {
+                self.bump();
+                self.bytestring()?
+            }
+            Some('\'' | '"') => {
+                let string = self.string()?;
+                match expected {
+                    Some("o") => Variant::ObjectPath(string),
+                    Some("g") => Variant::Signature(string),
+                    _ => Variant::String(string),
+                }
+            }
+            Some(c) if c.is_ascii_alphabetic() => {
+                let word = self.word();
+                match word {
+                    "true" => Variant::Bool(true),
+                    "false" => Variant::Bool(false),
+                    "nothing" =>
+                        match expected.and_then(|signature| signature.strip_prefix('m')) {
+                            Some(element) => Variant::Maybe(element.to_string(), None),
+                            None => {
+                                return Err(self.error("unable to infer type of 'nothing'"));
+                            }
+                        }
+                    "just" => {
+                        let element = expected.and_then(|signature| signature.strip_prefix('m'));
+                        let child = self.value(element)?;
+                        Variant::Maybe(child.type_string(), Some(Box::new(child)))
+                    }
+                    "inf" | "nan" => self.number(word, expected)?,
+                    _ =>
+                        match TYPE_KEYWORDS.iter().find(|(keyword, _)| *keyword == word) {
+                            Some((_, code)) => self.value(Some(code))?,
+                            None => {
+                                return Err(self.error(&format!("unknown keyword '{}'", word)));
+                            }
+                        }
+                }
+            }
+            Some(c) if c.is_ascii_digit() || matches!(c, '-' | '+' | '.') => {
+                let word = self.word();
+                self.number(word, expected)?
+            }
+            Some(c) => {
+                return Err(self.error(&format!("unexpected '{}'", c)));
+            }
+            None => {
+                return Err(self.error("unexpected end of input"));
+            }
+        };
+
+        match expected {
+            Some(signature) if value.type_string() != signature =>
+                Err(
+                    self.error(
+                        &format!(
+                            "expected type '{}' but found '{}'",
+                            signature,
+                            value.type_string()
+                        )
+                    )
+                ),
+            _ => Ok(value),
+        }
+    }
+
+    /// Parses a quoted string, handling the escapes GLib emits and `\xNN`
+    fn string(&mut self) -> Result<String, String> {
+        let quote = self.bump().unwrap_or('\'');
+        let mut string = String::new();
+
+        loop {
+            match self.bump() {
+                None => {
+                    return Err(self.error("unterminated string"));
+                }
+                Some(c) if c == quote => {
+                    return Ok(string);
+                }
+                Some('\\') =>
+                    match self.bump() {
+                        Some('n') => string.push('\n'),
+                        Some('t') => string.push('\t'),
+                        Some('r') => string.push('\r'),
+                        Some('a') => string.push('\x07'),
+                        Some('b') => string.push('\x08'),
+                        Some('f') => string.push('\x0c'),
+                        Some('v') => string.push('\x0b'),
+                        Some(c @ ('x' | 'u' | 'U')) => {
+                            let digits = match c {
+                                'x' => 2,
+                                'u' => 4,
+                                _ => 8,
+                            };
+                            let start = self.pos;
+                            for _ in 0..digits {
+                                self.bump();
+                            }
+                            let code = u32
+                                ::from_str_radix(&self.text[start..self.pos], 16)
+                                .ok()
+                                .and_then(char::from_u32)
+                                .ok_or_else(|| self.error("invalid character escape"))?;
+                            string.push(code);
+                        }
+                        Some(c) => string.push(c),
+                        None => {
+                            return Err(self.error("unterminated string"));
+                        }
+                    }
+                Some(c) => string.push(c),
+            }
+        }
+    }
+
+    /// **Parses a bytestring** (e.g. `b'/usr/share'`), an `ay` array holding the text and a trailing nul
+    ///
+    /// Escapes are the ones GLib emits: `\n` and its siblings, up to three octal digits for any other byte,
+    /// and a backslash before any other character for that character.
+    fn bytestring(&mut self) -> Result<Variant, String> {
+        let quote = self.bump().unwrap_or('\'');
+        let mut bytes = Vec::new();
+
+        loop {
+            match self.bump() {
+                None => {
+                    return Err(self.error("unterminated bytestring"));
+                }
+                Some(c) if c == quote => {
+                    break;
+                }
+                Some('\\') =>
+                    match self.bump() {
+                        Some('n') => bytes.push(b'\n'),
+                        Some('t') => bytes.push(b'\t'),
+                        Some('r') => bytes.push(b'\r'),
+                        Some('a') => bytes.push(0x07),
+                        Some('b') => bytes.push(0x08),
+                        Some('f') => bytes.push(0x0c),
+                        Some('v') => bytes.push(0x0b),
+                        Some(c @ '0'..='7') => {
+                            let mut code = c.to_digit(8).unwrap_or_default();
+                            for _ in 0..2 {
+                                match self.peek().and_then(|c| c.to_digit(8)) {
+                                    Some(digit) => {
+                                        code = code * 8 + digit;
+                                        self.bump();
+                                    }
+                                    None => {
+                                        break;
+                                    }
+                                }
+                            }
+                            bytes.push(u8::try_from(code).map_err(|_| self.error("octal escape out of range"))?);
+                        }
+                        Some(c) => bytes.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes()),
+                        None => {
+                            return Err(self.error("unterminated bytestring"));
+                        }
+                    }
+                Some(c) => bytes.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes()),
+            }
+        }
+        bytes.push(0);
+
+        Ok(Variant::Array("y".into(), bytes.into_iter().map(Variant::Byte).collect()))
+    }
+
+    /// Converts a numeric literal to the expected type (`int32` or `double` when unknown)
+    fn number(&self, word: &str, expected: Option<&str>) -> Result<Variant, String> {
+        let is_hex = word.trim_start_matches(['-', '+']).starts_with("0x");
+        let is_float = match expected {
+            Some(signature) => signature == "d",
+            None => !is_hex && (word.contains(['.', 'e', 'E']) || word.ends_with("inf") || word == "nan"),
+        };
+
+        if is_float {
+            return word
+                .parse::<f64>()
+                .map(Variant::Double)
+                .map_err(|_| self.error(&format!("invalid number '{}'", word)));
+        }
+
+        let (negative, digits) = match word.strip_prefix('-') {
+            Some(rest) => (true, rest),
+            None => (false, word.strip_prefix('+').unwrap_or(word)),
+        };
+        let magnitude = match digits.strip_prefix("0x") {
+            Some(hex) => i128::from_str_radix(hex, 16),
+            None => digits.parse::<i128>(),
+        }.map_err(|_| self.error(&format!("invalid number '{}'", word)))?;
+        let number = if negative { -magnitude } else { magnitude };
+        let out_of_range = || self.error(&format!("number '{}' out of range", word));
+
+        Ok(match expected.unwrap_or("i") {
+            "y" => Variant::Byte(u8::try_from(number).map_err(|_| out_of_range())?),
+            "n" => Variant::Int16(i16::try_from(number).map_err(|_| out_of_range())?),
+            "q" => Variant::Uint16(u16::try_from(number).map_err(|_| out_of_range())?),
+            "i" => Variant::Int32(i32::try_from(number).map_err(|_| out_of_range())?),
+            "u" => Variant::Uint32(u32::try_from(number).map_err(|_| out_of_range())?),
+            "x" => Variant::Int64(i64::try_from(number).map_err(|_| out_of_range())?),
+            "t" => Variant::Uint64(u64::try_from(number).map_err(|_| out_of_range())?),
+            "h" => Variant::Handle(i32::try_from(number).map_err(|_| out_of_range())?),
+            signature => {
+                return Err(self.error(&format!("a number can't have type '{}'", signature)));
+            }
+        })
+    }
+
+    fn array(&mut self, expected: Option<&str>) -> Result<Variant, String> {
+        self.expect('[')?;
+
+        let mut element = expected
+            .and_then(|signature| signature.strip_prefix('a'))
+            .map(String::from);
+        // Whether the element type was inferred from bare numbers, which a bare double turns into `d`
+        let mut numeric = false;
+        let mut elements = Vec::new();
+
+        if !self.eat(']') {
+            loop {
+                let bare = self.at_number();
+                let value = match numeric && bare {
+                    true => self.value(None)?,
+                    false => self.value(element.as_deref())?,
+                };
+                match element.as_deref() {
+                    None => {
+                        element = Some(value.type_string());
+                        numeric = bare;
+                    }
+                    Some("i") if numeric && matches!(value, Variant::Double(_)) => {
+                        element = Some("d".into());
+                    }
+                    _ => (),
+                }
+                elements.push(value);
+
+                if self.eat(']') {
+                    break;
+                }
+                self.expect(',')?;
+            }
+        }
+
+        if numeric && element.as_deref() == Some("d") {
+            elements = elements
+                .into_iter()
+                .map(|value| {
+                    match value {
+                        Variant::Int32(number) => Variant::Double(number.into()),
+                        value => value,
+                    }
+                })
+                .collect();
+        }
+
+        match element {
+            Some(element) => Ok(Variant::Array(element, elements)),
+            None => Err(self.error("unable to infer type of empty array")),
+        }
+    }
+
+    fn dict(&mut self, expected: Option<&str>) -> Result<Variant, String> {
+        self.expect('{')?;
+
+        let (mut key_type, mut value_type) = match
+            expected.and_then(|signature| signature.strip_prefix("a{"))
+        {
+            Some(members) => {
+                let (key, rest) = split_type(members)?;
+                let (value, _) = split_type(rest)?;
+                (Some(key.to_string()), Some(value.to_string()))
+            }
+            None => (None, None),
+        };
+        let mut entries = Vec::new();
+
+        if !self.eat('}') {
+            loop {
+                let key = self.value(key_type.as_deref())?;
+                if !self.eat(':') {
+                    return Err(self.error("standalone dictionary entries are not supported"));
+                }
+                let value = self.value(value_type.as_deref())?;
+                key_type.get_or_insert_with(|| key.type_string());
+                value_type.get_or_insert_with(|| value.type_string());
+                entries.push(Variant::DictEntry(Box::new(key), Box::new(value)));
+
+                if self.eat('}') {
+                    break;
+                }
+                self.expect(',')?;
+            }
+        }
+
+        match (key_type, value_type) {
+            (Some(key), Some(value)) => Ok(Variant::Array(format!("{{{}{}}}", key, value), entries)),
+            _ => Err(self.error("unable to infer type of empty dictionary")),
+        }
+    }
+
+    fn tuple(&mut self, expected: Option<&str>) -> Result<Variant, String> {
+        self.expect('(')?;
+
+        let mut members = match expected {
+            Some(signature) if signature.starts_with('(') => Some(member_types(signature)?.into_iter()),
+            _ => None,
+        };
+        let mut fields = Vec::new();
+
+        while !self.eat(')') {
+            let member = members.as_mut().and_then(Iterator::next);
+            fields.push(self.value(member)?);
+
+            if !self.eat(',') {
+                self.expect(')')?;
+                break;
+            }
+        }
+
+        Ok(Variant::Tuple(fields))
+    }
+}
+
+fn print_value(value: &Variant, out: &mut String, annotate: bool) {
+    let annotated = |keyword: &str, out: &mut String| {
+        if annotate {
+            out.push_str(keyword);
+            out.push(' ');
+        }
+    };
+
+    match value {
+        Variant::Bool(boolean) => {
+            let _ = write!(out, "{}", boolean);
+        }
+        Variant::Byte(number) => {
+            annotated("byte", out);
+            let _ = write!(out, "0x{:02x}", number);
+        }
+        Variant::Int16(number) => {
+            annotated("int16", out);
+            let _ = write!(out, "{}", number);
+        }
+        Variant::Uint16(number) => {
+            annotated("uint16", out);
+            let _ = write!(out, "{}", number);
+        }
+        Variant::Int32(number) => {
+            let _ = write!(out, "{}", number);
+        }
+        Variant::Uint32(number) => {
+            annotated("uint32", out);
+            let _ = write!(out, "{}", number);
+        }
+        Variant::Int64(number) => {
+            annotated("int64", out);
+            let _ = write!(out, "{}", number);
+        }
+        Variant::Uint64(number) => {
+            annotated("uint64", out);
+            let _ = write!(out, "{}", number);
+        }
+        Variant::Handle(number) => {
+            annotated("handle", out);
+            let _ = write!(out, "{}", number);
+        }
+        // The words GLib prints and parses for the doubles that have no digits
+        Variant::Double(number) if number.is_nan() => out.push_str("nan"),
+        Variant::Double(number) if number.is_infinite() => out.push_str(if *number > 0.0 { "inf" } else { "-inf" }),
+        Variant::Double(number) => {
+            let _ = write!(out, "{:?}", number);
+        }
+        Variant::String(string) => print_string(string, out),
+        Variant::ObjectPath(path) => {
+            annotated("objectpath", out);
+            print_string(path, out);
+        }
+        Variant::Signature(signature) => {
+            annotated("signature", out);
+            print_string(signature, out);
+        }
+        Variant::Variant(child) => {
+            out.push('<');
+            print_value(child, out, true);
+            out.push('>');
+        }
+        Variant::Maybe(_, child) => {
+            if annotate {
+                let _ = write!(out, "@{} ", value.type_string());
+            }
+            match child {
+                Some(child) => {
+                    if matches!(**child, Variant::Maybe(..)) {
+                        out.push_str("just ");
+                    }
+                    print_value(child, out, false);
+                }
+                None => out.push_str("nothing"),
+            }
+        }
+        Variant::Array(element, elements) => {
+            if let Some(text) = bytestring(element, elements) {
+                print_bytestring(&text, out);
+                return;
+            }
+
+            let is_dict = element.starts_with('{');
+
+            if elements.is_empty() {
+                if annotate {
+                    let _ = write!(out, "@{} ", value.type_string());
+                }
+                out.push_str(if is_dict { "{}" } else { "[]" });
+                return;
+            }
+
+            out.push(if is_dict { '{' } else { '[' });
+            for (i, element) in elements.iter().enumerate() {
+                if i > 0 {
+                    out.push_str(", ");
+                }
+                match element {
+                    Variant::DictEntry(key, value) if is_dict => {
+                        print_value(key, out, annotate && i == 0);
+                        out.push_str(": ");
+                        print_value(value, out, annotate && i == 0);
+                    }
+                    _ => print_value(element, out, annotate && i == 0),
+                }
+            }
+            out.push(if is_dict { '}' } else { ']' });
+        }
+        Variant::Tuple(fields) => {
+            out.push('(');
+            for (i, field) in fields.iter().enumerate() {
+                if i > 0 {
+                    out.push_str(", ");
+                }
+                print_value(field, out, annotate);
+            }
+            if fields.len() == 1 {
+                out.push(',');
+            }
+            out.push(')');
+        }
+        Variant::DictEntry(key, value) => {
+            out.push('{');
+            print_value(key, out, annotate);
+            out.push_str(", ");
+            print_value(value, out, annotate);
+            out.push('}');
+        }
+    }
+}
+
+/// The text of an `ay` array that GLib prints as a bytestring: one that ends with its only nul byte
+fn bytestring(element: &str, elements: &[Variant]) -> Option<Vec<u8>> {
+    if element != "y" {
+        return None;
+    }
+
+    let bytes: Vec<u8> = elements
+        .iter()
+        .map(|element| {
+            match element {
+                Variant::Byte(byte) => Some(*byte),
+                _ => None,
+            }
+        })
+        .collect::<Option<_>>()?;
+
+    match bytes.split_last() {
+        Some((0, text)) if !text.contains(&0) => Some(text.to_vec()),
+        _ => None,
+    }
+}
+
+/// Prints a bytestring literal, escaping every byte that isn't printable ASCII
+fn print_bytestring(bytes: &[u8], out: &mut String) {
+    let quote = if bytes.contains(&b'\'') && !bytes.contains(&b'"') { '"' } else { '\'' };
+
+    out.push('b');
+    out.push(quote);
+    for &byte in bytes {
+        match byte {
+            b'\\' => out.push_str("\\\\"),
+            b'\n' => out.push_str("\\n"),
+            b'\t' => out.push_str("\\t"),
+            b'\r' => out.push_str("\\r"),
+            0x07 => out.push_str("\\a"),
+            0x08 => out.push_str("\\b"),
+            0x0c => out.push_str("\\f"),
+            0x0b => out.push_str("\\v"),
+            byte if byte == quote as u8 => {
+                out.push('\\');
+                out.push(quote);
+            }
+            0x20..=0x7e => out.push(byte as char),
+            byte => {
+                let _ = write!(out, "\\{:03o}", byte);
+            }
+        }
+    }
+    out.push(quote);
+}
+
+/// Prints a string literal, preferring single quotes like GLib does
+fn print_string(string: &str, out: &mut String) {
+    let quote = if string.contains('\'') && !string.contains('"') { '"' } else { '\'' };
+
+    out.push(quote);
+    for c in string.chars() {
+        match c {
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\t' => out.push_str("\\t"),
+            '\r' => out.push_str("\\r"),
+            '\x07' => out.push_str("\\a"),
+            '\x08' => out.push_str("\\b"),
+            '\x0c' => out.push_str("\\f"),
+            '\x0b' => out.push_str("\\v"),
+            c if c == quote => {
+                out.push('\\');
+                out.push(c);
+            }
+            c if c.is_control() => {
+                let _ = write!(out, "\\u{:04x}", c as u32);
+            }
+            c => out.push(c),
+        }
+    }
+    out.push(quote);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    /// Prints `value`, checks the text is `expected` and parses it back to `value`
+    fn round_trip(value: Variant, expected: &str) {
+        let text = print(&value);
+        assert_eq!(text, expected);
+        assert_eq!(parse(&text), Ok(value), "{}", text);
+    }
+
+    #[test]
+    fn prints_doubles_without_digits_as_glib_words() {
+        round_trip(Variant::Double(f64::NAN), "nan");
+        round_trip(Variant::Double(f64::INFINITY), "inf");
+        round_trip(Variant::Double(f64::NEG_INFINITY), "-inf");
+        round_trip(Variant::Double(1.0), "1.0");
+        round_trip(Variant::Array("d".into(), vec![Variant::Double(f64::NAN), Variant::Double(0.5)]), "[nan, 0.5]");
+        assert_eq!(parse_as("nan", "d"), Ok(Variant::Double(f64::NAN)));
+    }
+
+    #[test]
+    fn reads_and_writes_bytestrings() {
+        let bytes = |text: &[u8]| Variant::Array("y".into(), text.iter().copied().map(Variant::Byte).collect());
+
+        assert_eq!(parse("b'/usr/share'"), Ok(bytes(b"/usr/share\0")));
+        assert_eq!(parse("b\"it's\""), Ok(bytes(b"it's\0")));
+        assert_eq!(parse("b'\\101\\n\\\\\\''"), Ok(bytes(b"A\n\\'\0")));
+        assert_eq!(parse("b'caf\u{e9}'"), Ok(bytes("café\0".as_bytes())));
+        assert_eq!(parse_as("[b'a', b'']", "aay"), Ok(Variant::Array("ay".into(), vec![bytes(b"a\0"), bytes(b"\0")])));
+        assert!(parse("b'\\777'").is_err());
+        assert!(parse("b'open").is_err());
+
+        round_trip(bytes(b"/usr/share\0"), "b'/usr/share'");
+        round_trip(bytes(b"it's\0"), "b\"it's\"");
+        round_trip(bytes(b"'\"\\\0"), "b'\\'\"\\\\'");
+        round_trip(bytes(b"\x01\xff7\0"), "b'\\001\\3777'");
+        round_trip(bytes(b"\0"), "b''");
+        // Only a single trailing nul makes a bytestring
+        round_trip(bytes(b"a\0b\0"), "[byte 0x61, 0x00, 0x62, 0x00]");
+        round_trip(bytes(b"ab"), "[byte 0x61, 0x62]");
+        round_trip(bytes(b""), "@ay []");
+    }
+
+    #[test]
+    fn rejects_malformed_type_strings() {
+        for type_string in ["(", "{", "a{", "(i}", "{i)", "a{sss}", "{s}", "(é", "a", "mm", "z", ")"] {
+            assert!(member_types(type_string).is_err() || split_type(type_string).is_err(), "{}", type_string);
+            assert!(alignment(type_string).is_err() || fixed_size(type_string).is_err(), "{}", type_string);
+            assert!(decode(type_string, &[0; 8]).is_err(), "{}", type_string);
+            assert!(parse(&format!("@{} []", type_string)).is_err(), "{}", type_string);
+        }
+
+        assert_eq!(split_type("a{s(ii)}i"), Ok(("a{s(ii)}", "i")));
+        assert_eq!(member_types("(sa{sv}mi)"), Ok(vec!["s", "a{sv}", "mi"]));
+        assert_eq!(member_types("()"), Ok(vec![]));
+    }
+
+    #[test]
+    fn compares_values_by_content() {
+        assert_eq!(parse("'on'"), parse("\"on\""));
+        assert_eq!(parse("1.0"), parse("1.00"));
+        assert_ne!(parse("1"), parse("1.0"));
+        assert_ne!(parse("uint32 1"), parse("1"));
+        assert_ne!(Variant::Int32(1), Variant::Handle(1));
+        assert_ne!(Variant::String("/".into()), Variant::ObjectPath("/".into()));
+    }
+
+    #[test]
+    fn reads_glib_shorthands() {
+        let doubles = |numbers: &[f64]| Variant::Array("d".into(), numbers.iter().copied().map(Variant::Double).collect());
+
+        assert_eq!(parse("[1, 2.5]"), Ok(doubles(&[1.0, 2.5])));
+        assert_eq!(parse("[2.5, 1, -3]"), Ok(doubles(&[2.5, 1.0, -3.0])));
+        assert_eq!(parse("[1, 2]"), Ok(Variant::Array("i".into(), vec![Variant::Int32(1), Variant::Int32(2)])));
+        assert!(parse("[1, 'a']").is_err());
+        assert!(parse("[int32 1, 2.5]").is_err());
+
+        assert_eq!(parse("'\\x41\\x7e'"), Ok(Variant::String("A~".into())));
+        assert!(parse("'\\x4'").is_err());
+
+        assert_eq!(parse("(1)"), Ok(Variant::Tuple(vec![Variant::Int32(1)])));
+        round_trip(Variant::Tuple(vec![Variant::Int32(1)]), "(1,)");
+    }
+
+    /// Texts GLib accepts that are easy to get wrong, fed to the parser along with arbitrary text
+    const SEEDS: [&str; 8] = ["[1, 2.5]", "[.5, -1, inf]", "'\\x41'", "\"\\u00e9\\x\"", "(1)", "(1,)", "((1), [(2.5)])", "@a(d) [(1)]"];
+
+    /// Type strings of basic types, which can be dictionary keys
+    const BASIC_TYPES: &str = "bynqiuxthdsog";
+
+    /// Any valid type string, nested a few containers deep
+    fn any_type() -> impl Strategy<Value = String> {
+        let leaf = proptest::sample::select((BASIC_TYPES.to_string() + "v").chars().collect::<Vec<_>>()).prop_map(String::from);
+        let basic = proptest::sample::select(BASIC_TYPES.chars().collect::<Vec<_>>());
+
+        leaf.prop_recursive(4, 24, 4, move |inner| {
+            prop_oneof![
+                inner.clone().prop_map(|element| format!("a{}", element)),
+                inner.clone().prop_map(|element| format!("m{}", element)),
+                proptest::collection::vec(inner.clone(), 0..4).prop_map(|members| format!("({})", members.concat())),
+                (basic.clone(), inner).prop_map(|(key, value)| format!("a{{{}{}}}", key, value)),
+            ]
+        })
+    }
+
+    /// Any value of the type `type_string`
+    fn any_value(type_string: &str) -> BoxedStrategy<Variant> {
+        let (first, rest) = type_string.split_at(1);
+        match first {
+            "b" => any::<bool>().prop_map(Variant::Bool).boxed(),
+            "y" => any::<u8>().prop_map(Variant::Byte).boxed(),
+            "n" => any::<i16>().prop_map(Variant::Int16).boxed(),
+            "q" => any::<u16>().prop_map(Variant::Uint16).boxed(),
+            "i" => any::<i32>().prop_map(Variant::Int32).boxed(),
+            "u" => any::<u32>().prop_map(Variant::Uint32).boxed(),
+            "x" => any::<i64>().prop_map(Variant::Int64).boxed(),
+            "t" => any::<u64>().prop_map(Variant::Uint64).boxed(),
+            "h" => any::<i32>().prop_map(Variant::Handle).boxed(),
+            "d" => proptest::num::f64::ANY.prop_map(Variant::Double).boxed(),
+            "s" => "[^\u{0}]*".prop_map(Variant::String).boxed(),
+            "o" => "/|(/[A-Za-z0-9_]+)+".prop_map(Variant::ObjectPath).boxed(),
+            "g" => "[bynqiuxthdsogv]{0,8}".prop_map(Variant::Signature).boxed(),
+            // A variant holds a value of any basic type, to keep the nesting bounded
+            "v" => proptest::sample::select(BASIC_TYPES.chars().collect::<Vec<_>>())
+                .prop_flat_map(|child| any_value(&child.to_string()))
+                .prop_map(|child| Variant::Variant(Box::new(child)))
+                .boxed(),
+            "m" => {
+                let element = rest.to_string();
+                proptest::option::of(any_value(rest))
+                    .prop_map(move |child| Variant::Maybe(element.clone(), child.map(Box::new)))
+                    .boxed()
+            }
+            "a" => {
+                let element = rest.to_string();
+                proptest::collection::vec(any_value(rest), 0..4)
+                    .prop_map(move |elements| Variant::Array(element.clone(), elements))
+                    .boxed()
+            }
+            "(" => {
+                let members: Vec<_> = member_types(type_string).unwrap().into_iter().map(any_value).collect();
+                members.prop_map(Variant::Tuple).boxed()
+            }
+            _ => {
+                let members = member_types(type_string).unwrap();
+                (any_value(members[0]), any_value(members[1]))
+                    .prop_map(|(key, value)| Variant::DictEntry(Box::new(key), Box::new(value)))
+                    .boxed()
+            }
+        }
+    }
+
+    fn any_variant() -> impl Strategy<Value = Variant> {
+        any_type().prop_flat_map(|type_string| any_value(&type_string))
+    }
+
+    proptest! {
+        #[test]
+        fn printed_values_parse_back(value in any_variant()) {
+            let text = print(&value);
+            prop_assert_eq!(parse(&text), Ok(value), "{}", text);
+        }
+
+        #[test]
+        fn encoded_values_decode_back(value in any_variant()) {
+            let data = encode(&value);
+            prop_assert_eq!(decode(&value.type_string(), &data), Ok(value));
+        }
+
+        #[test]
+        fn never_panics_on_arbitrary_text(text in prop_oneof!["\\PC{0,32}", proptest::sample::select(&SEEDS[..]).prop_map(String::from)]) {
+            // Whatever is accepted prints as text that reads back the same
+            if let Ok(value) = parse(&text) {
+                let printed = print(&value);
+                prop_assert_eq!(parse(&printed), Ok(value), "{}", printed);
+            }
+        }
+
+        #[test]
+        fn never_panics_on_arbitrary_data(type_string in any_type(), data in proptest::collection::vec(any::<u8>(), 0..64)) {
+            let _ = decode(&type_string, &data);
+        }
+    }
+}
@@ -3,225 +3,128 @@
 //! It provides functions to save the current system configuration, load a previous system configuration,
 //! and enable incognito mode by setting specific keys and fields to desired values.
 //!
-//! The `save_current_system` function saves the current system configuration by using the `dconf dump` command
-//! and writes the output to a specified file. It can be run in silent mode to suppress console output.
+//! Every function talks to the desktop through a `SettingsBackend`, so the same logic can drive `dconf`
+//! on a live GNOME session or any other implementation of the trait.
+//!
+//! The `save_current_system` function saves the current system configuration, captured from the
+//! backend's database into a versioned `Snapshot`, to a specified file.
+//! The `snapshot_current_system` wrapper only does so while incognito is inactive, so the snapshot of the
+//! real desktop is never overwritten by a second enable. When the desktop still holds exactly the settings of
+//! the saved snapshot, that snapshot is used again rather than archived a second time.
 //!
 //! The `load_previous_system` function loads a previous system configuration from a specified file
-//! using the `dconf load` command. If the file does not exist, it displays an error message.
+//! back into the backend, accepting both snapshots and the raw `dconf dump` files written by older
+//! versions. Only the keys incognito wrote are restored unless a full restore is requested.
+//! If the file does not exist, it returns an `Error::NotFound` with a hint on how to get one.
 //!
-//! The `enable_incognito` function enables incognito mode by setting specific keys and fields to desired values.
-//! It takes parameters for the wallpaper, theme, icons, and a silent flag to suppress console output.
+//! The `enable_incognito` function enables incognito mode by writing every key of a `Profile`,
+//! committing all of them to the backend at once.
 //!
-//! The module also defines a `GSetting` struct to represent a key and field pair, and a `Settings` type
-//! which is a HashMap of `GSetting` keys to string values.
+//! None of these functions print anything themselves: progress, per-key outcomes and conflicts are emitted
+//! as `Event`s to the `Reporter` they're given, which decides how (and whether) to show them.
 //!
 //! Note: The code contains commented out code for the `backup_key_values` function, which is not currently implemented.
 //! It is intended to backup the current system configuration values to a file.
 
-use crate::utils::gsettings;
-use std::process::Command;
-use std::collections::HashMap;
-use colored::Colorize;
-use indicatif::{ ProgressBar, ProgressStyle };
+use crate::utils::apply::{ self, ApplyReport };
+use crate::utils::backend::SettingsBackend;
+use crate::utils::diff::{ self, DiffFormat };
+use crate::utils::error::{ Error, Result };
+use crate::utils::gvariant::Variant;
+use crate::utils::profile::{ self, Profile, DEFAULT_PROFILE };
+use crate::utils::record::Recording;
+use crate::utils::reporter::{ Event, Reporter, Silent };
+use crate::utils::schema::{ Schemas, Severity };
+use crate::utils::snapshot::{ self, Retention, Snapshot, Store };
+use crate::utils::state::{ self, State };
+use crate::utils::status::Status;
+use std::collections::BTreeMap;
 use std::fs;
-use std::fs::{ File, OpenOptions };
-use std::io;
-use std::io::prelude::*;
 use std::path::{ Path, PathBuf };
-use std::thread::sleep;
-use std::time::Duration;
 
-#[derive(Debug, PartialEq, Eq, Hash)]
-struct GSetting<'a> {
-    key: &'a str,
-    field: &'a str,
+/// GNOME Shell's list of enabled extension UUIDs
+const ENABLED_EXTENSIONS: &str = "/org/gnome/shell/enabled-extensions";
+
+/// Save the current system configuration to a file
+///
+/// The `Snapshot` captured from the backend's whole database is written to `config`, creating its
+/// directory if needed.
+pub fn save_current_system(snapshot: &Snapshot, config: &str) -> Result<()> {
+    let contents = snapshot.to_toml()?;
+
+    let path = Path::new(config);
+    if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        fs::create_dir_all(dir).map_err(|why| Error::io("create", dir, why))?;
+    }
+    fs::write(path, contents).map_err(|why| Error::io("write to", path, why))
 }
 
-type Settings<'a> = HashMap<GSetting<'a>, String>;
+/// Save the current system configuration, unless incognito is already active
+///
+/// Once incognito is enabled the desktop no longer reflects the user's real settings, so taking
+/// another snapshot would overwrite the one needed to restore them. The snapshot is only retaken
+/// when `force` is set.
+///
+/// A desktop that holds exactly the settings of the snapshot already in `config` gets no new one: that
+/// snapshot is still valid, and archiving a copy of it would only push older snapshots out of the store.
+///
+/// Older versions of the tool kept no state, only a legacy `.txt` dump next to `config`. When that dump
+/// exists without a state, incognito is taken to be active with it as the snapshot, since the desktop may
+/// well be disguised and the dump is then the only copy of the real settings.
+///
+/// Every new snapshot is also archived in `store`, which is then pruned to `retention`.
+///
+/// Returns the incognito state, which names the snapshot a restore will use.
+pub fn snapshot_current_system(
+    backend: &dyn SettingsBackend,
+    store: &Store,
+    reporter: &mut dyn Reporter,
+    config: &str,
+    force: bool,
+    retention: Retention
+) -> Result<State> {
+    let state_path = state::path_for(config);
+    let legacy = Path::new(config).with_extension("txt");
+
+    let state = match State::load(&state_path)? {
+        None if legacy.exists() => {
+            let snapshot = Snapshot::load(&legacy)?;
+            let state = State::activated(&snapshot, &legacy.to_string_lossy());
+            state.save(&state_path)?;
+            Some(state)
+        }
+        state => state,
+    };
 
-/// Save the current system configuration to a file
-pub fn save_current_system(silent: bool, config: String) {
-    let output = Command::new("dconf").arg("dump").arg("/").output().expect("Failed to dump data");
-
-    match check_file_exists(&config) {
-        true => {
-            if !silent {
-                if output.status.success() {
-                    //println!();
-                    //print!("   💾 {}  ", "Saving Current System Config".magenta().bold());
-
-                    let pb = ProgressBar::new_spinner();
-                    pb.enable_steady_tick(Duration::from_millis(120));
-                    pb.set_style(
-                        ProgressStyle::with_template("{msg} {spinner:.green} ")
-                            .unwrap()
-                            // For more spinners check out the cli-spinners project:
-                            // https://github.com/sindresorhus/cli-spinners/blob/master/spinners.json
-                            .tick_strings(&["⣾", "⣽", "⣻", "⢿", "⡿", "⣟", "⣯", "⣷"])
-                    );
-                    pb.set_message(
-                        format!(
-                            "\n           💾 {} ",
-                            "Saving Current System Config".magenta().bold()
-                        )
-                    );
-                    sleep(Duration::from_secs(3));
-
-                    let path = Path::new(&config);
-                    let display = path.display();
-
-                    // Open file in write-only mode
-                    let mut file = match File::create(&path) {
-                        Err(why) => panic!("Couldn't create {}: {}", display, why),
-                        Ok(file) => file,
-                    };
-
-                    // Write the `output` string to `file`
-                    match file.write_all(String::from_utf8_lossy(&output.stdout).as_bytes()) {
-                        Err(why) => panic!("Couldn't write to {}: {}", display, why),
-                        Ok(_) =>
-                            println!("\n\n           ✅ {}", "Successfully wrote config!".bold()),
-                    }
-                } else {
-                    println!("\n           ❗ {}", "Error dumping system config".red().bold());
-                }
-            } else {
-                //println!("Running in silent mode...");
-                let path = Path::new(&config);
-                let display = path.display();
-
-                // Open file in write-only mode
-                let mut file = match File::create(&path) {
-                    Err(why) =>
-                        panic!(
-                            "\n\n{}",
-                            format!(
-                                "🚨 {} {}{} {}",
-                                "Couldn't create".bold().red(),
-                                display,
-                                ":".bold().red(),
-                                why
-                            )
-                        ),
-                    Ok(file) => file,
-                };
-
-                // Write the `output` string to `file`
-                match file.write_all(String::from_utf8_lossy(&output.stdout).as_bytes()) {
-                    Err(why) =>
-                        panic!(
-                            "\n\n{}",
-                            format!(
-                                "🚨 {} {}{} {}",
-                                "Couldn't write to".bold().red(),
-                                display,
-                                ":".bold().red(),
-                                why
-                            )
-                        ),
-                    Ok(_) => (),
-                }
-            }
+    match state {
+        Some(state) if state.active && !force => {
+            reporter.emit(Event::SnapshotKept(&state));
+            Ok(state)
         }
-        false => {
-            let path = PathBuf::from(&config);
-            let dir = path.parent().unwrap();
-
-            match dir.exists() {
-                true => (),
-                false => {
-                    fs::create_dir_all(&dir).unwrap_or_else(|why|
-                        println!("\n           Failed to create! -> {:?}", why.kind())
-                    );
-                }
+        _ => {
+            reporter.emit(Event::Snapshotting);
+            let snapshot = Snapshot::capture(backend)?;
+
+            let saved = Snapshot::load(Path::new(config)).ok();
+            if let Some(saved) = saved.filter(|saved| saved.format > 0 && saved.entries == snapshot.entries) {
+                let state = State::activated(&saved, config);
+                state.save(&state_path)?;
+                reporter.emit(Event::SnapshotReused(&state));
+                return Ok(state);
             }
 
-            match path.exists() {
-                true => (),
-                false => {
-                    touch(&path).unwrap_or_else(|why| {
-                        println!("\n           ❗{:?}", why.kind());
-                    });
-                }
-            }
+            save_current_system(&snapshot, config)?;
+            let state = State::activated(&snapshot, config);
+            state.save(&state_path)?;
+            reporter.emit(Event::SnapshotSaved(&state));
 
-            if !silent {
-                if output.status.success() {
-                    //println!();
-                    //print!("   💾 {}  ", "Saving Current System Config".magenta().bold());
-
-                    let pb = ProgressBar::new_spinner();
-                    pb.enable_steady_tick(Duration::from_millis(120));
-                    pb.set_style(
-                        ProgressStyle::with_template("{msg} {spinner:.green} ")
-                            .unwrap()
-                            // For more spinners check out the cli-spinners project:
-                            // https://github.com/sindresorhus/cli-spinners/blob/master/spinners.json
-                            .tick_strings(&["⣾", "⣽", "⣻", "⢿", "⡿", "⣟", "⣯", "⣷"])
-                    );
-                    pb.set_message(
-                        format!(
-                            "\n           💾 {} ",
-                            "Saving Current System Config".magenta().bold()
-                        )
-                    );
-                    sleep(Duration::from_secs(3));
-
-                    let path = Path::new(&config);
-                    let display = path.display();
-
-                    // Open file in write-only mode
-                    let mut file = match File::create(&path) {
-                        Err(why) => panic!("\nCouldn't create {}: {}", display, why),
-                        Ok(file) => file,
-                    };
-
-                    // Write the `output` string to `file`
-                    match file.write_all(String::from_utf8_lossy(&output.stdout).as_bytes()) {
-                        Err(why) => panic!("\nCouldn't write to {}: {}", display, why),
-                        Ok(_) =>
-                            println!("\n\n           ✅ {}", "Successfully wrote config!".bold()),
-                    }
-                } else {
-                    println!("\n\n           ❗ {}", "Error dumping system config".red().bold());
-                }
-            } else {
-                //println!("Running in silent mode...");
-                let path = Path::new(&config);
-                let display = path.display();
-
-                // Open file in write-only mode
-                let mut file = match File::create(&path) {
-                    Err(why) =>
-                        panic!(
-                            "\n\n{}",
-                            format!(
-                                "🚨 {} {}{} {}",
-                                "Couldn't create".bold().red(),
-                                display,
-                                ":".bold().red(),
-                                why
-                            )
-                        ),
-                    Ok(file) => file,
-                };
-
-                // Write the `output` string to `file`
-                match file.write_all(String::from_utf8_lossy(&output.stdout).as_bytes()) {
-                    Err(why) =>
-                        panic!(
-                            "\n\n{}",
-                            format!(
-                                "🚨 {} {}{} {}",
-                                "Couldn't write to".bold().red(),
-                                display,
-                                ":".bold().red(),
-                                why
-                            )
-                        ),
-                    Ok(_) => (),
-                }
+            // The config file alone is enough to restore, so a failing archive only warrants a warning
+            let archived = store.save(&snapshot).and_then(|_| store.prune(retention));
+            if let Err(why) = archived {
+                reporter.emit(Event::Warning { message: "Couldn't archive snapshot:", detail: why.to_string() });
             }
+
+            Ok(state)
         }
     }
 }
@@ -269,139 +172,643 @@ pub fn save_current_system(silent: bool, config: String) {
 // }
 
 /// Load a previous system configuration from a file
-pub fn load_previous_system(file: String) {
-    match check_file_exists(&file) {
-        true => {
-            let output = Command::new("sh")
-                .arg("-c")
-                .arg(format!("dconf load / < {}", &file))
-                .output()
-                .expect("Failed to execute command");
-
-            //print!("🛠️ {}  ", "Loading Previous Config".magenta().bold());
-            let pb = ProgressBar::new_spinner();
-            pb.enable_steady_tick(Duration::from_millis(120));
-            pb.set_style(
-                ProgressStyle::with_template("{msg} {spinner:.green} ")
-                    .unwrap()
-                    // For more spinners check out the cli-spinners project:
-                    // https://github.com/sindresorhus/cli-spinners/blob/master/spinners.json
-                    .tick_strings(&["⣾", "⣽", "⣻", "⢿", "⡿", "⣟", "⣯", "⣷"])
-            );
-            pb.set_message(
-                format!("\n            🛠️ {} ", "Loading Previous Config".cyan().bold())
-            );
-            sleep(Duration::from_secs(3));
-
-            if output.status.success() {
-                print!("\n\n            ✅ {}\n\n", "DONE! ".bold());
-            } else {
-                println!(
-                    "\n            ❗{}\n\n",
-                    "Error loading previous configuration file".red().bold()
-                )
-            }
-        }
+///
+/// Only the keys incognito wrote are put back to their snapshot values (or reset, if they were unset
+/// when the snapshot was taken), so settings the user changed in the meantime are left alone.
+/// With `full`, every key of the snapshot is put back instead, as earlier versions did, and managed keys
+/// the snapshot doesn't hold are reset.
+///
+/// A managed key that no longer holds the value incognito wrote was changed by the user while incognito
+/// was active. Those conflicts are reported and resolved according to `policy`.
+///
+/// With `snapshot_id`, the archived snapshot with that id is restored instead of the one in `file`.
+/// With `per_key`, the keys are written one at a time if the backend rejects them as a whole.
+///
+/// ## Returns:
+///
+/// The outcome of every key put back, or an `Error` if the snapshot or the incognito state doesn't exist
+/// or can't be read, or the state couldn't be updated afterwards.
+pub fn load_previous_system(
+    backend: &dyn SettingsBackend,
+    file: &str,
+    snapshot_id: Option<&str>,
+    full: bool,
+    policy: ConflictPolicy,
+    per_key: bool,
+    reporter: &mut dyn Reporter
+) -> Result<ApplyReport> {
+    let path = locate_snapshot(file, snapshot_id).ok_or_else(|| not_found(file, snapshot_id))?;
+    let snapshot = Snapshot::load(&path)?;
+    let state_path = state::path_for(file);
+    let state = State::load(&state_path)?;
+
+    let changes = match full {
+        true => full_changes(&snapshot, state.as_ref()),
+        false => restore_changes(backend, &snapshot, state.as_ref(), policy, reporter)?,
+    };
+    reporter.emit(Event::Restoring { snapshot: &snapshot, path: &path, full });
+    let report = apply::apply(backend, &changes, per_key, reporter);
+
+    // Keys that couldn't be put back stay managed, so the next restore tries them again. Once none are
+    // left the desktop is back to its real settings, and the next enable may snapshot again. A snapshot
+    // restored without any state (a legacy dump) gets one, so it isn't taken for an active incognito later.
+    let mut state = state.unwrap_or_else(|| State::activated(&snapshot, &path.to_string_lossy()));
+    state.applied.retain(|key, _| report.keys.get(key).is_some_and(|outcome| !outcome.is_ok()));
+    state.active = !state.applied.is_empty();
+    state.save(&state_path)?;
+
+    if report.is_complete() {
+        reporter.emit(Event::Restored);
+    }
+    Ok(report)
+}
+
+/// **The changes a full restore makes**: every key of `snapshot`, and a reset of every key incognito wrote
+/// that the snapshot doesn't hold, since it was unset before incognito was enabled
+fn full_changes(snapshot: &Snapshot, state: Option<&State>) -> BTreeMap<String, Option<Variant>> {
+    let unset = state
+        .into_iter()
+        .flat_map(|state| state.applied.keys())
+        .filter(|key| !snapshot.entries.contains_key(*key))
+        .map(|key| (key.clone(), None));
+
+    snapshot.entries
+        .iter()
+        .map(|(key, value)| (key.clone(), Some(value.clone())))
+        .chain(unset)
+        .collect()
+}
+
+/// The error for a snapshot `locate_snapshot` couldn't find, with a hint on how to get one
+fn not_found(file: &str, snapshot_id: Option<&str>) -> Error {
+    match snapshot_id {
+        Some(id) =>
+            Error::NotFound(
+                format!("Snapshot {} not found: run `nix-incognito snapshots list` to see the saved ones", id)
+            ),
+        None => Error::NotFound(format!("Config file {} not found: enable incognito to generate it", file)),
+    }
+}
+
+/// Preview what `load_previous_system` would change, without writing anything
+///
+/// Conflicts are resolved as `policy` says, except that a dry run never prompts and keeps the current
+/// value instead.
+pub fn preview_restore(
+    backend: &dyn SettingsBackend,
+    file: &str,
+    snapshot_id: Option<&str>,
+    full: bool,
+    policy: ConflictPolicy,
+    format: DiffFormat,
+    reporter: &mut dyn Reporter
+) -> Result<()> {
+    let path = locate_snapshot(file, snapshot_id).ok_or_else(|| not_found(file, snapshot_id))?;
+    let snapshot = Snapshot::load(&path)?;
+
+    let changes = match full {
+        true => full_changes(&snapshot, State::load(&state::path_for(file))?.as_ref()),
         false => {
-            println!(
-                "\n\n            👀 {}{}\n\n",
-                "Config file not found: ".red().bold(),
-                "Enable Incognito to generate it!".bold()
-            );
+            let policy = match policy {
+                ConflictPolicy::Prompt => ConflictPolicy::KeepCurrent,
+                policy => policy,
+            };
+            // Conflicts would end up in the middle of the raw JSON diff
+            let mut quiet = Silent;
+            let conflicts: &mut dyn Reporter = match format {
+                DiffFormat::Json => &mut quiet,
+                DiffFormat::Human => reporter,
+            };
+            let state = State::load(&state::path_for(file))?;
+            restore_changes(backend, &snapshot, state.as_ref(), policy, conflicts)?
         }
+    };
+    reporter.emit(Event::Restoring { snapshot: &snapshot, path: &path, full });
+
+    let diff = diff::compare(backend, &changes)?;
+    reporter.emit(Event::Diff { title: "Restore previous system", diff: &diff, format });
+    Ok(())
+}
+
+/// Preview what `enable_incognito` would change, without writing anything
+pub fn preview_enable(
+    backend: &dyn SettingsBackend,
+    profile: &Profile,
+    format: DiffFormat,
+    reporter: &mut dyn Reporter
+) -> Result<()> {
+    let changes = profile
+        .changes()?
+        .into_iter()
+        .map(|(key, value)| (key, Some(value)))
+        .collect();
+
+    let diff = diff::compare(backend, &changes)?;
+    reporter.emit(Event::Diff { title: "Enable incognito", diff: &diff, format });
+    Ok(())
+}
+
+/// Report whether the desktop is disguised, comparing its live settings with the managed keys
+///
+/// With `short`, only the disguise state is reported, for use in shell prompts.
+pub fn report_status(backend: &dyn SettingsBackend, config: &str, short: bool, reporter: &mut dyn Reporter) -> Result<()> {
+    let status = Status::check(backend, config)?;
+    reporter.emit(Event::Status { status: &status, short });
+    Ok(())
+}
+
+/// List the profiles incognito can apply
+pub fn list_profiles(reporter: &mut dyn Reporter) {
+    reporter.emit(Event::Profiles(&profile::available()));
+}
+
+/// Report the assets, required extensions and every key and value a profile writes
+pub fn show_profile(profile: &Profile, reporter: &mut dyn Reporter) -> Result<()> {
+    let changes = profile.changes()?;
+    reporter.emit(Event::Profile { profile, changes: &changes });
+    Ok(())
+}
+
+/// Report the merged settings of a layered profile, with the layer each value comes from
+pub fn resolve_profile(profile: &Profile, reporter: &mut dyn Reporter) -> Result<()> {
+    let changes = profile.changes()?;
+    reporter.emit(Event::Layers { profile, changes: &changes });
+    Ok(())
+}
+
+/// Start recording a profile, snapshotting the desktop the user is about to tweak
+pub fn start_recording(backend: &dyn SettingsBackend, name: &str, force: bool, reporter: &mut dyn Reporter) -> Result<()> {
+    let recording = Recording::start(backend, name, force)?;
+    reporter.emit(Event::RecordingStarted { name: &recording.name, keys: recording.started.entries.len() });
+    Ok(())
+}
+
+/// Finish the recording in progress, writing every setting that changed since it started to a profile file
+pub fn finish_recording(
+    backend: &dyn SettingsBackend,
+    output: Option<PathBuf>,
+    force: bool,
+    reporter: &mut dyn Reporter
+) -> Result<()> {
+    let recording = Recording::load()?.ok_or_else(|| {
+        Error::NotFound("No recording in progress, start one with `nix-incognito profile record <name>`".into())
+    })?;
+    let recorded = recording.finish(backend)?;
+    let path = match output {
+        Some(path) => path,
+        None =>
+            profile
+                ::user_path(&recording.name)
+                .ok_or(Error::NotFound("Unable to locate the user config directory".into()))?,
+    };
+
+    if path.exists() && !force {
+        return Err(
+            Error::Other(
+                format!("{} already exists, pass --force to overwrite it or --output to write elsewhere", path.display())
+            )
+        );
+    }
+    if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        fs::create_dir_all(dir).map_err(|why| Error::io("create", dir, why))?;
     }
+
+    let contents = format!(
+        "# Recorded with `nix-incognito profile record {}`.\n# Apply it with `nix-incognito enable --from {}`.\n\n{}",
+        recording.name,
+        path.display(),
+        recorded.profile.to_toml()?
+    );
+    fs::write(&path, contents).map_err(|why| Error::io("write to", &path, why))?;
+    Recording::discard()?;
+
+    reporter.emit(Event::Recorded { name: &recording.name, recorded: &recorded, path: &path });
+    Ok(())
+}
+
+/// Stop the recording in progress without writing a profile
+pub fn cancel_recording(reporter: &mut dyn Reporter) -> Result<()> {
+    let recording = Recording::load()?.ok_or(Error::NotFound("No recording in progress".into()))?;
+    Recording::discard()?;
+
+    reporter.emit(Event::RecordingCancelled { name: &recording.name });
+    Ok(())
 }
 
-/// Enable incognito mode by setting specific keys and fields to desired values
+/// List the archived snapshots, marking the one incognito would currently restore
+pub fn list_snapshots(config: &str, reporter: &mut dyn Reporter) -> Result<()> {
+    let (snapshots, skipped) = Store::open()?.list()?;
+    for why in skipped {
+        reporter.emit(Event::Warning { message: "Skipped an unreadable snapshot:", detail: why.to_string() });
+    }
+    let active = match State::load(&state::path_for(config))? {
+        Some(state) if state.active => Some(state.snapshot_id),
+        _ => None,
+    };
+
+    reporter.emit(Event::Snapshots { snapshots: &snapshots, active: active.as_deref() });
+    Ok(())
+}
+
+/// Report the metadata and every entry of the archived snapshot with the given `id`
+pub fn show_snapshot(id: &str, reporter: &mut dyn Reporter) -> Result<()> {
+    let snapshot = Store::open()?.get(id)?;
+    reporter.emit(Event::Snapshot(&snapshot));
+    Ok(())
+}
+
+/// Enable incognito mode by writing every key of a profile
 /// ## Args:
-///     * wallpaper: String - The path to the wallpaper image file
-///     * theme: String - The name of the theme to use
-///     * icons: String - The name of the icon theme to use
-///     * silent: bool - Flag to suppress console output
+///     * backend: &dyn SettingsBackend - The settings store to apply the keys to
+///     * config: &str - The path of the config snapshot, used to record which keys were written
+///     * profile: &Profile - The look to apply, with any command line assets already filled in
+///     * per_key: bool - Whether to write the keys one at a time if the backend rejects them as a whole
+///     * reporter: &mut dyn Reporter - Where the progress and the outcome of every key are reported
 /// ## Example:
 ///    ```
-///     enable_incognito("/path/to/wallpaper.jpg", "Fluent-Round-Dark", "Windows-Eleven", false);
+///     let profile = Profile::resolve("win11")?;
+///     enable_incognito(&CommandBackend, "/path/to/config.toml", &profile, false, &mut Silent);
 ///    ```
 /// ## Note:
-///   This function sets the following keys and fields in incognito mode:
-///     - `org.gnome.desktop.background`: `picture-uri`, `picture-uri-dark`, `picture-options`
-///     - `org.gnome.shell.extensions.user-theme`: `name`
-///     - `org.gnome.shell.extensions.dash-to-dock`: `extend-height`
-///     - `org.gnome.desktop.interface`: `icon-theme`, `gtk-theme`
-///     - `org.gnome.desktop.wm.preferences`: `theme`
-pub fn enable_incognito(wallpaper: String, theme: String, icons: String, silent: bool) {
-    //! Define the keys and fields to be set in incognito mode
-    //! and set them to the desired values
-    let gsetting_value_map: Settings = HashMap::from([
-        (
-            GSetting { key: "org.gnome.desktop.background", field: "picture-uri" },
-            String::from(format!("file://{}", &wallpaper)),
-        ),
-        (
-            GSetting { key: "org.gnome.desktop.background", field: "picture-uri-dark" },
-            String::from(format!("file://{}", &wallpaper)),
-        ),
-        (
-            GSetting { key: "org.gnome.desktop.background", field: "picture-options" },
-            String::from("stretched"),
-        ),
-        (
-            GSetting { key: "org.gnome.shell.extensions.user-theme", field: "name" },
-            String::from(format!("{}", &theme)),
-        ),
-        (
-            GSetting { key: "org.gnome.desktop.interface", field: "icon-theme" },
-            String::from(format!("{}", &icons)),
-        ),
-        (
-            GSetting { key: "org.gnome.desktop.interface", field: "gtk-theme" },
-            String::from(format!("{}", &theme)),
-        ),
-        (
-            GSetting { key: "org.gnome.desktop.wm.preferences", field: "theme" },
-            String::from(format!("{}", &theme)),
-        ),
-    ]);
-
-    if !silent {
-        println!("           🥷 {}  ", "Engaging Nix Incognito...".bold());
-        println!();
-
-        // Set the given key and field to the provided value
-        for (map, value) in gsetting_value_map.iter() {
-            match gsettings::set(map.key, map.field, value) {
-                Ok(s) => println!("           ✅ {}", s.bold().cyan()),
-                Err(e) => println!("           🚨 {}", e.bold().red()),
-            };
+///   Keys that already hold their value are left alone, and the rest are applied as a single
+///   transaction. If the backend rejects it nothing is written, unless `per_key` is set: every key is
+///   then written on its own instead and the ones that fail are reported. The keys holding the
+///   profile's values are recorded in the incognito state so that a restore only touches those.
+///
+///   GNOME Shell extensions the profile needs are not enabled automatically, and its theme and icons
+///   are not installed; a warning lists any that are missing.
+///
+/// ## Returns:
+///
+/// The outcome of every key of the profile, which `ApplyReport::into_result` turns into an
+/// `Error::Apply` if some of them couldn't be written, or an `Error` if the profile is invalid.
+pub fn enable_incognito(
+    backend: &dyn SettingsBackend,
+    config: &str,
+    profile: &Profile,
+    per_key: bool,
+    reporter: &mut dyn Reporter
+) -> Result<ApplyReport> {
+    let changes = profile.changes()?;
+
+    reporter.emit(Event::Applying { profile: &profile.name });
+    for extension in missing_extensions(backend, profile) {
+        reporter.emit(Event::MissingExtension(&extension));
+    }
+    for (kind, name) in profile.assets.missing() {
+        reporter.emit(Event::MissingAsset { kind, name });
+    }
+
+    let changeset = changes
+        .iter()
+        .map(|(key, value)| (key.clone(), Some(value.clone())))
+        .collect();
+    let report = apply::apply(backend, &changeset, per_key, reporter);
+
+    let written: BTreeMap<String, Variant> = report
+        .succeeded()
+        .filter_map(|key| changes.get_key_value(key))
+        .map(|(key, value)| (key.clone(), value.clone()))
+        .collect();
+    if let Err(why) = record_applied(config, &profile.name, &written) {
+        reporter.emit(Event::Warning { message: "Restore will revert every managed key:", detail: why.to_string() });
+    }
+
+    Ok(report)
+}
+
+/// **Checks a profile against the installed GSettings schemas before anything is written**
+///
+/// Keys no installed schema describes are only warned about, since their values can't be checked. Keys
+/// that don't exist, values of the wrong type and values outside of a key's choices or range are errors.
+///
+/// ## Returns:
+///
+/// `Ok` if the profile can be applied, or an `Error::InvalidProfile` if any of its values is invalid.
+pub fn validate_profile(profile: &Profile, reporter: &mut dyn Reporter) -> Result<()> {
+    let schemas = Schemas::load();
+    if schemas.is_empty() {
+        return Ok(());
+    }
+
+    let issues = schemas.validate(profile)?;
+    for issue in &issues {
+        reporter.emit(Event::Issue(issue));
+    }
+
+    match issues.iter().filter(|issue| issue.severity == Severity::Error).count() {
+        0 => Ok(()),
+        errors =>
+            Err(
+                Error::InvalidProfile(
+                    format!(
+                        "Profile {} has {} invalid value{} for the installed schemas, no settings were changed",
+                        profile.name,
+                        errors,
+                        if errors == 1 { "" } else { "s" }
+                    )
+                )
+            ),
+    }
+}
+
+/// Extensions the profile relies on that aren't in GNOME Shell's `enabled-extensions` list
+fn missing_extensions(backend: &dyn SettingsBackend, profile: &Profile) -> Vec<String> {
+    let enabled = backend
+        .read(ENABLED_EXTENSIONS)
+        .ok()
+        .flatten();
+    let enabled: Vec<String> = match enabled {
+        Some(Variant::Array(_, items)) =>
+            items
+                .into_iter()
+                .filter_map(|item| {
+                    match item {
+                        Variant::String(uuid) => Some(uuid),
+                        _ => None,
+                    }
+                })
+                .collect(),
+        _ => Vec::new(),
+    };
+
+    profile.extensions
+        .iter()
+        .filter(|uuid| !enabled.contains(uuid))
+        .cloned()
+        .collect()
+}
+
+/// Adds the keys written by `enable_incognito` to the incognito state of `config`
+fn record_applied(config: &str, profile: &str, changes: &BTreeMap<String, Variant>) -> Result<()> {
+    let state_path = state::path_for(config);
+
+    match State::load(&state_path)? {
+        Some(mut state) => {
+            state.profile = profile.to_string();
+            state.applied.extend(changes.iter().map(|(key, value)| (key.clone(), value.clone())));
+            state.save(&state_path)
         }
+        None => Ok(()),
+    }
+}
 
-        match
-            gsettings::set_dconf("/org/gnome/shell/extensions/dash-to-dock/extend-height", "true")
-        {
-            Ok(s) => println!("           ✅ {}", s.bold().cyan()),
-            Err(e) => println!("           🚨 {}", e.bold().red()),
-        };
-    } else {
-        // Run without printing result to stdout
-        for (map, value) in gsetting_value_map.iter() {
-            let _res = gsettings::set(map.key, map.field, value);
+/// Path of the snapshot to restore: the archived one with `snapshot_id`, or the one in `file`
+pub fn locate_snapshot(file: &str, snapshot_id: Option<&str>) -> Option<PathBuf> {
+    match snapshot_id {
+        Some(id) =>
+            Store::open()
+                .and_then(|store| store.path(id))
+                .ok()
+                .filter(|path| path.exists()),
+        None => snapshot::locate(file),
+    }
+}
+
+/// **Picks the snapshot `toggle` restores**
+///
+/// The config file is used while it holds the snapshot the incognito state names. If it was replaced or
+/// removed since, the archived copy of that snapshot is used instead; a config file holding another
+/// snapshot is only used when that copy is gone too, with a warning.
+///
+/// ## Returns:
+///
+/// The id of the archived snapshot to restore, `None` to restore the config file, or an
+/// `Error::NotFound` if there is nothing to restore from.
+pub fn pick_snapshot(config: &str, state: Option<&State>, reporter: &mut dyn Reporter) -> Result<Option<String>> {
+    let current = snapshot::locate(config).and_then(|path| Snapshot::load(&path).ok());
+    let Some(state) = state else {
+        return current.map(|_| None).ok_or_else(|| not_found(config, None));
+    };
+
+    match current {
+        Some(snapshot) if snapshot.id == state.snapshot_id => Ok(None),
+        _ if locate_snapshot(config, Some(&state.snapshot_id)).is_some() => Ok(Some(state.snapshot_id.clone())),
+        Some(snapshot) => {
+            reporter.emit(Event::Warning {
+                message: "Restoring a snapshot incognito wasn't enabled from:",
+                detail: format!("{} instead of {}", snapshot.id, state.snapshot_id),
+            });
+            Ok(None)
         }
-        let _res = gsettings::set_dconf(
-            "org/gnome/shell/extensions/dash-to-dock/extend-height",
-            "true"
-        );
+        None =>
+            Err(
+                Error::NotFound(
+                    format!(
+                        "Nothing to restore: {} is missing and snapshot {} isn't archived; {}",
+                        config,
+                        state.snapshot_id,
+                        "run `nix-incognito snapshots list` and restore one with `--snapshot`"
+                    )
+                )
+            ),
+    }
+}
+
+/// **Builds the changeset of a selective restore**
+///
+/// Every managed key is mapped to its snapshot value (`None` to reset it), except for conflicting keys
+/// that `policy` decided to keep.
+fn restore_changes(
+    backend: &dyn SettingsBackend,
+    snapshot: &Snapshot,
+    state: Option<&State>,
+    policy: ConflictPolicy,
+    reporter: &mut dyn Reporter
+) -> Result<BTreeMap<String, Option<Variant>>> {
+    let mut changes = BTreeMap::new();
+
+    for (key, applied) in managed_keys(state) {
+        let value = snapshot.entries.get(&key).cloned();
+        if let Some(applied) = applied {
+            let conflict = Conflict {
+                current: backend.read(&key)?,
+                key,
+                snapshot: value,
+                applied,
+            };
+            if conflict.exists() && !conflict.resolve(policy, reporter) {
+                continue;
+            }
+            changes.insert(conflict.key, conflict.snapshot);
+        } else {
+            changes.insert(key, value);
+        }
+    }
+
+    Ok(changes)
+}
+
+/// Keys a selective restore puts back, mapped to the value incognito wrote to them
+///
+/// These are the keys recorded in the incognito `state`, or every key incognito can write (with unknown
+/// values) if there is no state or it predates that record.
+fn managed_keys(state: Option<&State>) -> BTreeMap<String, Option<Variant>> {
+    match state {
+        Some(state) if !state.applied.is_empty() =>
+            state.applied
+                .iter()
+                .map(|(key, value)| (key.clone(), Some(value.clone())))
+                .collect(),
+        _ =>
+            Profile::builtin(DEFAULT_PROFILE)
+                .and_then(|profile| profile.changes().ok())
+                .unwrap_or_default()
+                .into_keys()
+                .map(|key| (key, None))
+                .collect(),
     }
 }
 
-/// Check if the file exists
-fn check_file_exists(file: &str) -> bool {
-    Path::new(file).exists()
+/// **How restore treats managed keys the user changed while incognito was active**
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ConflictPolicy {
+    /// Keep the value the user set
+    KeepCurrent,
+    /// Overwrite it with the value from the snapshot
+    TakeSnapshot,
+    /// Ask for every conflicting key, keeping the current value when there is no terminal to ask on
+    Prompt,
+}
+
+/// The three values of a managed key at restore time
+#[derive(Debug)]
+pub struct Conflict {
+    pub key: String,
+    /// Value before incognito was enabled, `None` if the key was unset
+    pub snapshot: Option<Variant>,
+    /// Value incognito wrote
+    pub applied: Variant,
+    /// Value right now, `None` if the key is unset
+    pub current: Option<Variant>,
 }
 
-/// Create a file if it doesn't exist
-fn touch(path: &Path) -> io::Result<()> {
-    match OpenOptions::new().create(true).write(true).open(path) {
-        Ok(_) => Ok(()),
-        Err(e) => Err(e),
+impl Conflict {
+    /// The key was changed after incognito wrote it, to something other than the snapshot value
+    fn exists(&self) -> bool {
+        self.current.as_ref() != Some(&self.applied) && self.current != self.snapshot
+    }
+
+    /// **Decides whether the snapshot value should replace the current one**
+    ///
+    /// ## Returns:
+    ///
+    /// `true` to take the snapshot value, `false` to keep the current value.
+    fn resolve(&self, policy: ConflictPolicy, reporter: &mut dyn Reporter) -> bool {
+        reporter.emit(Event::Conflict(self));
+
+        let take_snapshot = match policy {
+            ConflictPolicy::KeepCurrent => false,
+            ConflictPolicy::TakeSnapshot => true,
+            ConflictPolicy::Prompt => reporter.ask(self).unwrap_or(false),
+        };
+
+        reporter.emit(Event::Resolved { key: &self.key, take_snapshot });
+        take_snapshot
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::backend::fixtures::{ self, string, ICONS, THEME };
+    use crate::utils::backend::MemoryBackend;
+
+    /// The stock desktop, and the path of a config snapshot in a scratch directory
+    fn desktop() -> (tempfile::TempDir, String, MemoryBackend) {
+        let dir = tempfile::tempdir().unwrap();
+        let config = dir.path().join("current_system_config.toml").to_string_lossy().into_owned();
+        (dir, config, fixtures::desktop())
+    }
+
+    #[test]
+    fn restore_stops_at_an_unreadable_state() {
+        let (_dir, config, backend) = desktop();
+        save_current_system(&Snapshot::capture(&backend).unwrap(), &config).unwrap();
+        backend.write(THEME, &string("Fluent-Round-Dark")).unwrap();
+        fs::write(state::path_for(&config), "active = [").unwrap();
+
+        let restored = load_previous_system(&backend, &config, None, false, ConflictPolicy::TakeSnapshot, false, &mut Silent);
+        assert!(matches!(restored, Err(Error::Corrupt { .. })));
+        assert_eq!(backend.read(THEME).unwrap(), Some(string("Fluent-Round-Dark")));
+
+        let previewed = preview_restore(&backend, &config, None, false, ConflictPolicy::TakeSnapshot, DiffFormat::Json, &mut Silent);
+        assert!(matches!(previewed, Err(Error::Corrupt { .. })));
+    }
+
+    #[test]
+    fn a_full_restore_resets_the_managed_keys_the_snapshot_lacks() {
+        let (_dir, config, backend) = desktop();
+        backend.reset(ICONS).unwrap();
+        let snapshot = Snapshot::capture(&backend).unwrap();
+        save_current_system(&snapshot, &config).unwrap();
+        let mut state = State::activated(&snapshot, &config);
+        state.applied = BTreeMap::from([
+            (THEME.to_string(), string("Fluent")),
+            (ICONS.to_string(), string("Windows-Eleven")),
+        ]);
+        state.save(&state::path_for(&config)).unwrap();
+        let disguised: BTreeMap<String, Option<Variant>> = state.applied
+            .iter()
+            .map(|(key, value)| (key.clone(), Some(value.clone())))
+            .collect();
+
+        backend.apply(&disguised).unwrap();
+
+        // Nothing is written when the changeset is rejected, so every key stays managed
+        let rejecting = MemoryBackend { values: backend.values.clone(), ..Default::default() }.rejecting(ICONS);
+        let report = load_previous_system(&rejecting, &config, None, true, ConflictPolicy::TakeSnapshot, false, &mut Silent)
+            .unwrap();
+        assert!(!report.keys[THEME].is_ok());
+        assert_eq!(State::load(&state::path_for(&config)).unwrap().unwrap().applied.len(), 2);
+
+        let report = load_previous_system(&backend, &config, None, true, ConflictPolicy::TakeSnapshot, false, &mut Silent)
+            .unwrap();
+        assert!(report.is_complete());
+        assert_eq!(backend.read(THEME).unwrap(), Some(string("Yaru")));
+        assert_eq!(backend.read(ICONS).unwrap(), None);
+        assert!(!State::load(&state::path_for(&config)).unwrap().unwrap().active);
+    }
+
+    #[test]
+    fn a_legacy_dump_without_state_is_the_real_desktop() {
+        let (dir, config, backend) = desktop();
+        let legacy = Path::new(&config).with_extension("txt");
+        fs::write(&legacy, "[org/gnome/desktop/interface]\ngtk-theme='Adwaita'\n").unwrap();
+
+        // Enabled by an older version: the desktop is disguised, and the dump holds the real settings
+        let retention = Retention { keep: 10, max_age_days: None };
+        let store = Store::at(dir.path().join("snapshots"));
+        let kept = snapshot_current_system(&backend, &store, &mut Silent, &config, false, retention).unwrap();
+        assert_eq!(kept.snapshot, legacy.to_string_lossy());
+        assert!(kept.active);
+        assert!(!Path::new(&config).exists());
+
+        load_previous_system(&backend, &config, None, false, ConflictPolicy::TakeSnapshot, false, &mut Silent).unwrap();
+        assert_eq!(backend.read(THEME).unwrap(), Some(string("Adwaita")));
+        assert!(!State::load(&state::path_for(&config)).unwrap().unwrap().active);
+    }
+
+    #[test]
+    fn an_unchanged_desktop_keeps_its_snapshot() {
+        let (dir, config, backend) = desktop();
+        let store = Store::at(dir.path().join("snapshots"));
+        let retention = Retention { keep: 10, max_age_days: None };
+        let archived = || store.list().unwrap().0.len();
+
+        let taken = snapshot_current_system(&backend, &store, &mut Silent, &config, true, retention).unwrap();
+        assert_eq!(archived(), 1);
+
+        let kept = snapshot_current_system(&backend, &store, &mut Silent, &config, true, retention).unwrap();
+        assert_eq!(kept.snapshot_id, taken.snapshot_id);
+        assert_eq!(archived(), 1);
+
+        // Ids are unique to the millisecond, so a change made a moment later gets an archive of its own
+        std::thread::sleep(std::time::Duration::from_millis(2));
+        backend.write(THEME, &string("Adwaita")).unwrap();
+        let retaken = snapshot_current_system(&backend, &store, &mut Silent, &config, true, retention).unwrap();
+        assert_ne!(retaken.snapshot_id, taken.snapshot_id);
+        assert_eq!(archived(), 2);
+        assert_eq!(Snapshot::load(Path::new(&config)).unwrap().id, retaken.snapshot_id);
     }
 }
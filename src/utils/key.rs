@@ -0,0 +1,244 @@
+//! # Key Module
+//! This module defines **`Key`**, a single setting that can be addressed either by its full dconf path
+//! (`/org/gnome/desktop/interface/icon-theme`) or by GSettings schema and key name
+//! (`org.gnome.desktop.interface` + `icon-theme`). Both forms resolve to the same dconf path, so keys compare
+//! equal however they were written.
+//!
+//! Schemas normally store their keys in the directory named after their id, with the dots replaced by
+//! slashes. Relocatable schemas have no directory of their own and are instantiated at a path instead,
+//! which is given after the schema id the way `gsettings` accepts it:
+//! `org.gnome.Terminal.Legacy.Profile:/org/gnome/terminal/legacy/profiles:/:<id>/`.
+//!
+//! Keys are checked when they are built, so a `Key` always holds a well-formed dconf path: it starts with
+//! `/`, doesn't end with `/` and has no empty segment.
+use crate::utils::error::{ Error, Result };
+use serde::{ Deserialize, Deserializer, Serialize, Serializer };
+use std::cmp::Ordering;
+use std::fmt::{ self, Display };
+use std::hash::{ Hash, Hasher };
+
+/// **A setting, addressed by dconf path and optionally by schema**
+#[derive(Debug, Clone)]
+pub struct Key {
+    /// Full dconf path (e.g. `/org/gnome/desktop/interface/icon-theme`)
+    path: String,
+    /// Id of the schema the key was addressed by, if any
+    schema: Option<String>,
+    /// Whether the schema was given a path of its own
+    relocated: bool,
+}
+
+impl Key {
+    /// **Builds a key from its full dconf path**
+    ///
+    /// ## Returns:
+    ///
+    /// The key, or an `Error::InvalidKey` if `path` isn't a valid dconf key path.
+    pub fn from_path(path: &str) -> Result<Self> {
+        let malformed = |why: &str| Err(Error::InvalidKey(format!("{} is not a valid dconf key path: {}", path, why)));
+
+        if !path.starts_with('/') {
+            return malformed("it has to start with /");
+        }
+        if path.ends_with('/') {
+            return malformed("it names a directory, not a key");
+        }
+        if path.contains("//") {
+            return malformed("it contains an empty segment");
+        }
+        if path.chars().any(char::is_whitespace) {
+            return malformed("it contains whitespace");
+        }
+
+        Ok(Key { path: path.to_string(), schema: None, relocated: false })
+    }
+
+    /// **Builds a key from a GSettings schema and key name**
+    ///
+    /// ## Args:
+    ///
+    /// * `schema` - Schema id (e.g. `org.gnome.desktop.interface`), followed by `:` and the directory
+    ///   for relocatable schemas (e.g. `org.gnome.Terminal.Legacy.Profile:/org/gnome/terminal/:id/`)
+    /// * `name` - Key within the schema (e.g. `icon-theme`)
+    ///
+    /// ## Returns:
+    ///
+    /// The key, or an `Error::InvalidKey` if the schema id, directory or key name is malformed.
+    pub fn from_schema(schema: &str, name: &str) -> Result<Self> {
+        let (id, dir) = match schema.split_once(':') {
+            Some((id, dir)) => (id, Some(dir)),
+            None => (schema, None),
+        };
+
+        let valid_id = id
+            .split('.')
+            .all(|part| !part.is_empty() && part.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_'));
+        if !valid_id {
+            return Err(Error::InvalidKey(format!("{} is not a valid schema id", id)));
+        }
+
+        let valid_name =
+            name.starts_with(|c: char| c.is_ascii_alphabetic()) &&
+            name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-');
+        if !valid_name {
+            return Err(Error::InvalidKey(format!("{} is not a valid key name for schema {}", name, id)));
+        }
+
+        let dir = match dir {
+            Some(dir) if !dir.starts_with('/') || !dir.ends_with('/') || dir.contains("//") => {
+                return Err(
+                    Error::InvalidKey(format!("{} is not a valid path for schema {}: it has to start and end with /", dir, id))
+                );
+            }
+            Some(dir) => dir.to_string(),
+            None => format!("/{}/", id.replace('.', "/")),
+        };
+
+        Ok(Key {
+            path: format!("{}{}", dir, name),
+            schema: Some(id.to_string()),
+            relocated: schema.contains(':'),
+        })
+    }
+
+    /// Full dconf path of the key
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// dconf directory the key is stored in, ending in `/`
+    pub fn dir(&self) -> &str {
+        &self.path[..self.path.rfind('/').map_or(0, |index| index + 1)]
+    }
+
+    /// Name of the key within its directory (and schema)
+    pub fn name(&self) -> &str {
+        &self.path[self.dir().len()..]
+    }
+
+    /// Id of the schema the key was addressed by, `None` for keys addressed by dconf path
+    pub fn schema(&self) -> Option<&str> {
+        self.schema.as_deref()
+    }
+
+    /// Whether the key was addressed by a schema with an explicit path, as relocatable schemas require
+    pub fn is_relocated(&self) -> bool {
+        self.relocated
+    }
+
+    /// **The key in the form `gsettings` accepts**, `SCHEMA[:PATH] KEY`, for keys addressed by schema
+    pub fn gsettings(&self) -> Option<String> {
+        self.schema.as_ref().map(|id| {
+            match self.relocated {
+                true => format!("{}:{} {}", id, self.dir(), self.name()),
+                false => format!("{} {}", id, self.name()),
+            }
+        })
+    }
+}
+
+impl Display for Key {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.path)
+    }
+}
+
+// Keys are the same setting whenever they resolve to the same dconf path, however they were addressed
+
+impl PartialEq for Key {
+    fn eq(&self, other: &Self) -> bool {
+        self.path == other.path
+    }
+}
+
+impl Eq for Key {}
+
+impl PartialOrd for Key {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Key {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.path.cmp(&other.path)
+    }
+}
+
+impl Hash for Key {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.path.hash(state);
+    }
+}
+
+impl Serialize for Key {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.path)
+    }
+}
+
+impl<'de> Deserialize<'de> for Key {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+        let path = String::deserialize(deserializer)?;
+        Key::from_path(&path).map_err(serde::de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TERMINAL: &str = "org.gnome.Terminal.Legacy.Profile:/org/gnome/terminal/legacy/profiles:/:b1dcc9dd/";
+
+    #[test]
+    fn rejects_malformed_paths() {
+        for path in ["org/gnome/desktop/key", "/org/gnome/desktop/", "/org//gnome/key", "/", "", "/org/gnome/icon theme"] {
+            assert!(matches!(Key::from_path(path), Err(Error::InvalidKey(_))), "{}", path);
+        }
+        assert!(serde_json::from_str::<Key>("\"/org/gnome/desktop/\"").is_err());
+    }
+
+    #[test]
+    fn rejects_malformed_schema_keys() {
+        for (schema, name) in [
+            ("org.gnome.desktop.interface", ""),
+            ("org.gnome.desktop.interface", "icon_theme"),
+            ("org.gnome.desktop.interface", "1st"),
+            ("org..gnome", "icon-theme"),
+            ("", "icon-theme"),
+            ("org.gnome.Terminal.Legacy.Profile:org/gnome/terminal/", "font"),
+            ("org.gnome.Terminal.Legacy.Profile:/org/gnome/terminal", "font"),
+            ("org.gnome.Terminal.Legacy.Profile:/org//terminal/", "font"),
+        ] {
+            assert!(matches!(Key::from_schema(schema, name), Err(Error::InvalidKey(_))), "{} {}", schema, name);
+        }
+    }
+
+    #[test]
+    fn schema_and_path_forms_are_the_same_key() {
+        let by_schema = Key::from_schema("org.gnome.desktop.interface", "icon-theme").unwrap();
+        let by_path = Key::from_path("/org/gnome/desktop/interface/icon-theme").unwrap();
+
+        assert_eq!(by_schema, by_path);
+        assert_eq!((by_schema.dir(), by_schema.name()), ("/org/gnome/desktop/interface/", "icon-theme"));
+        assert_eq!(by_schema.gsettings().as_deref(), Some("org.gnome.desktop.interface icon-theme"));
+        assert_eq!(by_path.gsettings(), None);
+        assert!(!by_schema.is_relocated());
+    }
+
+    #[test]
+    fn relocatable_keys_round_trip_through_the_gsettings_form() {
+        let key = Key::from_schema(TERMINAL, "font").unwrap();
+
+        assert!(key.is_relocated());
+        assert_eq!(key.schema(), Some("org.gnome.Terminal.Legacy.Profile"));
+        assert_eq!(key, Key::from_path("/org/gnome/terminal/legacy/profiles:/:b1dcc9dd/font").unwrap());
+
+        let gsettings = key.gsettings().unwrap();
+        assert_eq!(gsettings, format!("{} font", TERMINAL));
+        let (schema, name) = gsettings.split_once(' ').unwrap();
+        let parsed = Key::from_schema(schema, name).unwrap();
+        assert_eq!(parsed.path(), key.path());
+        assert_eq!(parsed.gsettings(), key.gsettings());
+    }
+}
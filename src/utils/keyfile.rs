@@ -0,0 +1,329 @@
+//! # DCONF Keyfile Module
+//! This module reads and writes the **keyfile format** produced by `dconf dump` and accepted by
+//! `dconf load`:
+//!
+//! ```text
+//! [org/gnome/desktop/background]
+//! picture-options='stretched'
+//! picture-uri='file:///usr/share/backgrounds/win11.jpg'
+//!
+//! [org/gnome/desktop/interface]
+//! icon-theme='Windows-Eleven'
+//! ```
+//!
+//! Group names are directories relative to the directory the keyfile was dumped from (`[/]` being that
+//! directory itself), and values are in GVariant text format.
+//!
+//! A **`Keyfile`** keeps every line as it was read, including comments, blank lines and spacing, so
+//! writing an unmodified keyfile gives back exactly the text it was parsed from. Only entries that are
+//! changed with **`Keyfile::set`** are written in the canonical `key=value` form. Keys are addressed by
+//! their full dconf path, which makes it possible to diff keyfiles dumped from different directories.
+use crate::utils::error::{ Error, Result };
+use crate::utils::gvariant::{ self, Variant };
+use std::collections::BTreeMap;
+use std::fmt::{ self, Display };
+
+/// **A parsed dconf keyfile**
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Keyfile {
+    /// Directory the keyfile is relative to, ending in `/`
+    dir: String,
+    lines: Vec<Line>,
+    /// Whether the text ended with a line break
+    trailing_newline: bool,
+}
+
+/// A single line of a keyfile
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Line {
+    /// A `[group]` header, with the full path of the directory it opens
+    Group { raw: String, path: String },
+    /// A `key=value` line; `raw` is `None` once the entry has been changed
+    Entry { raw: Option<String>, name: String, value: String },
+    /// A blank line or a comment, kept verbatim
+    Other(String),
+}
+
+impl Keyfile {
+    /// **Parses a keyfile**
+    ///
+    /// ## Args:
+    ///
+    /// * `dir` - The directory the keyfile was dumped from (e.g. `/`)
+    /// * `text` - The keyfile, in the format produced by `dconf dump`
+    ///
+    /// ## Returns:
+    ///
+    /// The keyfile, or an `Error::InvalidKey` naming the first malformed line. Values are only
+    /// checked when they are read with **`Keyfile::values`**.
+    pub fn parse(dir: &str, text: &str) -> Result<Self> {
+        let mut lines = Vec::new();
+        let mut in_group = false;
+        let body = match text {
+            "" => None,
+            text => Some(text.strip_suffix('\n').unwrap_or(text)),
+        };
+
+        for (number, raw) in body.into_iter().flat_map(|body| body.split('\n')).enumerate() {
+            let line = raw.trim();
+            let malformed = |why: &str| {
+                Error::InvalidKey(format!("Invalid keyfile line {}: {} ({})", number + 1, line, why))
+            };
+            if line.is_empty() || line.starts_with('#') {
+                lines.push(Line::Other(raw.to_string()));
+                continue;
+            }
+
+            if let Some(name) = line.strip_prefix('[') {
+                let name = name.strip_suffix(']').ok_or_else(|| malformed("unterminated group name"))?;
+                lines.push(Line::Group { raw: raw.to_string(), path: group_path(dir, name) });
+                in_group = true;
+                continue;
+            }
+
+            let (name, value) = line.split_once('=').ok_or_else(|| malformed("expected key=value"))?;
+            if !in_group {
+                return Err(malformed("key outside of a group"));
+            }
+            if name.trim().is_empty() || name.contains('/') {
+                return Err(malformed("invalid key name"));
+            }
+            lines.push(Line::Entry {
+                raw: Some(raw.to_string()),
+                name: name.trim().to_string(),
+                value: value.trim().to_string(),
+            });
+        }
+
+        Ok(Keyfile {
+            dir: normalize_dir(dir),
+            lines,
+            trailing_newline: text.ends_with('\n'),
+        })
+    }
+
+    /// **Builds a keyfile from full key paths and values**, in the same layout as `dconf dump`
+    ///
+    /// ## Args:
+    ///
+    /// * `dir` - The directory the keyfile will be loaded into (e.g. `/`)
+    /// * `entries` - Full key paths below `dir` with their values
+    pub fn from_entries<'a>(dir: &str, entries: impl IntoIterator<Item = (&'a str, &'a Variant)>) -> Self {
+        let mut keyfile = Keyfile { dir: normalize_dir(dir), lines: Vec::new(), trailing_newline: true };
+        let mut sorted: Vec<(&str, &Variant)> = entries.into_iter().collect();
+        sorted.sort_by_key(|(key, _)| split_key(key).0);
+
+        for (key, value) in sorted {
+            keyfile.set(key, value);
+        }
+
+        keyfile
+    }
+
+    /// Whether the keyfile holds no entries
+    pub fn is_empty(&self) -> bool {
+        self.entries().next().is_none()
+    }
+
+    /// **Every entry as its full key path and value text**, in file order
+    pub fn entries(&self) -> impl Iterator<Item = (String, &str)> {
+        let mut group = None;
+
+        self.lines.iter().filter_map(move |line| {
+            match line {
+                Line::Group { path, .. } => {
+                    group = Some(path.as_str());
+                    None
+                }
+                Line::Entry { name, value, .. } => group.map(|path| (format!("{}{}", path, name), value.as_str())),
+                Line::Other(_) => None,
+            }
+        })
+    }
+
+    /// **Parses every value**
+    ///
+    /// ## Returns:
+    ///
+    /// Every full key path mapped to its value, later entries winning over earlier ones with the same path,
+    /// or an `Error::InvalidKey` if a value isn't valid GVariant text.
+    pub fn values(&self) -> Result<BTreeMap<String, Variant>> {
+        self.entries()
+            .map(|(key, text)| {
+                gvariant
+                    ::parse(text)
+                    .map(|value| (key.clone(), value))
+                    .map_err(|why| Error::InvalidKey(format!("Invalid value for {}: {}", key, why)))
+            })
+            .collect()
+    }
+
+    /// **Sets `key` to `value`**, adding it to its group (or a new group at the end) if it isn't present yet
+    pub fn set(&mut self, key: &str, value: &Variant) {
+        self.set_text(key, &gvariant::print(value));
+    }
+
+    /// **Compares this keyfile with `other`**
+    ///
+    /// ## Returns:
+    ///
+    /// The changeset that turns this keyfile's values into `other`'s: every key whose value differs or
+    /// that only `other` has mapped to its new value, and every key missing from `other` mapped to `None`.
+    /// Values are compared as typed `Variant`s, so differences in spelling alone are ignored. Returns an
+    /// `Error::InvalidKey` if a value of either keyfile isn't valid GVariant text.
+    pub fn diff(&self, other: &Keyfile) -> Result<BTreeMap<String, Option<Variant>>> {
+        let before = self.values()?;
+        let after = other.values()?;
+
+        let removed = before
+            .keys()
+            .filter(|key| !after.contains_key(*key))
+            .map(|key| (key.clone(), None));
+        let changed = after
+            .iter()
+            .filter(|(key, value)| before.get(*key) != Some(value))
+            .map(|(key, value)| (key.clone(), Some(value.clone())));
+
+        Ok(removed.chain(changed).collect())
+    }
+
+    /// Sets the value text of `key`, replacing every existing entry for it
+    fn set_text(&mut self, key: &str, text: &str) {
+        let (path, name) = split_key(key);
+        let mut group: Option<&str> = None;
+        let mut found = false;
+
+        for line in self.lines.iter_mut() {
+            match line {
+                Line::Group { path, .. } => {
+                    group = Some(path.as_str());
+                }
+                Line::Entry { raw, name: entry, value } if group == Some(path.as_str()) && entry == name => {
+                    if value != text {
+                        *raw = None;
+                        *value = text.to_string();
+                    }
+                    found = true;
+                }
+                _ => {}
+            }
+        }
+        if found {
+            return;
+        }
+
+        let entry = Line::Entry { raw: None, name: name.to_string(), value: text.to_string() };
+        match self.group_end(&path) {
+            Some(index) => self.lines.insert(index, entry),
+            None => {
+                if !self.lines.is_empty() {
+                    self.lines.push(Line::Other(String::new()));
+                }
+                let name = path.strip_prefix(&self.dir).unwrap_or(&path).trim_end_matches('/');
+                self.lines.push(Line::Group {
+                    raw: format!("[{}]", if name.is_empty() { "/" } else { name }),
+                    path: path.clone(),
+                });
+                self.lines.push(entry);
+            }
+        }
+    }
+
+    /// Index right after the last entry of the first group opening `path`, if there is one
+    fn group_end(&self, path: &str) -> Option<usize> {
+        let start = self.lines.iter().position(|line| {
+            matches!(line, Line::Group { path: group, .. } if group == path)
+        })?;
+        let end = self.lines[start + 1..]
+            .iter()
+            .position(|line| matches!(line, Line::Group { .. }))
+            .map_or(self.lines.len(), |offset| start + 1 + offset);
+
+        let last_entry = self.lines[start..end]
+            .iter()
+            .rposition(|line| matches!(line, Line::Entry { .. }))
+            .map_or(start, |offset| start + offset);
+
+        Some(last_entry + 1)
+    }
+}
+
+impl Display for Keyfile {
+    /// Writes the keyfile back, unchanged lines exactly as they were read
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (index, line) in self.lines.iter().enumerate() {
+            if index > 0 {
+                f.write_str("\n")?;
+            }
+            match line {
+                Line::Group { raw, .. } | Line::Entry { raw: Some(raw), .. } | Line::Other(raw) => f.write_str(raw)?,
+                Line::Entry { raw: None, name, value } => write!(f, "{}={}", name, value)?,
+            }
+        }
+
+        if self.trailing_newline && !self.lines.is_empty() {
+            f.write_str("\n")?;
+        }
+        Ok(())
+    }
+}
+
+/// Full path of the directory opened by the group `name` of a keyfile relative to `dir`
+fn group_path(dir: &str, name: &str) -> String {
+    match name.trim().trim_matches('/') {
+        "" => normalize_dir(dir),
+        name => format!("{}{}/", normalize_dir(dir), name),
+    }
+}
+
+/// `dir` with exactly one trailing `/`
+fn normalize_dir(dir: &str) -> String {
+    format!("{}/", dir.trim_end_matches('/'))
+}
+
+/// Splits a full key path into its directory (ending in `/`) and key name
+fn split_key(key: &str) -> (String, &str) {
+    match key.rsplit_once('/') {
+        Some((dir, name)) => (format!("{}/", dir), name),
+        None => ("/".to_string(), key),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    /// A line `dconf dump` could have written, or a user could have added by hand
+    fn any_line() -> impl Strategy<Value = String> {
+        prop_oneof![
+            "[ \t]*\\[[a-z/-]{0,12}\\][ \t]*",
+            "[ \t]*[a-z-]{1,8}[ \t]*=[ \t]*'[a-z ]{0,8}'[ \t]*",
+            "[ \t]*#\\PC{0,12}",
+            "[ \t]*",
+        ]
+    }
+
+    proptest! {
+        #[test]
+        fn writes_back_the_text_it_parsed(lines in proptest::collection::vec(any_line(), 0..12), newline in any::<bool>()) {
+            // Entries are only valid after a group header
+            let text = format!("[org/gnome]\n{}{}", lines.join("\n"), if newline { "\n" } else { "" });
+            prop_assert_eq!(Keyfile::parse("/", &text).unwrap().to_string(), text);
+        }
+
+        #[test]
+        fn built_keyfiles_read_back_their_values(
+            entries in proptest::collection::btree_map("(/[a-z]{1,4}){1,3}/[a-z-]{1,6}", "\\PC*", 0..8)
+        ) {
+            let values: BTreeMap<String, Variant> = entries
+                .into_iter()
+                .map(|(key, text)| (key, Variant::String(text)))
+                .collect();
+            let keyfile = Keyfile::from_entries("/", values.iter().map(|(key, value)| (key.as_str(), value)));
+
+            let text = keyfile.to_string();
+            prop_assert_eq!(Keyfile::parse("/", &text).unwrap().values().unwrap(), values, "{}", text);
+        }
+    }
+}
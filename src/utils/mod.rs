@@ -1,3 +1,20 @@
+pub mod apply;
+pub mod backend;
 pub mod cli;
+pub mod dconf;
+pub mod diff;
+pub mod doctor;
+pub mod error;
 pub mod gsettings;
+pub mod gvariant;
 pub mod incognito;
+pub mod key;
+pub mod keyfile;
+pub mod output;
+pub mod profile;
+pub mod record;
+pub mod reporter;
+pub mod schema;
+pub mod snapshot;
+pub mod state;
+pub mod status;
@@ -0,0 +1,86 @@
+//! # Output Module
+//! This module implements the machine-readable output of `enable`, `restore`, `status` and `diff`.
+//!
+//! With `--output json` the human output (emoji, boxes, spinners) is suppressed and the command prints a
+//! single JSON **`Document`** on stdout once it's done, whether it succeeded or not:
+//!
+//! ```json
+//! { "command": "enable", "ok": false, "profile": "win11", "keys": { ... }, "error": { "code": 9, ... } }
+//! ```
+//!
+//! `command`, `ok` and `error` are always present; every other field is filled in by the `Json` reporter
+//! from the events the command emits, so a failing command still reports what it did before failing.
+use crate::utils::error::{ Error, Result };
+use serde::Serialize;
+use serde_json::{ Map, Value };
+use std::io::{ self, Write };
+
+/// **How a command reports its results**
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// Colored output for people, as configured by --silent
+    Human,
+    /// Uncolored text, one line per step
+    Plain,
+    /// A single JSON document on stdout
+    Json,
+}
+
+/// **Structured result of a command**
+#[derive(Debug, Serialize)]
+pub struct Document {
+    /// Name of the command that ran
+    command: &'static str,
+    /// Whether the command succeeded
+    ok: bool,
+    /// Everything the command reported, by field name
+    #[serde(flatten)]
+    fields: Map<String, Value>,
+    /// Why the command failed, `null` if it succeeded
+    error: Option<Error>,
+}
+
+impl Document {
+    /// Creates an empty document for `command`
+    pub fn new(command: &'static str) -> Self {
+        Document { command, ok: true, fields: Map::new(), error: None }
+    }
+
+    /// Sets the field `name`, replacing any previous value
+    pub fn set(&mut self, name: &str, value: impl Serialize) {
+        let value = serde_json::to_value(value).unwrap_or_else(|why| Value::String(why.to_string()));
+        self.fields.insert(name.to_string(), value);
+    }
+
+    /// **Prints the document with the command's `result`**
+    ///
+    /// ## Returns:
+    ///
+    /// The exit code of the command: `0`, or the code of its error.
+    pub fn finish(mut self, result: Result<()>) -> i32 {
+        let code = result
+            .as_ref()
+            .err()
+            .map_or(0, Error::exit_code);
+        self.ok = result.is_ok();
+        self.error = result.err();
+
+        // A consumer that stops reading early (e.g. `| head`) isn't worth a panic
+        match serde_json::to_string_pretty(&self) {
+            Ok(json) => {
+                let _ = writeln!(io::stdout(), "{}", json);
+            }
+            Err(why) => eprintln!("Couldn't serialize the output: {}", why),
+        }
+
+        code
+    }
+}
+
+#[cfg(test)]
+impl Document {
+    /// Value of the field `name`, if it was set
+    pub fn get(&self, name: &str) -> Option<&Value> {
+        self.fields.get(name)
+    }
+}
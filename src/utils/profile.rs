@@ -0,0 +1,525 @@
+//! # Profile Module
+//! This module defines the **`Profile`** format, which describes everything incognito mode applies to the
+//! desktop. Profiles are TOML files:
+//!
+//! ```toml
+//! name = "win11"
+//! description = "Windows 11 look"
+//! extensions = ["user-theme@gnome-shell-extensions.gcampax.github.com"]
+//!
+//! [assets]
+//! wallpaper = "/run/current-system/sw/share/backgrounds/incognito/win11.jpg"
+//! theme = "Fluent-Round-Dark"
+//! icons = "Windows-Eleven"
+//!
+//! [[settings]]
+//! schema = "org.gnome.desktop.interface"
+//! key = "icon-theme"
+//! value = "'{icons}'"
+//!
+//! [dconf]
+//! "/org/gnome/shell/extensions/dash-to-dock/extend-height" = "true"
+//! ```
+//!
+//! `settings` address keys by GSettings schema and key, `dconf` by their full dconf path (for keys whose
+//! schema may not be installed). Relocatable schemas take their path after the id, as with `gsettings`
+//! (`schema = "org.gnome.Terminal.Legacy.Profile:/org/gnome/terminal/legacy/profiles:/:<id>/"`). Malformed
+//! schema ids, key names and paths are rejected when the profile is read. Values are in GVariant text
+//! format, and the `{wallpaper}`, `{theme}` and `{icons}` placeholders are replaced with the profile's
+//! assets, which can be overridden on the command line.
+//!
+//! Profiles can be layered: a profile may name the profiles it builds on in `extends`, and several profiles
+//! can be merged in order with **`Profile::compose`** (e.g. `win11` + `single-workspace` +
+//! `no-notifications`). Later layers override the keys and assets of earlier ones, and the merged profile
+//! remembers which layer each key came from.
+//!
+//! The built-in profiles (`win11`, `win10`, `win7`, `macos` and `corporate-ubuntu`, plus the
+//! `single-workspace` and `no-notifications` overlays) are compiled into the tool; user profiles are read
+//! from `$XDG_CONFIG_HOME/incognito/profiles/<name>.toml` or from any path.
+use crate::utils::doctor;
+use crate::utils::error::{ Error, Result };
+use crate::utils::gvariant::{ self, Variant };
+use crate::utils::key::Key;
+use serde::{ Deserialize, Serialize };
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{ Path, PathBuf };
+
+/// Name of the profile used when none is given
+pub const DEFAULT_PROFILE: &str = "win11";
+
+/// Profiles shipped with the tool, as `(name, TOML source)`
+const BUILTIN_PROFILES: &[(&str, &str)] = &[
+    ("win11", include_str!("../../profiles/win11.toml")),
+    ("win10", include_str!("../../profiles/win10.toml")),
+    ("win7", include_str!("../../profiles/win7.toml")),
+    ("macos", include_str!("../../profiles/macos.toml")),
+    ("corporate-ubuntu", include_str!("../../profiles/corporate-ubuntu.toml")),
+    ("single-workspace", include_str!("../../profiles/single-workspace.toml")),
+    ("no-notifications", include_str!("../../profiles/no-notifications.toml")),
+];
+
+/// **A look incognito mode can apply**
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Profile {
+    /// Short name, used to select the profile
+    pub name: String,
+    /// One line description shown by `profile list`
+    #[serde(default)]
+    pub description: String,
+    /// Profiles this one builds on, merged in order before its own keys
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub extends: Vec<String>,
+    /// UUIDs of the GNOME Shell extensions the profile relies on
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub extensions: Vec<String>,
+    /// Files and themes the settings refer to
+    #[serde(default, skip_serializing_if = "Assets::is_empty")]
+    pub assets: Assets,
+    /// Keys addressed by GSettings schema
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub settings: Vec<Setting>,
+    /// Keys addressed by full dconf path, mapped to their value
+    #[serde(default)]
+    pub dconf: BTreeMap<Key, String>,
+    /// Name of the profile each key was last set by, keyed by dconf path
+    #[serde(skip)]
+    origins: BTreeMap<String, String>,
+}
+
+/// **Assets substituted into a profile's values**
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Assets {
+    /// Wallpaper image path, replaces `{wallpaper}`
+    #[serde(default)]
+    pub wallpaper: String,
+    /// GTK, shell and window manager theme, replaces `{theme}`
+    #[serde(default)]
+    pub theme: String,
+    /// Icon theme, replaces `{icons}`
+    #[serde(default)]
+    pub icons: String,
+}
+
+/// **A single key addressed by GSettings schema**
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "SettingFile", into = "SettingFile")]
+pub struct Setting {
+    /// The key, built from the schema id (with its path for relocatable schemas) and key name
+    pub key: Key,
+    /// Value in GVariant text format
+    pub value: String,
+}
+
+/// How a `Setting` is written in a profile file
+#[derive(Serialize, Deserialize)]
+struct SettingFile {
+    /// Schema id (e.g. `org.gnome.desktop.interface`), followed by `:` and a path for relocatable schemas
+    schema: String,
+    /// Key within the schema (e.g. `icon-theme`)
+    key: String,
+    /// Value in GVariant text format
+    value: String,
+}
+
+impl Assets {
+    /// Whether no asset is set
+    pub fn is_empty(&self) -> bool {
+        self.wallpaper.is_empty() && self.theme.is_empty() && self.icons.is_empty()
+    }
+
+    /// **The theme and icon theme that aren't installed**, as `(kind, name)` pairs
+    ///
+    /// Incognito only points the desktop at them, so GNOME falls back to its default look for any that
+    /// are missing.
+    pub fn missing(&self) -> Vec<(&'static str, &str)> {
+        self.missing_from(asset_dirs)
+    }
+
+    /// The assets not found in any of the directories `dirs` returns for `themes` or `icons`
+    fn missing_from(&self, dirs: impl Fn(&str) -> Vec<PathBuf>) -> Vec<(&'static str, &str)> {
+        [("theme", "themes", &self.theme), ("icon theme", "icons", &self.icons)]
+            .into_iter()
+            .filter(|(_, kind, name)| !name.is_empty() && !dirs(kind).iter().any(|dir| dir.join(name).is_dir()))
+            .map(|(description, _, name)| (description, name.as_str()))
+            .collect()
+    }
+}
+
+impl TryFrom<SettingFile> for Setting {
+    type Error = Error;
+
+    fn try_from(file: SettingFile) -> Result<Self> {
+        Ok(Setting { key: Key::from_schema(&file.schema, &file.key)?, value: file.value })
+    }
+}
+
+impl From<Setting> for SettingFile {
+    fn from(setting: Setting) -> Self {
+        let schema = match setting.key.is_relocated() {
+            true => format!("{}:{}", setting.key.schema().unwrap_or_default(), setting.key.dir()),
+            false => setting.key.schema().unwrap_or_default().to_string(),
+        };
+
+        SettingFile { schema, key: setting.key.name().to_string(), value: setting.value }
+    }
+}
+
+impl Profile {
+    /// **Finds a profile by name or path**, with the profiles it `extends` merged in
+    ///
+    /// Anything that looks like a path (contains `/` or ends in `.toml`) is read from disk. Otherwise the
+    /// user's profile directory is searched first, then the built-in profiles. Names in `extends` are looked
+    /// up the same way.
+    ///
+    /// ## Returns:
+    ///
+    /// The profile, or an `Error` if it (or one it extends) can't be found or parsed, or if it ends up
+    /// extending itself.
+    pub fn resolve(name: &str) -> Result<Self> {
+        Profile::resolve_within(name, &mut Vec::new())
+    }
+
+    /// **Merges several profiles in order**, each one overriding the keys and assets of the ones before it
+    ///
+    /// ## Args:
+    ///
+    /// * `names` - Names or paths of the profiles, base first (e.g. `["win11", "single-workspace"]`)
+    ///
+    /// ## Returns:
+    ///
+    /// The merged profile, named after its layers (e.g. `win11+single-workspace`), or an `Error` if one of
+    /// them can't be resolved.
+    pub fn compose(names: &[String]) -> Result<Self> {
+        let layers = match names.is_empty() {
+            true => vec![Profile::resolve(DEFAULT_PROFILE)?],
+            false => names
+                .iter()
+                .map(|name| Profile::resolve(name))
+                .collect::<Result<Vec<_>>>()?,
+        };
+        let name = layers
+            .iter()
+            .map(|layer| layer.name.as_str())
+            .collect::<Vec<_>>()
+            .join("+");
+
+        let mut layers = layers.into_iter();
+        let mut profile = layers.next().expect("at least one layer");
+        for layer in layers {
+            profile = profile.merge(layer);
+        }
+        profile.name = name;
+
+        Ok(profile)
+    }
+
+    /// Resolves `name`, keeping track of the profiles being resolved to catch `extends` cycles
+    fn resolve_within(name: &str, chain: &mut Vec<String>) -> Result<Self> {
+        if chain.iter().any(|parent| parent == name) {
+            return Err(
+                Error::InvalidProfile(format!("Profile {} extends itself ({} -> {})", name, chain.join(" -> "), name))
+            );
+        }
+
+        Profile::find(name)?.inherit(name, chain)
+    }
+
+    /// **Reads a profile file**, with the profiles it `extends` merged in
+    pub fn from_file(path: &Path) -> Result<Self> {
+        Profile::load(path)?.inherit(&path.display().to_string(), &mut Vec::new())
+    }
+
+    /// Merges the profiles this one `extends` under it, `name` being how it was looked up
+    fn inherit(mut self, name: &str, chain: &mut Vec<String>) -> Result<Self> {
+        self.origins = self
+            .keys()
+            .map(|(key, _)| (key.path().to_string(), self.name.clone()))
+            .collect();
+        if self.extends.is_empty() {
+            return Ok(self);
+        }
+
+        chain.push(name.to_string());
+        let mut base: Option<Profile> = None;
+        for parent in &self.extends {
+            let parent = Profile::resolve_within(parent, chain)?;
+            base = Some(match base {
+                Some(base) => base.merge(parent),
+                None => parent,
+            });
+        }
+        chain.pop();
+
+        let (name, description) = (self.name.clone(), self.description.clone());
+        let mut merged = base.expect("extends is not empty").merge(self);
+        merged.name = name;
+        if !description.is_empty() {
+            merged.description = description;
+        }
+
+        Ok(merged)
+    }
+
+    /// Finds a single profile by name or path, without merging the profiles it extends
+    fn find(name: &str) -> Result<Self> {
+        if name.contains('/') || name.ends_with(".toml") {
+            return Profile::load(Path::new(name));
+        }
+
+        if let Some(path) = user_path(name) {
+            if path.exists() {
+                return Profile::load(&path);
+            }
+        }
+
+        Profile::builtin(name).ok_or(Error::NotFound(format!("Unknown profile {}", name)))
+    }
+
+    /// **Lays `layer` over this profile**
+    ///
+    /// Keys set by `layer` replace the ones with the same dconf path, whether they were addressed by schema
+    /// or by path, and its non-empty assets replace the current ones. Extensions are added to the list.
+    fn merge(mut self, layer: Profile) -> Self {
+        for setting in layer.settings {
+            self.settings.retain(|existing| existing.key != setting.key);
+            self.dconf.remove(&setting.key);
+            self.settings.push(setting);
+        }
+        for (key, value) in layer.dconf {
+            self.settings.retain(|existing| existing.key != key);
+            self.dconf.insert(key, value);
+        }
+        self.origins.extend(layer.origins);
+
+        for extension in layer.extensions {
+            if !self.extensions.contains(&extension) {
+                self.extensions.push(extension);
+            }
+        }
+
+        let assets = [
+            (&mut self.assets.wallpaper, layer.assets.wallpaper),
+            (&mut self.assets.theme, layer.assets.theme),
+            (&mut self.assets.icons, layer.assets.icons),
+        ];
+        for (current, replacement) in assets {
+            if !replacement.is_empty() {
+                *current = replacement;
+            }
+        }
+
+        self
+    }
+
+    /// Returns the built-in profile called `name`
+    pub fn builtin(name: &str) -> Option<Self> {
+        BUILTIN_PROFILES.iter()
+            .find(|(builtin, _)| *builtin == name)
+            .map(|(_, source)| toml::from_str(source).expect("built-in profiles are valid"))
+    }
+
+    /// **Reads a profile file**
+    pub fn load(path: &Path) -> Result<Self> {
+        let contents = match fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(why) if why.kind() == std::io::ErrorKind::NotFound => {
+                return Err(Error::NotFound(format!("Profile file {} doesn't exist", path.display())));
+            }
+            Err(why) => {
+                return Err(Error::io("read", path, why));
+            }
+        };
+
+        toml
+            ::from_str(&contents)
+            .map_err(|why| Error::InvalidProfile(format!("Couldn't parse {}: {}", path.display(), why)))
+    }
+
+    /// **Creates a profile that sets the given dconf keys**, as written by `profile finish`
+    ///
+    /// ## Returns:
+    ///
+    /// The profile, or an `Error::InvalidKey` if one of the keys isn't a valid dconf path.
+    pub fn recorded(name: &str, description: String, values: BTreeMap<String, Variant>) -> Result<Self> {
+        let dconf = values
+            .into_iter()
+            .map(|(key, value)| Ok((Key::from_path(&key)?, gvariant::print(&value))))
+            .collect::<Result<_>>()?;
+
+        Ok(Profile {
+            name: name.to_string(),
+            description,
+            extends: Vec::new(),
+            extensions: Vec::new(),
+            assets: Assets::default(),
+            settings: Vec::new(),
+            dconf,
+            origins: BTreeMap::new(),
+        })
+    }
+
+    /// **Serializes the profile** in the profile file format
+    pub fn to_toml(&self) -> Result<String> {
+        toml
+            ::to_string(self)
+            .map_err(|why| Error::Other(format!("Couldn't serialize profile {}: {}", self.name, why)))
+    }
+
+    /// Replaces the assets that were given on the command line
+    pub fn with_assets(
+        mut self,
+        wallpaper: Option<String>,
+        theme: Option<String>,
+        icons: Option<String>
+    ) -> Self {
+        if let Some(wallpaper) = wallpaper {
+            self.assets.wallpaper = wallpaper;
+        }
+        if let Some(theme) = theme {
+            self.assets.theme = theme;
+        }
+        if let Some(icons) = icons {
+            self.assets.icons = icons;
+        }
+        self
+    }
+
+    /// **Builds the keys and values the profile writes**
+    ///
+    /// ## Returns:
+    ///
+    /// Every key as a full dconf path, mapped to its value with the assets filled in, or an
+    /// `Error::InvalidKey` if a value isn't valid GVariant text.
+    pub fn changes(&self) -> Result<BTreeMap<String, Variant>> {
+        let mut changes = BTreeMap::new();
+
+        for (key, value) in self.keys() {
+            let value = gvariant
+                ::parse(&self.substitute(value))
+                .map_err(|why| Error::InvalidKey(format!("Invalid value for {} in profile {}: {}", key, self.name, why)))?;
+            changes.insert(key.path().to_string(), value);
+        }
+
+        Ok(changes)
+    }
+
+    /// **Name of the profile that set `key`**, which differs from this profile's name for inherited keys
+    pub fn origin(&self, key: &str) -> &str {
+        self.origins.get(key).map(String::as_str).unwrap_or(&self.name)
+    }
+
+    /// **The key the profile sets at the dconf path `path`**, as it was addressed (by schema or by path)
+    pub fn key(&self, path: &str) -> Option<&Key> {
+        self.keys()
+            .map(|(key, _)| key)
+            .filter(|key| key.path() == path)
+            .last()
+    }
+
+    /// Every key the profile sets, with its value before substitution
+    fn keys(&self) -> impl Iterator<Item = (&Key, &String)> {
+        self.settings
+            .iter()
+            .map(|setting| (&setting.key, &setting.value))
+            .chain(self.dconf.iter())
+    }
+
+    /// Fills in the asset placeholders, escaped so they stay valid inside GVariant string literals
+    fn substitute(&self, value: &str) -> String {
+        let escape = |asset: &str| asset.replace('\\', "\\\\").replace('\'', "\\'");
+
+        value
+            .replace("{wallpaper}", &escape(&self.assets.wallpaper))
+            .replace("{theme}", &escape(&self.assets.theme))
+            .replace("{icons}", &escape(&self.assets.icons))
+    }
+}
+
+/// **Lists every available profile**, user profiles first, as `(name, description, source)`
+pub fn available() -> Vec<(String, String, String)> {
+    let mut profiles = Vec::new();
+
+    if let Some(dir) = user_dir() {
+        let mut paths: Vec<PathBuf> = fs
+            ::read_dir(&dir)
+            .map(|entries| {
+                entries
+                    .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+                    .filter(|path| path.extension().is_some_and(|extension| extension == "toml"))
+                    .collect()
+            })
+            .unwrap_or_default();
+        paths.sort();
+
+        for path in paths {
+            let name = path.file_stem().unwrap_or_default().to_string_lossy().to_string();
+            let description = match Profile::load(&path) {
+                Ok(profile) => profile.description,
+                Err(why) => why.to_string(),
+            };
+            profiles.push((name, description, path.display().to_string()));
+        }
+    }
+
+    for (name, _) in BUILTIN_PROFILES {
+        if profiles.iter().any(|(user, _, _)| user == name) {
+            continue;
+        }
+        if let Some(profile) = Profile::builtin(name) {
+            profiles.push((name.to_string(), profile.description, "built-in".to_string()));
+        }
+    }
+
+    profiles
+}
+
+/// **Path a user profile called `name` is read from** when selected by name
+pub fn user_path(name: &str) -> Option<PathBuf> {
+    user_dir().map(|dir| dir.join(format!("{}.toml", name)))
+}
+
+/// **Directories GNOME looks for themes (`kind` = `themes`) or icon themes (`icons`) in**
+pub fn asset_dirs(kind: &str) -> Vec<PathBuf> {
+    dirs
+        ::home_dir()
+        .map(|home| home.join(format!(".{}", kind)))
+        .into_iter()
+        .chain(doctor::data_dirs().into_iter().map(|dir| dir.join(kind)))
+        .collect()
+}
+
+/// Directory holding the user's own profiles
+fn user_dir() -> Option<PathBuf> {
+    dirs::config_dir().map(|dir| dir.join("incognito").join("profiles"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reports_assets_that_are_not_installed() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir_all(dir.path().join("themes").join("Fluent-Round-Dark")).unwrap();
+        fs::create_dir_all(dir.path().join("icons").join("Windows-Eleven")).unwrap();
+        let dirs = |kind: &str| vec![dir.path().join("missing").join(kind), dir.path().join(kind)];
+
+        let win11 = Profile::resolve("win11").unwrap();
+        assert_eq!(win11.assets.missing_from(dirs), []);
+
+        let win10 = Profile::resolve("win10").unwrap();
+        assert_eq!(win10.assets.missing_from(dirs), [("theme", "Windows-10-Dark")]);
+
+        let custom = win10.with_assets(None, Some("Fluent-Round-Dark".into()), Some("Papirus".into()));
+        assert_eq!(custom.assets.missing_from(dirs), [("icon theme", "Papirus")]);
+    }
+
+    #[test]
+    fn builtin_profiles_resolve() {
+        for (name, _) in BUILTIN_PROFILES {
+            let profile = Profile::resolve(name).unwrap();
+            assert!(profile.changes().is_ok(), "{}", name);
+        }
+    }
+}
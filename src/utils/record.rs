@@ -0,0 +1,155 @@
+//! # Record Module
+//! This module builds profiles from manual tweaks. **`Recording::start`** snapshots the desktop, the user
+//! adjusts it by hand (themes, dock, wallpaper, ...), and **`Recording::finish`** compares the desktop
+//! against that snapshot and turns every key that changed into a new `Profile`.
+//!
+//! The recording in progress is kept at `$XDG_STATE_HOME/incognito/recording.toml`, so it survives
+//! logging out while the desktop is being tweaked.
+use crate::utils::backend::SettingsBackend;
+use crate::utils::error::{ Error, Result };
+use crate::utils::gvariant::Variant;
+use crate::utils::keyfile::Keyfile;
+use crate::utils::profile::Profile;
+use crate::utils::snapshot::Snapshot;
+use serde::{ Deserialize, Serialize };
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::PathBuf;
+
+/// **A profile recording in progress**
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Recording {
+    /// Name of the profile being recorded
+    pub name: String,
+    /// The desktop as it was when the recording started
+    pub started: Snapshot,
+}
+
+/// **The outcome of a finished recording**
+#[derive(Debug, Clone, PartialEq)]
+pub struct Recorded {
+    /// Profile setting every key that was changed or added during the recording
+    pub profile: Profile,
+    /// Keys that were reset to their default during the recording, which a profile can't express
+    pub reset: Vec<String>,
+}
+
+impl Recording {
+    /// **Starts recording a profile called `name`** by snapshotting the desktop
+    ///
+    /// ## Args:
+    ///
+    /// * `backend` - Backend to snapshot
+    /// * `name` - Name of the profile to record
+    /// * `force` - Replace a recording that is already in progress
+    ///
+    /// ## Returns:
+    ///
+    /// The recording, or an `Error` if the name isn't usable, another recording is in progress, or the
+    /// snapshot can't be taken or saved.
+    pub fn start(backend: &dyn SettingsBackend, name: &str, force: bool) -> Result<Self> {
+        if name.is_empty() || name.contains('/') || name.ends_with(".toml") {
+            return Err(Error::InvalidProfile(format!("{} can't be used as a profile name", name)));
+        }
+
+        if let (Some(current), false) = (Recording::load()?, force) {
+            return Err(
+                Error::Other(
+                    format!(
+                        "Already recording profile {}, finish it first or pass --force to start over",
+                        current.name
+                    )
+                )
+            );
+        }
+
+        let recording = Recording {
+            name: name.to_string(),
+            started: Snapshot::capture(backend)?,
+        };
+        let path = Recording::path()?;
+        let contents = toml
+            ::to_string(&recording)
+            .map_err(|why| Error::Other(format!("Couldn't serialize the recording: {}", why)))?;
+
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).map_err(|why| Error::io("create", dir, why))?;
+        }
+        fs::write(&path, contents).map_err(|why| Error::io("write to", &path, why))?;
+
+        Ok(recording)
+    }
+
+    /// **Reads the recording in progress**, `None` if there is none
+    pub fn load() -> Result<Option<Self>> {
+        let path = Recording::path()?;
+
+        match fs::read_to_string(&path) {
+            Ok(contents) =>
+                toml
+                    ::from_str(&contents)
+                    .map(Some)
+                    .map_err(|why| Error::corrupt(&path, why)),
+            Err(why) if why.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(why) => Err(Error::io("read", &path, why)),
+        }
+    }
+
+    /// **Compares the desktop against the start of the recording**
+    ///
+    /// The recording stays in progress until **`Recording::discard`** is called, so nothing is lost if the
+    /// profile can't be written.
+    ///
+    /// ## Returns:
+    ///
+    /// The recorded profile and the keys that were reset, or an `Error` if the desktop can't be read or
+    /// nothing changed.
+    pub fn finish(&self, backend: &dyn SettingsBackend) -> Result<Recorded> {
+        let started = Keyfile::from_entries(
+            "/",
+            self.started.entries.iter().map(|(key, value)| (key.as_str(), value))
+        );
+        let current = Keyfile::parse("/", &backend.dump("/")?)?;
+        let changes = started.diff(&current)?;
+
+        let reset: Vec<String> = changes
+            .iter()
+            .filter(|(_, value)| value.is_none())
+            .map(|(key, _)| key.clone())
+            .collect();
+        let changed: BTreeMap<String, Variant> = changes
+            .into_iter()
+            .filter_map(|(key, value)| value.map(|value| (key, value)))
+            .collect();
+
+        if changed.is_empty() {
+            return Err(Error::Other(format!("Nothing changed since recording {} started", self.name)));
+        }
+
+        let description = format!("Recorded on {} ({})", self.started.hostname, self.started.timestamp);
+
+        Ok(Recorded {
+            profile: Profile::recorded(&self.name, description, changed)?,
+            reset,
+        })
+    }
+
+    /// **Removes the recording in progress**
+    pub fn discard() -> Result<()> {
+        let path = Recording::path()?;
+
+        match fs::remove_file(&path) {
+            Ok(()) => Ok(()),
+            Err(why) if why.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            Err(why) => Err(Error::io("remove", &path, why)),
+        }
+    }
+
+    /// Path of the recording in progress, under the XDG state directory
+    fn path() -> Result<PathBuf> {
+        dirs
+            ::state_dir()
+            .map(|dir| dir.join("incognito").join("recording.toml"))
+            .ok_or(Error::NotFound("Unable to locate the user state directory".into()))
+    }
+}
@@ -0,0 +1,1000 @@
+//! # Reporter Module
+//! This module separates what the tool does from how it's shown. The core functions (snapshotting,
+//! enabling, restoring, diffing) never print anything themselves: they emit an **`Event`** to a
+//! **`Reporter`** at every step, and the reporter selected on the command line decides what to make of it:
+//!
+//! * **`Human`** - the banner, the `Updating System` box, emoji, colors and spinners
+//! * **`Plain`** - one uncolored line per event, for logs and terminals without emoji
+//! * **`Silent`** - nothing at all, except for the error a command fails with
+//! * **`Json`** - nothing while the command runs, then a single JSON `Document` on stdout
+//!
+//! Reporters are also the only place the tool asks the user anything, so the core logic runs the same
+//! whether there is someone at the terminal or not.
+use crate::utils::apply::Outcome;
+use crate::utils::diff::{ self, Diff, DiffFormat };
+use crate::utils::doctor::{ self, Check };
+use crate::utils::error::{ Error, Result };
+use crate::utils::gvariant::Variant;
+use crate::utils::incognito::Conflict;
+use crate::utils::output::{ Document, OutputFormat };
+use crate::utils::profile::{ Profile, DEFAULT_PROFILE };
+use crate::utils::record::Recorded;
+use crate::utils::schema::{ Issue, Severity };
+use crate::utils::snapshot::Snapshot;
+use crate::utils::state::State;
+use crate::utils::status::{ Disguise, Status };
+use colored::Colorize;
+use indicatif::{ ProgressBar, ProgressDrawTarget, ProgressStyle };
+use serde_json::json;
+use std::collections::BTreeMap;
+use std::fmt::Display;
+use std::io::{ self, IsTerminal, Write };
+use std::path::Path;
+use std::time::Duration;
+
+/// Line the frame around an enable or restore is drawn with
+const RULE: &str = "━";
+
+const BANNER: &str = color_print::cstr!(
+    r#"<bold><red>
+    
+    
+    ███╗   ██╗██╗██╗  ██╗    ██╗███╗   ██╗ ██████╗ ██████╗  ██████╗ ███╗   ██╗██╗████████╗ ██████╗ 
+    ████╗  ██║██║╚██╗██╔╝    ██║████╗  ██║██╔════╝██╔═══██╗██╔════╝ ████╗  ██║██║╚══██╔══╝██╔═══██╗
+    ██╔██╗ ██║██║ ╚███╔╝     ██║██╔██╗ ██║██║     ██║   ██║██║  ███╗██╔██╗ ██║██║   ██║   ██║   ██║
+    ██║╚██╗██║██║ ██╔██╗     ██║██║╚██╗██║██║     ██║   ██║██║   ██║██║╚██╗██║██║   ██║   ██║   ██║
+    ██║ ╚████║██║██╔╝ ██╗    ██║██║ ╚████║╚██████╗╚██████╔╝╚██████╔╝██║ ╚████║██║   ██║   ╚██████╔╝
+    ╚═╝  ╚═══╝╚═╝╚═╝  ╚═╝    ╚═╝╚═╝  ╚═══╝ ╚═════╝ ╚═════╝  ╚═════╝ ╚═╝  ╚═══╝╚═╝   ╚═╝    ╚═════╝    
+</red>     
+          =============================================================================== 
+          ||  🪪  Created by: dedsyn4ps3       ✨ Inspiration from: Kali (of course!)  ||
+          ===============================================================================
+
+    "#
+);
+
+/// **Operations that change the desktop**
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operation {
+    Enable,
+    Restore,
+}
+
+/// **Something the tool did, or is about to do**
+#[derive(Debug)]
+pub enum Event<'a> {
+    /// An operation on the settings of `config` started
+    Begin { operation: Operation, config: &'a str },
+    /// The settings backend that will be used
+    Backend(&'a str),
+    /// A problem found while checking the profile against the installed schemas
+    Issue(&'a Issue),
+    /// Incognito is already active, so the snapshot of the real desktop is kept
+    SnapshotKept(&'a State),
+    /// The current settings are being saved
+    Snapshotting,
+    /// The desktop still holds the settings of the snapshot of `State`, so no new one was taken
+    SnapshotReused(&'a State),
+    /// The current settings were saved to the snapshot of `State`
+    SnapshotSaved(&'a State),
+    /// The profile's keys are about to be written
+    Applying { profile: &'a str },
+    /// A GNOME Shell extension the profile needs isn't enabled
+    MissingExtension(&'a str),
+    /// The profile's theme or icon theme (`kind`) called `name` isn't installed
+    MissingAsset { kind: &'a str, name: &'a str },
+    /// The settings saved in `snapshot`, read from `path`, are about to be put back
+    Restoring { snapshot: &'a Snapshot, path: &'a Path, full: bool },
+    /// `total` keys are about to be written, each of which is then reported by a `Key` event
+    Writing { total: usize },
+    /// A managed key was changed while incognito was active
+    Conflict(&'a Conflict),
+    /// How that conflict was settled: `true` if the snapshot value replaces the current one
+    Resolved { key: &'a str, take_snapshot: bool },
+    /// What happened to a key once it was written and read back
+    Key { key: &'a str, outcome: &'a Outcome },
+    /// Something went wrong that doesn't stop the operation
+    Warning { message: &'a str, detail: String },
+    /// Every setting of the snapshot is back
+    Restored,
+    /// What an operation would change, without writing anything
+    Diff { title: &'a str, diff: &'a Diff, format: DiffFormat },
+    /// Whether the desktop is disguised; with `short`, only the disguise state is wanted
+    Status { status: &'a Status, short: bool },
+    /// The profiles that can be applied, as `(name, description, source)`
+    Profiles(&'a [(String, String, String)]),
+    /// A profile, with every value it writes
+    Profile { profile: &'a Profile, changes: &'a BTreeMap<String, Variant> },
+    /// The merged values of a layered profile, each coming from the layer `Profile::origin` names
+    Layers { profile: &'a Profile, changes: &'a BTreeMap<String, Variant> },
+    /// Recording the profile `name` started, with the current value of `keys` keys saved
+    RecordingStarted { name: &'a str, keys: usize },
+    /// The recording of the profile `name` was saved to `path`
+    Recorded { name: &'a str, recorded: &'a Recorded, path: &'a Path },
+    /// The recording of the profile `name` was stopped without saving anything
+    RecordingCancelled { name: &'a str },
+    /// The archived snapshots, oldest first, with the id of the one incognito was enabled from
+    Snapshots { snapshots: &'a [Snapshot], active: Option<&'a str> },
+    /// An archived snapshot, with every entry
+    Snapshot(&'a Snapshot),
+    /// The results of every `doctor` check
+    Checks(&'a [Check]),
+    /// The operation started by `Begin` is over
+    End,
+}
+
+/// **Receives the events of a command and presents them**
+pub trait Reporter {
+    /// Presents a single event
+    fn emit(&mut self, event: Event);
+
+    /// **Asks whether a conflicting key should take its snapshot value**
+    ///
+    /// ## Returns:
+    ///
+    /// The answer, or `None` if there is nobody to ask.
+    fn ask(&mut self, _conflict: &Conflict) -> Option<bool> {
+        None
+    }
+
+    /// **Reports how the command ended**
+    ///
+    /// ## Returns:
+    ///
+    /// The exit code of the command: `0`, or the code of its error.
+    fn finish(&mut self, result: Result<()>) -> i32;
+}
+
+/// **Creates the reporter for an output format**
+///
+/// ## Args:
+///
+/// * `format` - Format selected with `--output`
+/// * `silent` - Whether `--silent` was given, which only applies to human output
+/// * `command` - Name of the command, as reported in JSON output
+pub fn new(format: OutputFormat, silent: bool, command: &'static str) -> Box<dyn Reporter> {
+    match (format, silent) {
+        (OutputFormat::Json, _) => Box::new(Json::new(command)),
+        (_, true) => Box::new(Silent),
+        (OutputFormat::Plain, false) => {
+            colored::control::set_override(false);
+            Box::new(Plain)
+        }
+        (OutputFormat::Human, false) => Box::new(Human::default()),
+    }
+}
+
+/// Exit code of a command's result
+fn exit_code(result: &Result<()>) -> i32 {
+    result
+        .as_ref()
+        .err()
+        .map_or(0, Error::exit_code)
+}
+
+/// Text of a value that may be unset
+fn text(value: Option<&Variant>) -> String {
+    value.map_or_else(|| "(unset)".to_string(), Variant::to_string)
+}
+
+/// **The original colored output**, with the banner and the `Updating System` box around operations
+#[derive(Debug, Default)]
+pub struct Human {
+    /// Operation in progress, outside of which no box is drawn
+    operation: Option<Operation>,
+    /// Name of the backend, shown once the profile is applied
+    backend: Option<String>,
+    /// Spinner or progress bar of the step running right now
+    progress: Option<ProgressBar>,
+}
+
+impl Human {
+    /// Shows a spinner next to `message` until the step it stands for is over
+    fn spinner(&mut self, message: String) {
+        let pb = ProgressBar::with_draw_target(None, ProgressDrawTarget::stdout());
+        pb.set_style(
+            ProgressStyle::with_template("{msg} {spinner:.green} ")
+                .unwrap()
+                // For more spinners check out the cli-spinners project:
+                // https://github.com/sindresorhus/cli-spinners/blob/master/spinners.json
+                .tick_strings(&["⣾", "⣽", "⣻", "⢿", "⡿", "⣟", "⣯", "⣷"])
+        );
+        pb.set_message(message);
+        pb.enable_steady_tick(Duration::from_millis(120));
+        self.stop();
+        self.progress = Some(pb);
+    }
+
+    /// Shows a bar counting the `total` keys as they are written
+    fn bar(&mut self, total: usize) {
+        let pb = ProgressBar::with_draw_target(Some(total as u64), ProgressDrawTarget::stdout());
+        pb.set_style(
+            ProgressStyle::with_template("           {msg} [{bar:30.cyan/blue}] {pos}/{len}")
+                .unwrap()
+                .progress_chars("━╸ ")
+        );
+        pb.set_message(format!("⏳ {}", "Applying".bold()));
+        self.stop();
+        self.progress = Some(pb);
+    }
+
+    /// Removes the spinner or bar of the previous step, if any
+    fn stop(&mut self) {
+        if let Some(pb) = self.progress.take() {
+            pb.finish_and_clear();
+        }
+    }
+
+    /// Prints a line above the spinner or bar, so it isn't overwritten by the next tick
+    fn line(&self, line: impl Display) {
+        match &self.progress {
+            Some(pb) if !pb.is_hidden() => pb.println(line.to_string()),
+            _ => println!("{}", line),
+        }
+    }
+
+    /// Prints the disguise state, as `status` shows it
+    fn status(status: &Status) {
+        match status.disguise {
+            Disguise::Active => println!("🥷 {}", "Incognito is active".green().bold()),
+            Disguise::Partial => println!("⚠️ {}", "Incognito is partially applied".yellow().bold()),
+            Disguise::Inactive => println!("💤 {}", "Incognito is not active".bold()),
+        }
+
+        if let Some(state) = &status.state {
+            println!(
+                "   {}  {} {}",
+                "Snapshot ".dimmed(),
+                state.snapshot_id.cyan().bold(),
+                format!("({}, {})", state.timestamp, age(status.snapshot_age)).dimmed()
+            );
+        }
+        match (&status.snapshot, &status.snapshot_error) {
+            (Some(path), None) => println!("   {}  {}", "Config   ".dimmed(), path.display()),
+            (_, Some(why)) if status.disguise != Disguise::Inactive =>
+                println!("   {}  {} {}", "Config   ".dimmed(), "can't restore:".red().bold(), why),
+            _ => (),
+        }
+        if status.disguise != Disguise::Inactive {
+            println!("   {}  {}", "Profile  ".dimmed(), status.profile);
+            println!(
+                "   {}  {} keys, {} differ",
+                "Managed  ".dimmed(),
+                status.managed,
+                status.differing.len()
+            );
+        }
+
+        if status.disguise == Disguise::Partial {
+            println!();
+            for (key, current) in &status.differing {
+                println!("   ❌ {}  {}", key.bold(), text(current.as_ref()).dimmed());
+            }
+        }
+
+        // The state file and the desktop disagree, e.g. after settings were changed by hand
+        let recorded = status.state.as_ref().is_some_and(|state| state.active);
+        match (recorded, status.disguise) {
+            (true, Disguise::Inactive) =>
+                println!("\n   {}", "The saved state says incognito is on, but none of its settings are applied".dimmed()),
+            (false, Disguise::Active | Disguise::Partial) =>
+                println!("\n   {}", "The incognito settings are applied, but weren't applied by `enable`".dimmed()),
+            _ => (),
+        }
+    }
+}
+
+impl Human {
+    /// Prints the profiles that can be applied, as `profile list` shows them
+    fn profiles(profiles: &[(String, String, String)]) {
+        let width = profiles
+            .iter()
+            .map(|(name, _, _)| name.len())
+            .max()
+            .unwrap_or_default();
+
+        println!("🎭 {}", "Available profiles".magenta().bold());
+        for (name, description, source) in profiles {
+            let marker = match name == DEFAULT_PROFILE {
+                true => format!(" {}", "(default)".green().bold()),
+                false => String::new(),
+            };
+            println!(
+                "   {}  {} {}{}",
+                format!("{:<width$}", name).cyan().bold(),
+                description,
+                format!("[{}]", source).dimmed(),
+                marker
+            );
+        }
+    }
+
+    /// Prints the assets, required extensions and every value of a profile, as `profile show` shows them
+    fn profile(profile: &Profile, changes: &BTreeMap<String, Variant>) {
+        println!("🎭 {} {}", "Profile".magenta().bold(), profile.name.cyan().bold());
+        if !profile.description.is_empty() {
+            println!("   {}", profile.description.dimmed());
+        }
+        println!();
+        println!("   {}  {}", "Wallpaper ".dimmed(), profile.assets.wallpaper);
+        println!("   {}  {}", "Theme     ".dimmed(), profile.assets.theme);
+        println!("   {}  {}", "Icons     ".dimmed(), profile.assets.icons);
+        for extension in &profile.extensions {
+            println!("   {}  {}", "Extension ".dimmed(), extension);
+        }
+        println!();
+        for (key, value) in changes {
+            match profile.key(key).and_then(|key| key.gsettings()) {
+                Some(schema) => println!("   {} = {}  {}", key.bold(), value, format!("({})", schema).dimmed()),
+                None => println!("   {} = {}", key.bold(), value),
+            }
+        }
+    }
+
+    /// Prints the merged values of a layered profile, as `profile resolve` shows them
+    fn layers(profile: &Profile, changes: &BTreeMap<String, Variant>) {
+        let width = changes
+            .keys()
+            .map(|key| key.len())
+            .max()
+            .unwrap_or_default();
+
+        println!("🧬 {} {}", "Resolved profile".magenta().bold(), profile.name.cyan().bold());
+        println!();
+        for (key, value) in changes {
+            println!(
+                "   {} = {} {}",
+                format!("{:<width$}", key).bold(),
+                value,
+                format!("[{}]", profile.origin(key)).dimmed()
+            );
+        }
+    }
+
+    /// Prints what a finished recording saved, as `profile finish` shows it
+    fn recorded(name: &str, recorded: &Recorded, path: &Path) {
+        println!(
+            "💾 {} {} ({} keys)",
+            "Recorded profile".magenta().bold(),
+            name.cyan().bold(),
+            recorded.profile.dconf.len()
+        );
+        for (key, value) in &recorded.profile.dconf {
+            println!("   {} = {}", key.path().bold(), value);
+        }
+        if !recorded.reset.is_empty() {
+            println!();
+            println!("⚠️ {}", "These settings were reset to their default and can't be part of a profile:".yellow().bold());
+            for key in &recorded.reset {
+                println!("   {}", key);
+            }
+        }
+        println!();
+        println!("   Saved to {}", path.display().to_string().bold());
+        println!("   Apply it with {}", format!("nix-incognito enable --from {}", path.display()).bold());
+    }
+
+    /// Prints the archived snapshots, newest first, as `snapshot list` shows them
+    fn snapshots(snapshots: &[Snapshot], active: Option<&str>) {
+        if snapshots.is_empty() {
+            println!("👀 {}", "No snapshots saved yet. Enable Incognito to take one!".bold());
+            return;
+        }
+
+        println!("🗂️ {} ({})", "Saved snapshots".magenta().bold(), snapshots.len());
+        for snapshot in snapshots.iter().rev() {
+            let marker = match active == Some(snapshot.id.as_str()) {
+                true => format!(" {}", "(active)".green().bold()),
+                false => String::new(),
+            };
+            println!(
+                "   {}  {}  {} on {}  {} keys{}",
+                snapshot.id.cyan().bold(),
+                snapshot.timestamp.dimmed(),
+                snapshot.desktop,
+                snapshot.hostname,
+                snapshot.entries.len(),
+                marker
+            );
+        }
+    }
+
+    /// Prints the metadata and every entry of a snapshot, as `snapshot show` shows them
+    fn snapshot(snapshot: &Snapshot) {
+        println!("🗃️ {} {}", "Snapshot".magenta().bold(), snapshot.id.cyan().bold());
+        println!("   {}  {}", "Taken    ".dimmed(), snapshot.timestamp);
+        println!("   {}  {}", "Host     ".dimmed(), snapshot.hostname);
+        println!("   {}  {}", "Desktop  ".dimmed(), snapshot.desktop);
+        println!("   {}  {} (format {})", "Tool     ".dimmed(), snapshot.tool_version, snapshot.format);
+        println!("   {}  {}", "Keys     ".dimmed(), snapshot.entries.len());
+        println!();
+        for (key, value) in &snapshot.entries {
+            println!("   {} = {}", key.bold(), value);
+        }
+    }
+
+    /// Prints the result of every check, as `doctor` shows them
+    fn checks(checks: &[Check]) {
+        println!("🩺 {}", "Nix Incognito doctor".magenta().bold());
+        println!();
+        for check in checks {
+            let icon = match check.status {
+                doctor::Status::Ok => "✅",
+                doctor::Status::Warn => "⚠️",
+                doctor::Status::Fail => "❌",
+            };
+            println!("   {} {}  {}", icon, format!("{:<16}", check.name).bold(), check.detail);
+        }
+
+        println!();
+        if checks.iter().all(|check| check.status != doctor::Status::Fail) {
+            println!("   {}", "Ready to go incognito!".green().bold());
+        }
+    }
+}
+
+/// How long ago a snapshot taken `seconds` ago was taken, e.g. `3 days ago`
+fn age(seconds: Option<i64>) -> String {
+    let Some(seconds) = seconds else {
+        return "unknown age".to_string();
+    };
+
+    let (count, unit) = match seconds.max(0) {
+        seconds if seconds < 60 => return "just now".to_string(),
+        seconds if seconds < 3600 => (seconds / 60, "minute"),
+        seconds if seconds < 86400 => (seconds / 3600, "hour"),
+        seconds => (seconds / 86400, "day"),
+    };
+    format!("{} {}{} ago", count, unit, if count == 1 { "" } else { "s" })
+}
+
+impl Reporter for Human {
+    fn emit(&mut self, event: Event) {
+        match event {
+            Event::Begin { operation, .. } => {
+                self.operation = Some(operation);
+
+                // Clear terminal screen
+                print!("{esc}c", esc = 27 as char);
+                println!();
+                println!("{}", BANNER);
+                println!();
+
+                println!(
+                    "         ┏{rule} {} {rule}┓",
+                    "Updating System".yellow().bold(),
+                    rule = RULE.repeat(30)
+                );
+                if operation == Operation::Restore {
+                    println!();
+                    println!(
+                        "            🗃️ {}",
+                        "Restoring previous system settings...".magenta().bold()
+                    );
+                }
+            }
+            Event::Backend(name) => {
+                self.backend = Some(name.to_string());
+            }
+            Event::Issue(issue) =>
+                match issue.severity {
+                    Severity::Error => println!("           🚨 {} {}", format!("{}:", issue.key).bold().red(), issue.message),
+                    Severity::Warning => println!("           ⚠️ {} {}", format!("{}:", issue.key).yellow().bold(), issue.message),
+                }
+            Event::SnapshotKept(state) =>
+                println!(
+                    "\n           🔒 {} {} {}",
+                    "Incognito already active, keeping snapshot".bold(),
+                    state.snapshot_id.cyan().bold(),
+                    format!("({})", state.timestamp).dimmed()
+                ),
+            Event::SnapshotReused(state) => {
+                self.stop();
+                println!(
+                    "\n           🔒 {} {} {}",
+                    "Settings unchanged since snapshot".bold(),
+                    state.snapshot_id.cyan().bold(),
+                    format!("({}), keeping it", state.timestamp).dimmed()
+                );
+            }
+            Event::Snapshotting => {
+                println!();
+                self.spinner(format!("           💾 {} ", "Saving Current System Config".magenta().bold()));
+            }
+            Event::SnapshotSaved(_) => {
+                self.stop();
+                println!("\n           ✅ {}", "Successfully wrote config!".bold());
+            }
+            Event::Applying { profile } => {
+                println!("           🥷 {}  ", "Engaging Nix Incognito...".bold());
+                if let Some(backend) = &self.backend {
+                    println!("           ⚙️ {} {}", "Using backend:".dimmed(), backend.bold());
+                }
+                println!("           🎭 {} {}", "Using profile:".dimmed(), profile.bold());
+            }
+            Event::MissingExtension(uuid) =>
+                println!("           ⚠️ {} {}", "Extension not enabled:".yellow().bold(), uuid),
+            Event::MissingAsset { kind, name } =>
+                println!("           ⚠️ {} {}", format!("The {} isn't installed:", kind).yellow().bold(), name),
+            Event::Restoring { .. } if self.operation.is_some() => {
+                println!();
+                self.spinner(format!("            🛠️ {} ", "Loading Previous Config".cyan().bold()));
+            }
+            Event::Restoring { .. } => (),
+            Event::Writing { total } => {
+                self.stop();
+                println!();
+                if total > 0 {
+                    self.bar(total);
+                }
+            }
+            Event::Conflict(conflict) => {
+                println!(
+                    "\n            ⚠️ {} {}",
+                    "Changed while incognito was active:".yellow().bold(),
+                    conflict.key.bold()
+                );
+                println!(
+                    "               {}  {}",
+                    "snapshot ".dimmed(),
+                    text(conflict.snapshot.as_ref())
+                );
+                println!("               {}  {}", "incognito".dimmed(), conflict.applied);
+                println!(
+                    "               {}  {}",
+                    "current  ".dimmed(),
+                    text(conflict.current.as_ref())
+                );
+            }
+            Event::Resolved { take_snapshot, .. } =>
+                match take_snapshot {
+                    true => println!("               ↩️ {}", "Restoring snapshot value".bold()),
+                    false => println!("               📌 {}", "Keeping current value".bold()),
+                }
+            Event::Key { key, outcome } => {
+                let field = key.rsplit('/').next().unwrap_or(key);
+                let done = match self.operation {
+                    Some(Operation::Restore) => "restored!",
+                    _ => "updated!",
+                };
+                match outcome {
+                    Outcome::Applied => self.line(format!("           ✅ {}", format!("{} {}", field, done).bold().cyan())),
+                    Outcome::Unchanged => self.line(format!("           ➖ {}", format!("{} already set", field).dimmed())),
+                    Outcome::Failed(why) =>
+                        self.line(format!("           🚨 {} {}", format!("{} failed:", field).bold().red(), why)),
+                    Outcome::Skipped(why) =>
+                        self.line(format!("           ⏭️ {} {}", format!("{} skipped:", field).yellow().bold(), why)),
+                }
+
+                if let Some(pb) = &self.progress {
+                    pb.inc(1);
+                    if pb.length().is_some_and(|length| pb.position() >= length) {
+                        self.stop();
+                    }
+                }
+            }
+            Event::Warning { message, detail } if self.operation.is_some() =>
+                println!("\n           ⚠️ {} {}", message.yellow().bold(), detail),
+            Event::Warning { message, detail } => println!("⚠️ {} {}", message.yellow().bold(), detail),
+            Event::Restored => {
+                self.stop();
+                print!("\n            ✅ {}\n\n", "DONE! ".bold());
+            }
+            Event::Diff { title, diff, format } => {
+                if let Err(why) = diff::print(title, diff, format) {
+                    eprintln!("🚨 {}", why.to_string().bold().red());
+                }
+            }
+            Event::Status { status, short: true } => println!("{}", status.disguise.name()),
+            Event::Status { status, short: false } => Human::status(status),
+            Event::Profiles(profiles) => Human::profiles(profiles),
+            Event::Profile { profile, changes } => Human::profile(profile, changes),
+            Event::Layers { profile, changes } => Human::layers(profile, changes),
+            Event::RecordingStarted { name, keys } => {
+                println!("🎬 {} {}", "Recording profile".magenta().bold(), name.cyan().bold());
+                println!(
+                    "   Saved the current value of {} keys. Tweak your desktop, then run {} to keep what you changed",
+                    keys,
+                    "nix-incognito profile finish".bold()
+                );
+            }
+            Event::Recorded { name, recorded, path } => Human::recorded(name, recorded, path),
+            Event::RecordingCancelled { name } =>
+                println!("🗑️ {} {}", "Stopped recording".magenta().bold(), name.cyan().bold()),
+            Event::Snapshots { snapshots, active } => Human::snapshots(snapshots, active),
+            Event::Snapshot(snapshot) => Human::snapshot(snapshot),
+            Event::Checks(checks) => Human::checks(checks),
+            Event::End => {
+                self.stop();
+                self.operation = None;
+                println!("\n         ┗{}┛", RULE.repeat(77));
+            }
+        }
+    }
+
+    fn ask(&mut self, _conflict: &Conflict) -> Option<bool> {
+        if !io::stdin().is_terminal() {
+            return None;
+        }
+
+        print!("               {} ", "[k]eep current / [t]ake snapshot (k):".cyan().bold());
+        let _ = io::stdout().flush();
+
+        let mut answer = String::new();
+        let _ = io::stdin().read_line(&mut answer);
+        Some(matches!(answer.trim().to_lowercase().as_str(), "t" | "take" | "take snapshot"))
+    }
+
+    fn finish(&mut self, result: Result<()>) -> i32 {
+        self.stop();
+        if let Err(e) = &result {
+            eprintln!("🚨 {}", e.to_string().bold().red());
+        }
+        exit_code(&result)
+    }
+}
+
+/// **One uncolored line per event**, without banner, box, emoji or spinners
+#[derive(Debug, Default)]
+pub struct Plain;
+
+impl Reporter for Plain {
+    fn emit(&mut self, event: Event) {
+        match event {
+            Event::Begin { operation: Operation::Enable, config } => println!("Enabling incognito ({})", config),
+            Event::Begin { operation: Operation::Restore, config } => println!("Restoring previous settings ({})", config),
+            Event::Backend(name) => println!("Backend: {}", name),
+            Event::Issue(issue) =>
+                match issue.severity {
+                    Severity::Error => println!("error: {}: {}", issue.key, issue.message),
+                    Severity::Warning => println!("warning: {}: {}", issue.key, issue.message),
+                }
+            Event::SnapshotKept(state) =>
+                println!("Incognito already active, keeping snapshot {} ({})", state.snapshot_id, state.timestamp),
+            Event::SnapshotReused(state) =>
+                println!("Settings unchanged since snapshot {} ({}), keeping it", state.snapshot_id, state.timestamp),
+            Event::Snapshotting => println!("Saving current settings"),
+            Event::SnapshotSaved(state) => println!("Saved snapshot {} to {}", state.snapshot_id, state.snapshot),
+            Event::Applying { profile } => println!("Applying profile {}", profile),
+            Event::MissingExtension(uuid) => println!("warning: extension not enabled: {}", uuid),
+            Event::MissingAsset { kind, name } => println!("warning: {} not installed: {}", kind, name),
+            Event::Restoring { path, full: true, .. } => println!("Restoring every setting of {}", path.display()),
+            Event::Restoring { path, full: false, .. } => println!("Restoring managed settings of {}", path.display()),
+            Event::Writing { total } => println!("Writing {} settings", total),
+            Event::Conflict(conflict) =>
+                println!(
+                    "conflict: {} (snapshot {}, incognito {}, current {})",
+                    conflict.key,
+                    text(conflict.snapshot.as_ref()),
+                    conflict.applied,
+                    text(conflict.current.as_ref())
+                ),
+            Event::Resolved { key, take_snapshot: true } => println!("{}: restoring snapshot value", key),
+            Event::Resolved { key, take_snapshot: false } => println!("{}: keeping current value", key),
+            Event::Key { key, outcome } =>
+                match outcome {
+                    Outcome::Applied => println!("{}: applied", key),
+                    Outcome::Unchanged => println!("{}: unchanged", key),
+                    Outcome::Failed(why) => println!("{}: failed: {}", key, why),
+                    Outcome::Skipped(why) => println!("{}: skipped: {}", key, why),
+                }
+            Event::Warning { message, detail } => println!("warning: {} {}", message, detail),
+            Event::Restored => println!("Done"),
+            Event::Diff { title, diff, format } => {
+                if let Err(why) = diff::print(title, diff, format) {
+                    eprintln!("error: {}", why);
+                }
+            }
+            Event::Status { status, short: true } => println!("{}", status.disguise.name()),
+            Event::Status { status, short: false } => {
+                println!("status: {}", status.disguise.name());
+                if let Some(state) = &status.state {
+                    println!("snapshot: {} ({}, {})", state.snapshot_id, state.timestamp, age(status.snapshot_age));
+                }
+                match (&status.snapshot, &status.snapshot_error) {
+                    (_, Some(why)) => println!("config: unusable: {}", why),
+                    (Some(path), None) => println!("config: {}", path.display()),
+                    (None, None) => (),
+                }
+                println!("profile: {}", status.profile);
+                println!("managed: {} keys, {} differ", status.managed, status.differing.len());
+                for (key, current) in &status.differing {
+                    println!("differs: {} = {}", key, text(current.as_ref()));
+                }
+            }
+            Event::Profiles(profiles) => {
+                for (name, description, source) in profiles {
+                    let marker = if name == DEFAULT_PROFILE { " (default)" } else { "" };
+                    println!("{}: {} [{}]{}", name, description, source, marker);
+                }
+            }
+            Event::Profile { profile, changes } => {
+                println!("profile: {}", profile.name);
+                println!("description: {}", profile.description);
+                println!("wallpaper: {}", profile.assets.wallpaper);
+                println!("theme: {}", profile.assets.theme);
+                println!("icons: {}", profile.assets.icons);
+                for extension in &profile.extensions {
+                    println!("extension: {}", extension);
+                }
+                for (key, value) in changes {
+                    println!("{} = {}", key, value);
+                }
+            }
+            Event::Layers { profile, changes } => {
+                println!("profile: {}", profile.name);
+                for (key, value) in changes {
+                    println!("{} = {} [{}]", key, value, profile.origin(key));
+                }
+            }
+            Event::RecordingStarted { name, keys } => println!("Recording profile {} ({} keys saved)", name, keys),
+            Event::Recorded { name, recorded, path } => {
+                println!("Recorded profile {} ({} keys) to {}", name, recorded.profile.dconf.len(), path.display());
+                for (key, value) in &recorded.profile.dconf {
+                    println!("{} = {}", key.path(), value);
+                }
+                for key in &recorded.reset {
+                    println!("warning: reset to its default, left out: {}", key);
+                }
+            }
+            Event::RecordingCancelled { name } => println!("Stopped recording {}", name),
+            Event::Snapshots { snapshots, active } => {
+                for snapshot in snapshots.iter().rev() {
+                    let marker = if active == Some(snapshot.id.as_str()) { " (active)" } else { "" };
+                    println!(
+                        "{} {} {} on {} {} keys{}",
+                        snapshot.id,
+                        snapshot.timestamp,
+                        snapshot.desktop,
+                        snapshot.hostname,
+                        snapshot.entries.len(),
+                        marker
+                    );
+                }
+            }
+            Event::Snapshot(snapshot) => {
+                println!("snapshot: {}", snapshot.id);
+                println!("taken: {}", snapshot.timestamp);
+                println!("host: {}", snapshot.hostname);
+                println!("desktop: {}", snapshot.desktop);
+                println!("tool: {} (format {})", snapshot.tool_version, snapshot.format);
+                for (key, value) in &snapshot.entries {
+                    println!("{} = {}", key, value);
+                }
+            }
+            Event::Checks(checks) => {
+                for check in checks {
+                    println!("{} {}: {}", check.status.name(), check.name, check.detail);
+                }
+            }
+            Event::End => (),
+        }
+    }
+
+    fn finish(&mut self, result: Result<()>) -> i32 {
+        if let Err(e) = &result {
+            eprintln!("error: {}", e);
+        }
+        exit_code(&result)
+    }
+}
+
+/// **No output at all**, except for the error a command fails with
+#[derive(Debug, Default)]
+pub struct Silent;
+
+impl Reporter for Silent {
+    fn emit(&mut self, _event: Event) {}
+
+    fn finish(&mut self, result: Result<()>) -> i32 {
+        if let Err(e) = &result {
+            eprintln!("🚨 {}", e.to_string().bold().red());
+        }
+        exit_code(&result)
+    }
+}
+
+/// **Collects every event into a JSON `Document`**, printed once the command is done
+#[derive(Debug)]
+pub struct Json {
+    document: Document,
+    /// Outcome of every key written
+    keys: BTreeMap<String, Outcome>,
+    /// Fields holding a list of items, filled in as the events come
+    lists: BTreeMap<&'static str, Vec<serde_json::Value>>,
+}
+
+impl Json {
+    fn new(command: &'static str) -> Self {
+        Json { document: Document::new(command), keys: BTreeMap::new(), lists: BTreeMap::new() }
+    }
+
+    /// Appends `value` to the list field `name`
+    fn push(&mut self, name: &'static str, value: serde_json::Value) {
+        self.lists.entry(name).or_default().push(value);
+    }
+}
+
+/// The `snapshot` field of enable and restore documents
+fn snapshot_json(id: &str, path: &str, timestamp: &str) -> serde_json::Value {
+    json!({ "id": id, "path": path, "timestamp": timestamp })
+}
+
+impl Reporter for Json {
+    fn emit(&mut self, event: Event) {
+        match event {
+            Event::Begin { operation, config } => {
+                let operation = match operation {
+                    Operation::Enable => "enable",
+                    Operation::Restore => "restore",
+                };
+                self.document.set("operation", operation);
+                self.document.set("config", config);
+            }
+            Event::Backend(name) => self.document.set("backend", name),
+            Event::Issue(issue) => self.push("issues", json!(issue)),
+            Event::SnapshotKept(state) | Event::SnapshotReused(state) | Event::SnapshotSaved(state) =>
+                self.document.set("snapshot", snapshot_json(&state.snapshot_id, &state.snapshot, &state.timestamp)),
+            Event::Applying { profile } => self.document.set("profile", profile),
+            Event::MissingExtension(uuid) => self.push("missing_extensions", json!(uuid)),
+            Event::MissingAsset { kind, name } => self.push("missing_assets", json!({ "kind": kind, "name": name })),
+            Event::Restoring { snapshot, path, full } => {
+                let path = path.display().to_string();
+                self.document.set("snapshot", snapshot_json(&snapshot.id, &path, &snapshot.timestamp));
+                self.document.set("full", full);
+            }
+            Event::Conflict(_) => (),
+            Event::Resolved { key, take_snapshot } =>
+                self.push(
+                    "conflicts",
+                    json!({ "key": key, "kept": if take_snapshot { "snapshot" } else { "current" } })
+                ),
+            Event::Key { key, outcome } => {
+                self.keys.insert(key.to_string(), outcome.clone());
+            }
+            Event::Warning { message, detail } => self.push("warnings", json!(format!("{} {}", message, detail))),
+            Event::Snapshotting | Event::Writing { .. } | Event::Restored | Event::End => (),
+            Event::Diff { diff, .. } => self.document.set("diff", diff),
+            Event::Status { status, .. } => {
+                self.document.set("active", status.disguise == Disguise::Active);
+                if let serde_json::Value::Object(fields) = json!(status) {
+                    for (name, value) in fields {
+                        self.document.set(&name, value);
+                    }
+                }
+            }
+            Event::Profiles(profiles) => {
+                let listed: Vec<serde_json::Value> = profiles
+                    .iter()
+                    .map(|(name, description, source)| {
+                        json!({
+                            "name": name,
+                            "description": description,
+                            "source": source,
+                            "default": name == DEFAULT_PROFILE,
+                        })
+                    })
+                    .collect();
+                self.document.set("profiles", listed);
+            }
+            Event::Profile { profile, changes } => {
+                self.document.set("profile", &profile.name);
+                self.document.set("description", &profile.description);
+                self.document.set("assets", &profile.assets);
+                self.document.set("extensions", &profile.extensions);
+                self.document.set("settings", changes);
+            }
+            Event::Layers { profile, changes } => {
+                self.document.set("profile", &profile.name);
+                let settings: BTreeMap<&String, serde_json::Value> = changes
+                    .iter()
+                    .map(|(key, value)| (key, json!({ "value": value, "origin": profile.origin(key) })))
+                    .collect();
+                self.document.set("settings", settings);
+            }
+            Event::RecordingStarted { name, keys } => {
+                self.document.set("recording", name);
+                self.document.set("saved_keys", keys);
+            }
+            Event::Recorded { name, recorded, path } => {
+                self.document.set("profile", name);
+                self.document.set("path", path);
+                let settings: BTreeMap<&str, &String> = recorded.profile.dconf
+                    .iter()
+                    .map(|(key, value)| (key.path(), value))
+                    .collect();
+                self.document.set("settings", settings);
+                self.document.set("reset", &recorded.reset);
+            }
+            Event::RecordingCancelled { name } => self.document.set("recording", name),
+            Event::Snapshots { snapshots, active } => {
+                let listed: Vec<serde_json::Value> = snapshots
+                    .iter()
+                    .rev()
+                    .map(|snapshot| {
+                        json!({
+                            "id": snapshot.id,
+                            "timestamp": snapshot.timestamp,
+                            "hostname": snapshot.hostname,
+                            "desktop": snapshot.desktop,
+                            "keys": snapshot.entries.len(),
+                            "active": active == Some(snapshot.id.as_str()),
+                        })
+                    })
+                    .collect();
+                self.document.set("snapshots", listed);
+            }
+            Event::Snapshot(snapshot) => self.document.set("snapshot", snapshot),
+            Event::Checks(checks) => self.document.set("checks", checks),
+        }
+    }
+
+    fn finish(&mut self, result: Result<()>) -> i32 {
+        let mut document = std::mem::replace(&mut self.document, Document::new(""));
+        if !self.keys.is_empty() {
+            document.set("keys", &self.keys);
+        }
+        for (name, items) in &self.lists {
+            document.set(name, items);
+        }
+        document.finish(result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::backend::MemoryBackend;
+
+    #[test]
+    fn enable_and_restore_report_the_same_snapshot_object() {
+        let config = "/home/user/.config/incognito/current_system_config.toml";
+        let snapshot = Snapshot::capture(&MemoryBackend::default()).unwrap();
+
+        let mut enable = Json::new("enable");
+        enable.emit(Event::SnapshotSaved(&State::activated(&snapshot, config)));
+        let mut restore = Json::new("restore");
+        restore.emit(Event::Restoring { snapshot: &snapshot, path: Path::new(config), full: false });
+
+        let expected = json!({ "id": snapshot.id, "path": config, "timestamp": snapshot.timestamp });
+        assert_eq!(enable.document.get("snapshot"), Some(&expected));
+        assert_eq!(restore.document.get("snapshot"), Some(&expected));
+    }
+
+    #[test]
+    fn listings_are_reported_newest_first_with_their_markers() {
+        let older = Snapshot::capture(&MemoryBackend::default()).unwrap();
+        let mut newer = older.clone();
+        newer.id = format!("{}-2", older.id);
+
+        let mut json = Json::new("snapshot");
+        json.emit(Event::Snapshots { snapshots: &[older.clone(), newer.clone()], active: Some(&older.id) });
+        let listed = json.document.get("snapshots").and_then(|listed| listed.as_array()).unwrap();
+        assert_eq!(listed[0]["id"], json!(newer.id));
+        assert_eq!(listed[0]["active"], json!(false));
+        assert_eq!(listed[1]["active"], json!(true));
+
+        let profiles = [
+            ("mine".to_string(), "My look".to_string(), "user".to_string()),
+            (DEFAULT_PROFILE.to_string(), "Default look".to_string(), "built-in".to_string()),
+        ];
+        let mut json = Json::new("profile");
+        json.emit(Event::Profiles(&profiles));
+        let listed = json.document.get("profiles").and_then(|listed| listed.as_array()).unwrap();
+        assert_eq!(listed[0], json!({ "name": "mine", "description": "My look", "source": "user", "default": false }));
+        assert_eq!(listed[1]["default"], json!(true));
+    }
+    #[test]
+    fn doctor_checks_go_through_the_reporter() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = dir.path().join("current_system_config.toml").to_string_lossy().into_owned();
+        let profile = Profile::builtin(DEFAULT_PROFILE).unwrap();
+
+        let mut json = Json::new("doctor");
+        let _ = doctor::run(&config, &profile, &mut json);
+        let checks = json.document.get("checks").and_then(|checks| checks.as_array()).unwrap();
+        assert_eq!(checks[0]["name"], json!("Session bus"));
+        assert!(checks.iter().all(|check| matches!(check["status"].as_str(), Some("ok" | "warn" | "fail"))));
+        assert!(checks.iter().any(|check| check["name"] == json!("Snapshot") && check["status"] == json!("ok")));
+    }
+}
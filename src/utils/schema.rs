@@ -0,0 +1,520 @@
+//! # GSettings Schema Module
+//! This module reads the **GSettings schemas** installed on the system and checks the keys and values a
+//! profile is about to write against them, so mistakes are reported precisely before anything changes
+//! instead of as a generic write error (or, with the dconf backends, not at all).
+//!
+//! Schemas are read from their XML sources (`*.gschema.xml`, with enums in `*.enums.xml`) in the same
+//! places GLib looks for them: `GSETTINGS_SCHEMA_DIR`, then `glib-2.0/schemas` below the user data directory
+//! and every entry of `XDG_DATA_DIRS`. NixOS keeps each package's schemas in
+//! `gsettings-schemas/<package>/glib-2.0/schemas` and GNOME Shell extensions ship theirs in
+//! `gnome-shell/extensions/<uuid>/schemas`, so those are searched as well. When several directories provide
+//! the same schema, the first one wins.
+//!
+//! For every key, **`Schemas::validate`** checks that:
+//!
+//! * a schema describes the key's dconf path (or, for keys addressed by schema, that the schema exists
+//!   and stores its keys at that path, or is relocatable and was given one),
+//! * the schema has a key with that name,
+//! * the value has the key's type (both backends store values with the type they were written with, so
+//!   a bare `1` is an `int32` and needs to be written as `uint32 1` or `1.0` for other numeric keys), and
+//! * the value honours the key's `<choices>`, `<range>`, `enum` or `flags` constraint.
+use crate::utils::doctor;
+use crate::utils::error::Result;
+use crate::utils::gvariant::{ self, Variant };
+use crate::utils::key;
+use crate::utils::profile::Profile;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::path::{ Path, PathBuf };
+
+/// **A schema and the keys it defines**
+#[derive(Debug, Clone, PartialEq)]
+pub struct Schema {
+    /// Schema id (e.g. `org.gnome.desktop.interface`)
+    pub id: String,
+    /// dconf directory the keys are stored in, `None` for relocatable schemas
+    pub path: Option<String>,
+    /// Keys by name
+    pub keys: BTreeMap<String, Key>,
+    /// Id of the schema this one extends, if any
+    extends: Option<String>,
+}
+
+/// **A key defined by a schema**
+#[derive(Debug, Clone, PartialEq)]
+pub struct Key {
+    /// GVariant type string of the key's values
+    pub type_string: String,
+    /// Restriction on the values the key accepts
+    pub constraint: Constraint,
+}
+
+/// **Restriction on the values a key accepts**
+#[derive(Debug, Clone, PartialEq)]
+pub enum Constraint {
+    /// Any value of the key's type
+    None,
+    /// One of the listed strings
+    Choices(Vec<String>),
+    /// A number between the two bounds, in the key's type
+    Range(Variant, Variant),
+    /// One of the nicks of the named `<enum>`
+    Enum(String),
+    /// Any combination of the nicks of the named `<flags>`
+    Flags(String),
+}
+
+/// **How serious a validation issue is**
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    /// The value can't be checked, e.g. because no installed schema describes the key
+    Warning,
+    /// The write would fail or store a value the desktop rejects
+    Error,
+}
+
+/// **A problem found with a key a profile writes**
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Issue {
+    /// Full dconf path of the key
+    pub key: String,
+    pub severity: Severity,
+    pub message: String,
+}
+
+/// **Every schema installed on the system**
+#[derive(Debug, Clone, Default)]
+pub struct Schemas {
+    /// Schemas by id
+    schemas: BTreeMap<String, Schema>,
+    /// Nicks of every `<enum>` and `<flags>` by id
+    enums: BTreeMap<String, Vec<String>>,
+}
+
+impl Schemas {
+    /// **Reads every installed schema**
+    ///
+    /// Files that can't be read or parsed are skipped, so one broken package never hides the others.
+    pub fn load() -> Self {
+        let mut schemas = Schemas::default();
+
+        for dir in schema_dirs() {
+            let mut files: Vec<PathBuf> = fs
+                ::read_dir(&dir)
+                .map(|entries| {
+                    entries
+                        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+                        .filter(|path| {
+                            let name = path.to_string_lossy();
+                            name.ends_with(".gschema.xml") || name.ends_with(".enums.xml")
+                        })
+                        .collect()
+                })
+                .unwrap_or_default();
+            files.sort();
+
+            for file in files {
+                if let Ok(contents) = fs::read_to_string(&file) {
+                    schemas.parse(&contents);
+                }
+            }
+        }
+
+        schemas.inherit();
+        schemas
+    }
+
+    /// Whether no schema was found at all
+    pub fn is_empty(&self) -> bool {
+        self.schemas.is_empty()
+    }
+
+    /// Returns the schema with the given `id`
+    pub fn get(&self, id: &str) -> Option<&Schema> {
+        self.schemas.get(id)
+    }
+
+    /// **Finds the schema that stores its keys in the dconf directory `dir`** (e.g. `/org/gnome/mutter/`)
+    pub fn at_path(&self, dir: &str) -> Option<&Schema> {
+        self.schemas.values().find(|schema| schema.path.as_deref() == Some(dir))
+    }
+
+    /// **Checks every key `profile` writes against the installed schemas**
+    ///
+    /// ## Returns:
+    ///
+    /// Every issue found, errors first, or an `Error` if the profile's values can't be built.
+    pub fn validate(&self, profile: &Profile) -> Result<Vec<Issue>> {
+        let mut issues: Vec<Issue> = profile
+            .changes()?
+            .iter()
+            .filter_map(|(path, value)| {
+                let key = profile.key(path)?;
+                self.check(key, value).err().map(|(severity, message)| Issue {
+                    key: path.clone(),
+                    severity,
+                    message,
+                })
+            })
+            .collect();
+        issues.sort_by_key(|issue| issue.severity != Severity::Error);
+
+        Ok(issues)
+    }
+
+    /// **Checks a single key and value**
+    ///
+    /// ## Args:
+    ///
+    /// * `key` - The key, as the profile addressed it
+    /// * `value` - Value the key would be set to
+    pub fn check(&self, key: &key::Key, value: &Variant) -> std::result::Result<(), (Severity, String)> {
+        let (dir, name) = (key.dir(), key.name());
+
+        let schema = match key.schema() {
+            Some(id) => {
+                let schema = self.get(id).ok_or((
+                    Severity::Warning,
+                    format!("schema {} is not installed, so the value can't be checked", id),
+                ))?;
+                match schema.path.as_deref() {
+                    Some(path) if path != dir =>
+                        Err((Severity::Error, format!("schema {} stores its keys in {}, not {}", id, path, dir))),
+                    None if !key.is_relocated() =>
+                        Err((
+                            Severity::Error,
+                            format!("schema {} is relocatable, give its path as {}:/path/to/dir/", id, id),
+                        )),
+                    _ => Ok(schema),
+                }?
+            }
+            None =>
+                self
+                    .at_path(dir)
+                    .ok_or((
+                        Severity::Warning,
+                        format!("no installed schema describes {}, so the value can't be checked", dir),
+                    ))?,
+        };
+
+        let definition = schema.keys
+            .get(name)
+            .ok_or((Severity::Error, format!("schema {} has no key named {}", schema.id, name)))?;
+        if value.type_string() != definition.type_string {
+            return Err((
+                Severity::Error,
+                format!(
+                    "{} has type '{}', but the key expects '{}'",
+                    value,
+                    value.type_string(),
+                    definition.type_string
+                ),
+            ));
+        }
+
+        self.check_constraint(&definition.constraint, value).map_err(|why| (Severity::Error, why))
+    }
+
+    /// Checks a parsed value against a key's constraint
+    fn check_constraint(&self, constraint: &Constraint, value: &Variant) -> std::result::Result<(), String> {
+        let allowed = |choices: &[String], value: &str| -> std::result::Result<(), String> {
+            match choices.iter().any(|choice| choice == value) {
+                true => Ok(()),
+                false => Err(format!("'{}' is not one of {}", value, choices.join(", "))),
+            }
+        };
+
+        match constraint {
+            Constraint::None => Ok(()),
+            Constraint::Choices(choices) =>
+                strings(value)
+                    .iter()
+                    .try_for_each(|value| allowed(choices, value)),
+            Constraint::Enum(id) | Constraint::Flags(id) => {
+                let nicks = self.enums.get(id).ok_or(format!("enum {} is not installed", id))?;
+                strings(value)
+                    .iter()
+                    .try_for_each(|value| allowed(nicks, value))
+            }
+            Constraint::Range(min, max) =>
+                match (number(value), number(min), number(max)) {
+                    (Some(number), Some(low), Some(high)) if number < low || number > high =>
+                        Err(format!("{} is outside of the range {} to {}", value, min, max)),
+                    _ => Ok(()),
+                }
+        }
+    }
+
+    /// Adds the schemas, enums and flags defined in a schema file, skipping it if it isn't valid XML
+    fn parse(&mut self, contents: &str) {
+        let document = match roxmltree::Document::parse(contents) {
+            Ok(document) => document,
+            Err(_) => {
+                return;
+            }
+        };
+
+        for node in document.root_element().children().filter(|node| node.is_element()) {
+            match node.tag_name().name() {
+                "enum" | "flags" => {
+                    let nicks = node
+                        .children()
+                        .filter(|child| child.has_tag_name("value"))
+                        .filter_map(|child| child.attribute("nick").map(String::from))
+                        .collect();
+                    if let Some(id) = node.attribute("id") {
+                        self.enums.entry(id.to_string()).or_insert(nicks);
+                    }
+                }
+                "schema" => {
+                    let Some(id) = node.attribute("id") else {
+                        continue;
+                    };
+                    let keys = node
+                        .children()
+                        .filter(|child| child.has_tag_name("key"))
+                        .filter_map(|key| Some((key.attribute("name")?.to_string(), parse_key(key)?)))
+                        .collect();
+
+                    self.schemas.entry(id.to_string()).or_insert(Schema {
+                        id: id.to_string(),
+                        path: node.attribute("path").map(String::from),
+                        keys,
+                        extends: node.attribute("extends").map(String::from),
+                    });
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Copies the keys of extended schemas into the schemas that extend them
+    fn inherit(&mut self) {
+        let ids: Vec<String> = self.schemas.keys().cloned().collect();
+
+        for id in ids {
+            let mut inherited = BTreeMap::new();
+            let mut parent = self.schemas[&id].extends.clone();
+            let mut seen = vec![id.clone()];
+
+            while let Some(parent_id) = parent.filter(|parent_id| !seen.contains(parent_id)) {
+                let Some(schema) = self.schemas.get(&parent_id) else {
+                    break;
+                };
+                for (name, key) in &schema.keys {
+                    inherited.entry(name.clone()).or_insert_with(|| key.clone());
+                }
+                parent = schema.extends.clone();
+                seen.push(parent_id);
+            }
+
+            if let Some(schema) = self.schemas.get_mut(&id) {
+                for (name, key) in inherited {
+                    schema.keys.entry(name).or_insert(key);
+                }
+            }
+        }
+    }
+}
+
+/// Reads a `<key>` element, `None` if it has no usable type
+fn parse_key(node: roxmltree::Node) -> Option<Key> {
+    let child = |name: &str| node.children().find(|child| child.has_tag_name(name));
+
+    if let Some(id) = node.attribute("enum") {
+        return Some(Key { type_string: "s".into(), constraint: Constraint::Enum(id.to_string()) });
+    }
+    if let Some(id) = node.attribute("flags") {
+        return Some(Key { type_string: "as".into(), constraint: Constraint::Flags(id.to_string()) });
+    }
+
+    let type_string = node.attribute("type")?.to_string();
+    let bound = |name: &str| {
+        child("range")
+            .and_then(|range| range.attribute(name))
+            .and_then(|text| gvariant::parse_as(text, &type_string).ok())
+    };
+
+    let constraint = if let (Some(min), Some(max)) = (bound("min"), bound("max")) {
+        Constraint::Range(min, max)
+    } else if let Some(choices) = child("choices") {
+        Constraint::Choices(
+            choices
+                .children()
+                .filter(|choice| choice.has_tag_name("choice"))
+                .filter_map(|choice| choice.attribute("value").map(String::from))
+                .collect()
+        )
+    } else {
+        Constraint::None
+    };
+
+    Some(Key { type_string, constraint })
+}
+
+/// Strings a `<choices>`, `enum` or `flags` constraint applies to: the value itself, or its elements
+fn strings(value: &Variant) -> Vec<String> {
+    match value {
+        Variant::String(string) => vec![string.clone()],
+        Variant::Maybe(_, Some(child)) => strings(child),
+        Variant::Array(_, elements) => elements.iter().flat_map(strings).collect(),
+        _ => Vec::new(),
+    }
+}
+
+/// Numeric value of a number, for `<range>` checks
+fn number(value: &Variant) -> Option<f64> {
+    Some(match value {
+        Variant::Byte(number) => (*number).into(),
+        Variant::Int16(number) => (*number).into(),
+        Variant::Uint16(number) => (*number).into(),
+        Variant::Int32(number) => (*number).into(),
+        Variant::Uint32(number) => (*number).into(),
+        Variant::Int64(number) => *number as f64,
+        Variant::Uint64(number) => *number as f64,
+        Variant::Double(number) => *number,
+        _ => {
+            return None;
+        }
+    })
+}
+
+/// **Directories schemas are read from**, in order of precedence
+pub fn schema_dirs() -> Vec<PathBuf> {
+    let mut dirs: Vec<PathBuf> = env
+        ::var("GSETTINGS_SCHEMA_DIR")
+        .map(|dirs| dirs.split(':').filter(|dir| !dir.is_empty()).map(PathBuf::from).collect())
+        .unwrap_or_default();
+
+    for data in doctor::data_dirs() {
+        dirs.push(data.join("glib-2.0").join("schemas"));
+        dirs.extend(subdirs(&data.join("gsettings-schemas"), &["glib-2.0", "schemas"]));
+        dirs.extend(subdirs(&data.join("gnome-shell").join("extensions"), &["schemas"]));
+    }
+
+    dirs
+}
+
+/// `<dir>/<entry>/<rest...>` for every entry of `dir`, sorted
+fn subdirs(dir: &Path, rest: &[&str]) -> Vec<PathBuf> {
+    let mut paths: Vec<PathBuf> = fs
+        ::read_dir(dir)
+        .map(|entries| {
+            entries
+                .filter_map(|entry| entry.ok())
+                .map(|entry| rest.iter().fold(entry.path(), |path, part| path.join(part)))
+                .collect()
+        })
+        .unwrap_or_default();
+    paths.sort();
+    paths
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::key::Key;
+
+    const SCHEMAS: &str = r#"
+        <schemalist>
+          <enum id="org.example.Position">
+            <value nick="left" value="0"/>
+            <value nick="right" value="1"/>
+          </enum>
+          <flags id="org.example.Buttons">
+            <value nick="minimize" value="1"/>
+            <value nick="close" value="2"/>
+          </flags>
+          <schema id="org.example.desktop" path="/org/example/desktop/">
+            <key name="theme" type="s"/>
+            <key name="scale" type="d"><range min="0.5" max="3.0"/></key>
+            <key name="mode" type="s">
+              <choices><choice value="light"/><choice value="dark"/></choices>
+            </key>
+            <key name="position" enum="org.example.Position"/>
+            <key name="buttons" flags="org.example.Buttons"/>
+            <key name="untyped"/>
+          </schema>
+          <schema id="org.example.panel" path="/org/example/panel/" extends="org.example.desktop">
+            <key name="size" type="i"/>
+          </schema>
+          <schema id="org.example.profile">
+            <key name="name" type="s"/>
+          </schema>
+        </schemalist>
+    "#;
+
+    fn schemas() -> Schemas {
+        let mut schemas = Schemas::default();
+        schemas.parse(SCHEMAS);
+        schemas.parse("<schemalist><schema id=\"org.example.broken\"");
+        schemas.inherit();
+        schemas
+    }
+
+    /// Severity of the issue with writing `value` to `key`, `None` if there is none
+    fn check(key: Result<Key>, value: &str) -> Option<Severity> {
+        schemas()
+            .check(&key.unwrap(), &gvariant::parse(value).unwrap())
+            .err()
+            .map(|(severity, _)| severity)
+    }
+
+    fn at(path: &str) -> Result<Key> {
+        Key::from_path(path)
+    }
+
+    #[test]
+    fn checks_values_against_the_type_of_the_key() {
+        assert_eq!(check(at("/org/example/desktop/theme"), "'Adwaita'"), None);
+        assert_eq!(check(at("/org/example/desktop/theme"), "1"), Some(Severity::Error));
+        assert_eq!(check(at("/org/example/desktop/scale"), "1"), Some(Severity::Error));
+        assert_eq!(check(at("/org/example/panel/size"), "uint32 1"), Some(Severity::Error));
+    }
+
+    #[test]
+    fn checks_ranges_choices_enums_and_flags() {
+        assert_eq!(check(at("/org/example/desktop/scale"), "1.5"), None);
+        assert_eq!(check(at("/org/example/desktop/scale"), "3.5"), Some(Severity::Error));
+        assert_eq!(check(at("/org/example/desktop/scale"), "0.25"), Some(Severity::Error));
+        assert_eq!(check(at("/org/example/desktop/mode"), "'dark'"), None);
+        assert_eq!(check(at("/org/example/desktop/mode"), "'blue'"), Some(Severity::Error));
+        assert_eq!(check(at("/org/example/desktop/position"), "'right'"), None);
+        assert_eq!(check(at("/org/example/desktop/position"), "'top'"), Some(Severity::Error));
+        assert_eq!(check(at("/org/example/desktop/buttons"), "['close', 'minimize']"), None);
+        assert_eq!(check(at("/org/example/desktop/buttons"), "['close', 'maximize']"), Some(Severity::Error));
+    }
+
+    #[test]
+    fn checks_relocatable_schemas_are_given_a_path() {
+        let profile = |schema: &str| Key::from_schema(schema, "name");
+
+        assert_eq!(check(profile("org.example.profile"), "'Work'"), Some(Severity::Error));
+        assert_eq!(check(profile("org.example.profile:/org/example/profiles/work/"), "'Work'"), None);
+        assert_eq!(check(profile("org.example.profile:/org/example/profiles/work/"), "1"), Some(Severity::Error));
+        assert_eq!(check(Key::from_schema("org.example.desktop", "theme"), "'Adwaita'"), None);
+        assert_eq!(check(Key::from_schema("org.example.desktop:/org/other/", "theme"), "'Adwaita'"), Some(Severity::Error));
+    }
+
+    #[test]
+    fn reports_unknown_keys_and_schemas() {
+        // Keys nothing describes can't be checked, keys missing from a known schema are mistakes
+        assert_eq!(check(at("/org/unknown/theme"), "'Adwaita'"), Some(Severity::Warning));
+        assert_eq!(check(Key::from_schema("org.unknown", "theme"), "'Adwaita'"), Some(Severity::Warning));
+        assert_eq!(check(at("/org/example/desktop/missing"), "'Adwaita'"), Some(Severity::Error));
+        assert_eq!(check(at("/org/example/desktop/untyped"), "'Adwaita'"), Some(Severity::Error));
+        assert!(schemas().get("org.example.broken").is_none());
+    }
+
+    #[test]
+    fn inherits_the_keys_of_extended_schemas() {
+        let schemas = schemas();
+
+        assert_eq!(schemas.at_path("/org/example/panel/").unwrap().id, "org.example.panel");
+        assert_eq!(schemas.get("org.example.panel").unwrap().keys.len(), 6);
+        assert_eq!(check(at("/org/example/panel/mode"), "'light'"), None);
+    }
+}
@@ -0,0 +1,427 @@
+//! # Snapshot Module
+//! This module defines the **`Snapshot`** format used to save the desktop configuration before incognito
+//! mode is enabled.
+//!
+//! Snapshots are TOML files that record where and when they were taken (tool version, timestamp, hostname
+//! and desktop environment) along with every dconf key and its value, written in GVariant text format:
+//!
+//! ```toml
+//! format = 1
+//! id = "20240501-093000-417"
+//! tool_version = "0.2.2"
+//! timestamp = "2024-05-01T09:30:00+02:00"
+//! hostname = "workstation"
+//! desktop = "GNOME"
+//!
+//! [entries]
+//! "/org/gnome/desktop/interface/icon-theme" = "'Adwaita'"
+//! ```
+//!
+//! Older versions of the tool wrote the raw output of `dconf dump /` to a `.txt` file instead. Those files
+//! are still accepted by **`Snapshot::load`**, so existing users can restore their desktop after upgrading.
+//!
+//! Besides the config file used for restores, every snapshot is archived in a **`Store`** under
+//! `$XDG_STATE_HOME/incognito/snapshots` as `<id>.toml`, pruned according to a **`Retention`** policy.
+//! Ids are derived from the time the snapshot was taken, down to the millisecond, and archived snapshots are
+//! never overwritten.
+use crate::utils::backend::SettingsBackend;
+use crate::utils::error::{ Error, Result };
+use crate::utils::gvariant::Variant;
+use crate::utils::keyfile::Keyfile;
+use chrono::{ DateTime, Duration, Local, SecondsFormat };
+use serde::{ Deserialize, Serialize };
+use std::collections::BTreeMap;
+use std::env;
+use std::fs::{ self, OpenOptions };
+use std::io::Write;
+use std::path::{ Path, PathBuf };
+
+/// Version of the snapshot format written by this build of the tool
+pub const FORMAT_VERSION: u32 = 1;
+
+/// Metadata value used when a field is unknown (e.g. for legacy snapshots)
+const UNKNOWN: &str = "unknown";
+
+/// Format of snapshot ids, e.g. `20240501-093000-417`
+const ID_FORMAT: &str = "%Y%m%d-%H%M%S-%3f";
+
+/// **A saved desktop configuration**
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Snapshot {
+    /// Version of the snapshot format, `0` for legacy dumps
+    pub format: u32,
+    /// Identifier derived from the time the snapshot was taken
+    pub id: String,
+    /// Version of the tool that took the snapshot
+    pub tool_version: String,
+    /// When the snapshot was taken, in RFC 3339 format
+    pub timestamp: String,
+    /// Host the snapshot was taken on
+    pub hostname: String,
+    /// Desktop environment detected when the snapshot was taken
+    pub desktop: String,
+    /// Every key below `/`, mapped to its value
+    pub entries: BTreeMap<String, Variant>,
+}
+
+impl Snapshot {
+    /// **Takes a snapshot of every key stored by the backend**
+    pub fn capture(backend: &dyn SettingsBackend) -> Result<Self> {
+        let now = Local::now();
+
+        Ok(Snapshot {
+            format: FORMAT_VERSION,
+            id: now.format(ID_FORMAT).to_string(),
+            tool_version: env!("CARGO_PKG_VERSION").to_string(),
+            timestamp: now.to_rfc3339_opts(SecondsFormat::Secs, false),
+            hostname: hostname(),
+            desktop: desktop(),
+            entries: Keyfile::parse("/", &backend.dump("/")?)?.values()?,
+        })
+    }
+
+    /// **Reads a snapshot file**, accepting both the structured format and legacy `dconf dump` output
+    ///
+    /// ## Returns:
+    ///
+    /// The snapshot, or an `Error` if the file can't be read or is in neither format.
+    pub fn load(path: &Path) -> Result<Self> {
+        let contents = fs
+            ::read_to_string(path)
+            .map_err(|why| Error::io("read", path, why))?;
+
+        match toml::from_str::<Snapshot>(&contents) {
+            Ok(snapshot) if snapshot.format > FORMAT_VERSION =>
+                Err(
+                    Error::corrupt(
+                        path,
+                        format!(
+                            "it uses snapshot format {}, but this version only supports up to {}",
+                            snapshot.format,
+                            FORMAT_VERSION
+                        )
+                    )
+                ),
+            Ok(snapshot) => Ok(snapshot),
+            Err(_) if !contents.contains("\nformat =") && !contents.starts_with("format =") => {
+                Snapshot::from_legacy(path, &contents)
+            }
+            Err(why) => Err(Error::corrupt(path, why)),
+        }
+    }
+
+    /// Builds a snapshot from the raw `dconf dump /` output written by earlier versions of the tool
+    fn from_legacy(path: &Path, contents: &str) -> Result<Self> {
+        let entries = Keyfile
+            ::parse("/", contents)
+            .and_then(|keyfile| keyfile.values())
+            .map_err(|why| Error::corrupt(path, why))?;
+        let timestamp = fs
+            ::metadata(path)
+            .and_then(|metadata| metadata.modified())
+            .map(DateTime::<Local>::from);
+
+        Ok(Snapshot {
+            format: 0,
+            id: match &timestamp {
+                Ok(modified) => modified.format(ID_FORMAT).to_string(),
+                Err(_) => "legacy".to_string(),
+            },
+            tool_version: UNKNOWN.to_string(),
+            timestamp: match &timestamp {
+                Ok(modified) => modified.to_rfc3339_opts(SecondsFormat::Secs, false),
+                Err(_) => UNKNOWN.to_string(),
+            },
+            hostname: UNKNOWN.to_string(),
+            desktop: UNKNOWN.to_string(),
+            entries,
+        })
+    }
+
+    /// **Serializes the snapshot** in the structured format
+    ///
+    /// The text is read back before it's returned, so a value that wouldn't survive the round trip is
+    /// reported now, before any setting is changed, rather than when the snapshot is needed to restore.
+    pub fn to_toml(&self) -> Result<String> {
+        let unsaveable = |detail: String| Error::Other(format!("Couldn't serialize snapshot {}: {}", self.id, detail));
+        let text = toml::to_string(self).map_err(|why| unsaveable(why.to_string()))?;
+        let read_back = toml::from_str::<Snapshot>(&text).map_err(|why| unsaveable(why.to_string()))?;
+
+        match self.entries.iter().find(|(key, value)| read_back.entries.get(*key) != Some(value)) {
+            Some((key, value)) => Err(unsaveable(format!("{} = {} doesn't read back the same", key, value))),
+            None if read_back != *self => Err(unsaveable("its metadata doesn't read back the same".into())),
+            None => Ok(text),
+        }
+    }
+}
+
+/// **Locates the snapshot to restore from**
+///
+/// Returns `config` itself if it exists, otherwise the legacy `.txt` snapshot next to it, if any.
+pub fn locate(config: &str) -> Option<PathBuf> {
+    let path = PathBuf::from(config);
+    let legacy = path.with_extension("txt");
+
+    if path.exists() {
+        Some(path)
+    } else if legacy.exists() {
+        Some(legacy)
+    } else {
+        None
+    }
+}
+
+fn hostname() -> String {
+    fs::read_to_string("/proc/sys/kernel/hostname")
+        .or_else(|_| fs::read_to_string("/etc/hostname"))
+        .map(|name| name.trim().to_string())
+        .ok()
+        .filter(|name| !name.is_empty())
+        .or_else(|| env::var("HOSTNAME").ok())
+        .unwrap_or_else(|| UNKNOWN.to_string())
+}
+
+fn desktop() -> String {
+    env::var("XDG_CURRENT_DESKTOP")
+        .or_else(|_| env::var("DESKTOP_SESSION"))
+        .ok()
+        .filter(|desktop| !desktop.is_empty())
+        .unwrap_or_else(|| UNKNOWN.to_string())
+}
+
+/// **How many archived snapshots to keep**
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Retention {
+    /// Number of most recent snapshots to keep
+    pub keep: usize,
+    /// Snapshots older than this many days are removed, if set
+    pub max_age_days: Option<u32>,
+}
+
+/// **Directory of archived snapshots**
+pub struct Store {
+    dir: PathBuf,
+}
+
+impl Store {
+    /// **Locates the snapshot store** under the XDG state directory
+    ///
+    /// ## Returns:
+    ///
+    /// The store, or an `Error::NotFound` if the state directory can't be determined.
+    pub fn open() -> Result<Self> {
+        let dir = dirs
+            ::state_dir()
+            .ok_or(Error::NotFound("Unable to locate the user state directory".into()))?
+            .join("incognito")
+            .join("snapshots");
+
+        Ok(Store::at(dir))
+    }
+
+    /// **A snapshot store kept in `dir`**, created on the first save
+    pub fn at(dir: impl Into<PathBuf>) -> Self {
+        Store { dir: dir.into() }
+    }
+
+    /// **Path of the archived snapshot with the given `id`**
+    ///
+    /// ## Returns:
+    ///
+    /// The path, or an `Error::NotFound` if `id` can't name a snapshot in the store (e.g. `../config`).
+    pub fn path(&self, id: &str) -> Result<PathBuf> {
+        if id.is_empty() || id.contains('/') || id.contains("..") {
+            return Err(Error::NotFound(format!("No snapshot with id {}: ids can't contain '/' or '..'", id)));
+        }
+
+        Ok(self.dir.join(format!("{}.toml", id)))
+    }
+
+    /// **Archives `snapshot`**, creating the store if needed
+    ///
+    /// ## Returns:
+    ///
+    /// The path of the archived snapshot, or an `Error` if it can't be written or a snapshot with the same
+    /// id is already archived, which is never overwritten.
+    pub fn save(&self, snapshot: &Snapshot) -> Result<PathBuf> {
+        let path = self.path(&snapshot.id)?;
+        let contents = snapshot.to_toml()?;
+        fs::create_dir_all(&self.dir).map_err(|why| Error::io("create", &self.dir, why))?;
+
+        OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&path)
+            .and_then(|mut file| file.write_all(contents.as_bytes()))
+            .map_err(|why| Error::io("write to", &path, why))?;
+
+        Ok(path)
+    }
+
+    /// **Reads the archived snapshot with the given `id`**
+    pub fn get(&self, id: &str) -> Result<Snapshot> {
+        let path = self.path(id)?;
+
+        match path.exists() {
+            true => Snapshot::load(&path),
+            false => Err(Error::NotFound(format!("No snapshot with id {}", id))),
+        }
+    }
+
+    /// **Reads every archived snapshot**, oldest first
+    ///
+    /// ## Returns:
+    ///
+    /// The snapshots, along with the `Error` of every file that couldn't be read, which is left out so a
+    /// single damaged file doesn't hide the others. Fails only if the store itself can't be read.
+    pub fn list(&self) -> Result<(Vec<Snapshot>, Vec<Error>)> {
+        let entries = match fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(why) if why.kind() == std::io::ErrorKind::NotFound => {
+                return Ok((Vec::new(), Vec::new()));
+            }
+            Err(why) => {
+                return Err(Error::io("read", &self.dir, why));
+            }
+        };
+
+        let mut snapshots = Vec::new();
+        let mut skipped = Vec::new();
+        for entry in entries {
+            let path = entry.map_err(|why| Error::io("read", &self.dir, why))?.path();
+            if path.extension().is_some_and(|extension| extension == "toml") {
+                match Snapshot::load(&path) {
+                    Ok(snapshot) => snapshots.push(snapshot),
+                    Err(why) => skipped.push(why),
+                }
+            }
+        }
+        snapshots.sort_by(|a, b| a.id.cmp(&b.id));
+
+        Ok((snapshots, skipped))
+    }
+
+    /// **Removes archived snapshots that fall outside of `retention`**
+    ///
+    /// The most recent snapshot is always kept, and files that can't be read are left alone.
+    ///
+    /// ## Returns:
+    ///
+    /// The ids of the removed snapshots, or an `Error` if the store can't be read or a snapshot removed.
+    pub fn prune(&self, retention: Retention) -> Result<Vec<String>> {
+        let (snapshots, _) = self.list()?;
+        let cutoff = retention.max_age_days.map(|days| Local::now() - Duration::days(days.into()));
+        let keep = retention.keep.max(1);
+
+        let mut removed = Vec::new();
+        for (age, snapshot) in snapshots.iter().rev().enumerate() {
+            let expired = match (cutoff, DateTime::parse_from_rfc3339(&snapshot.timestamp)) {
+                (Some(cutoff), Ok(taken)) => taken < cutoff,
+                _ => false,
+            };
+
+            if age > 0 && (age >= keep || expired) {
+                let path = self.path(&snapshot.id)?;
+                fs::remove_file(&path).map_err(|why| Error::io("remove", &path, why))?;
+                removed.push(snapshot.id.clone());
+            }
+        }
+
+        Ok(removed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::backend::MemoryBackend;
+
+    fn store() -> (tempfile::TempDir, Store) {
+        let dir = tempfile::tempdir().unwrap();
+        let store = Store::at(dir.path().join("snapshots"));
+        (dir, store)
+    }
+
+    fn snapshot(id: &str) -> Snapshot {
+        let backend = MemoryBackend::with([("/org/gnome/desktop/interface/gtk-theme", Variant::String("Yaru".into()))]);
+        Snapshot { id: id.to_string(), ..Snapshot::capture(&backend).unwrap() }
+    }
+
+    #[test]
+    fn ids_tell_snapshots_of_the_same_second_apart() {
+        let first = Snapshot::capture(&MemoryBackend::default()).unwrap();
+        std::thread::sleep(std::time::Duration::from_millis(2));
+        let second = Snapshot::capture(&MemoryBackend::default()).unwrap();
+
+        assert_ne!(first.id, second.id);
+        assert_eq!(first.id.len(), "20240501-093000-417".len());
+    }
+
+    #[test]
+    fn only_serializes_values_that_read_back_the_same() {
+        let mut saved = snapshot("20240501-093000-417");
+        saved.entries.insert("/org/gnome/desktop/peripherals/mouse/speed".into(), Variant::Double(f64::NAN));
+        saved.entries.insert("/org/gnome/desktop/peripherals/mouse/accel".into(), Variant::Double(f64::NEG_INFINITY));
+        saved.entries.insert(
+            "/org/gnome/desktop/background/picture-uri".into(),
+            Variant::Array(
+                "y".into(),
+                b"file:///it's \"quoted\" \\\n\x01\0".iter().map(|byte| Variant::Byte(*byte)).collect()
+            )
+        );
+
+        let text = saved.to_toml().unwrap();
+        assert_eq!(toml::from_str::<Snapshot>(&text).unwrap(), saved);
+
+        // An array whose elements don't match its type prints as a value of another type
+        saved.entries.insert(
+            "/org/gnome/desktop/input-sources/sources".into(),
+            Variant::Array("i".into(), vec![Variant::String("us".into())])
+        );
+        assert!(matches!(saved.to_toml(), Err(Error::Other(message)) if message.contains("input-sources")));
+    }
+
+    #[test]
+    fn never_overwrites_an_archived_snapshot() {
+        let (_dir, store) = store();
+        let original = snapshot("20240501-093000-417");
+        let path = store.save(&original).unwrap();
+
+        let mut other = original.clone();
+        other.entries.clear();
+        assert!(matches!(store.save(&other), Err(Error::Io { .. })));
+        assert_eq!(Snapshot::load(&path).unwrap(), original);
+    }
+
+    #[test]
+    fn rejects_ids_outside_of_the_store() {
+        let (_dir, store) = store();
+
+        for id in ["../current_system_config", "a/b", "..", ""] {
+            assert!(matches!(store.path(id), Err(Error::NotFound(_))), "{}", id);
+            assert!(matches!(store.get(id), Err(Error::NotFound(_))), "{}", id);
+        }
+        assert!(store.save(&snapshot("../escaped")).is_err());
+        assert!(store.path("20240501-093000-417").is_ok());
+    }
+
+    #[test]
+    fn skips_unreadable_snapshots() {
+        let (_dir, store) = store();
+        store.save(&snapshot("20240501-093000-417")).unwrap();
+        store.save(&snapshot("20240502-093000-417")).unwrap();
+        fs::write(store.dir.join("20240503-093000-417.toml"), "format = 1\nid = [").unwrap();
+
+        let (snapshots, skipped) = store.list().unwrap();
+        let ids: Vec<&str> = snapshots
+            .iter()
+            .map(|snapshot| snapshot.id.as_str())
+            .collect();
+        assert_eq!(ids, ["20240501-093000-417", "20240502-093000-417"]);
+        assert!(matches!(skipped[..], [Error::Corrupt { .. }]));
+
+        let removed = store.prune(Retention { keep: 1, max_age_days: None }).unwrap();
+        assert_eq!(removed, ["20240501-093000-417"]);
+        assert!(store.dir.join("20240503-093000-417.toml").exists());
+    }
+}
@@ -0,0 +1,82 @@
+//! # Incognito State Module
+//! This module persists whether incognito mode is currently active, so that enabling it a second time
+//! never overwrites the snapshot of the user's real desktop with the incognito settings.
+//!
+//! The state is stored as a small TOML file next to the config snapshot it describes
+//! (e.g. `current_system_config.state.toml` for `current_system_config.toml`).
+use crate::utils::error::{ Error, Result };
+use crate::utils::gvariant::Variant;
+use crate::utils::snapshot::Snapshot;
+use serde::{ Deserialize, Serialize };
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{ Path, PathBuf };
+
+/// **Persisted incognito state**
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct State {
+    /// Whether the incognito settings are currently applied
+    pub active: bool,
+    /// Identifier of the snapshot taken right before incognito was enabled
+    pub snapshot_id: String,
+    /// Path of that snapshot
+    pub snapshot: String,
+    /// When the snapshot was taken, in RFC 3339 format
+    pub timestamp: String,
+    /// Name of the profile applied while incognito was enabled
+    #[serde(default)]
+    pub profile: String,
+    /// Keys written while incognito was enabled, with the values written to them
+    #[serde(default)]
+    pub applied: BTreeMap<String, Variant>,
+}
+
+impl State {
+    /// Creates an active state for `snapshot`, which was just written to `path`
+    pub fn activated(snapshot: &Snapshot, path: &str) -> Self {
+        State {
+            active: true,
+            snapshot_id: snapshot.id.clone(),
+            snapshot: path.to_string(),
+            timestamp: snapshot.timestamp.clone(),
+            profile: String::new(),
+            applied: BTreeMap::new(),
+        }
+    }
+
+    /// **Reads the state file**
+    ///
+    /// ## Returns:
+    ///
+    /// The stored state, `None` if incognito has never been enabled, or an `Error` if the file can't be
+    /// read or parsed.
+    pub fn load(path: &Path) -> Result<Option<State>> {
+        match fs::read_to_string(path) {
+            Ok(contents) =>
+                toml
+                    ::from_str(&contents)
+                    .map(Some)
+                    .map_err(|why| Error::corrupt(path, why)),
+            Err(why) if why.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(why) => Err(Error::io("read", path, why)),
+        }
+    }
+
+    /// **Writes the state file**, creating its directory if needed
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let contents = toml
+            ::to_string(self)
+            .map_err(|why| Error::Other(format!("Couldn't serialize the incognito state: {}", why)))?;
+
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).map_err(|why| Error::io("create", dir, why))?;
+        }
+
+        fs::write(path, contents).map_err(|why| Error::io("write to", path, why))
+    }
+}
+
+/// Path of the state file that belongs to the config snapshot at `config`
+pub fn path_for(config: &str) -> PathBuf {
+    Path::new(config).with_extension("state.toml")
+}
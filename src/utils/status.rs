@@ -0,0 +1,189 @@
+//! # Status Module
+//! This module works out whether the desktop is disguised right now, as reported by `status`.
+//!
+//! The incognito state file only records what the tool did last; the desktop may have changed since
+//! (a restore done by hand, a profile applied with another tool, a partial failure). So the **`Status`**
+//! reads the live value of every managed key and compares it with the value incognito writes to it:
+//!
+//! * **active** - every managed key holds its incognito value
+//! * **partial** - only some of them do
+//! * **inactive** - none of them do
+//!
+//! The managed keys are the ones recorded in the state while incognito is on, or the keys of the
+//! profile it was last enabled with otherwise. Keys whose incognito value is also the value saved in the
+//! snapshot can't tell the two desktops apart, so they're left out of the comparison.
+use crate::utils::backend::SettingsBackend;
+use crate::utils::error::Result;
+use crate::utils::gvariant::Variant;
+use crate::utils::profile::{ Profile, DEFAULT_PROFILE };
+use crate::utils::snapshot::{ self, Snapshot };
+use crate::utils::state::{ self, State };
+use chrono::{ DateTime, Local };
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+/// **Whether the incognito look is on the desktop**
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Disguise {
+    Active,
+    Partial,
+    Inactive,
+}
+
+impl Disguise {
+    /// Name of the disguise state, as printed by `status --short`
+    pub fn name(&self) -> &'static str {
+        match self {
+            Disguise::Active => "active",
+            Disguise::Partial => "partial",
+            Disguise::Inactive => "inactive",
+        }
+    }
+}
+
+/// **What `status` reports**
+#[derive(Debug, Clone, Serialize)]
+pub struct Status {
+    /// Whether the desktop is disguised, from the live settings
+    pub disguise: Disguise,
+    /// Profile the incognito values come from
+    pub profile: String,
+    /// Number of managed keys compared with their incognito value
+    pub managed: usize,
+    /// Managed keys that don't hold their incognito value, with their live value (`None` if unset)
+    pub differing: BTreeMap<String, Option<Variant>>,
+    /// Snapshot a restore would use, `None` if there is none
+    pub snapshot: Option<PathBuf>,
+    /// Why that snapshot can't be used, `None` if it can
+    pub snapshot_error: Option<String>,
+    /// Age of the snapshot, in seconds
+    pub snapshot_age: Option<i64>,
+    /// The saved incognito state, `None` if incognito was never enabled
+    pub state: Option<State>,
+}
+
+impl Status {
+    /// **Compares the live settings of `backend` with the values incognito writes**
+    ///
+    /// ## Args:
+    ///
+    /// * `backend` - The settings store to read the live values from
+    /// * `config` - Path of the config snapshot, next to which the incognito state is kept
+    ///
+    /// ## Returns:
+    ///
+    /// The status, or an `Error` if the state file can't be read. A missing or unreadable snapshot is
+    /// reported in the status rather than as an error.
+    pub fn check(backend: &dyn SettingsBackend, config: &str) -> Result<Self> {
+        let state = State::load(&state::path_for(config))?;
+
+        let path = snapshot::locate(config);
+        let saved = path.as_deref().map(Snapshot::load);
+        let snapshot_error = match &saved {
+            Some(Err(why)) => Some(why.to_string()),
+            Some(Ok(_)) => None,
+            None => Some(format!("{} doesn't exist", config)),
+        };
+        let saved = saved.and_then(|saved| saved.ok());
+
+        let timestamp = state
+            .as_ref()
+            .map(|state| state.timestamp.clone())
+            .or_else(|| saved.as_ref().map(|saved| saved.timestamp.clone()));
+        let snapshot_age = timestamp
+            .and_then(|timestamp| DateTime::parse_from_rfc3339(&timestamp).ok())
+            .map(|taken| (Local::now() - DateTime::<Local>::from(taken)).num_seconds());
+
+        let (profile, expected) = incognito_values(state.as_ref());
+        let mut managed = 0;
+        let mut differing = BTreeMap::new();
+        for (key, value) in expected {
+            let indistinct = saved.as_ref().is_some_and(|saved| saved.entries.get(&key) == Some(&value));
+            if indistinct {
+                continue;
+            }
+
+            managed += 1;
+            // A key that can't be read can't be shown to hold its incognito value either
+            let current = backend.read(&key).unwrap_or(None);
+            if current.as_ref() != Some(&value) {
+                differing.insert(key, current);
+            }
+        }
+
+        let disguise = match differing.len() {
+            0 if managed > 0 => Disguise::Active,
+            count if count < managed => Disguise::Partial,
+            _ => Disguise::Inactive,
+        };
+
+        Ok(Status { disguise, profile, managed, differing, snapshot: path, snapshot_error, snapshot_age, state })
+    }
+}
+
+/// **The value incognito writes to every managed key**
+///
+/// ## Returns:
+///
+/// The name of the profile the values come from, and the values: the ones recorded in `state`, or
+/// those of the profile it names (the default one if it names none, or can't be resolved anymore).
+fn incognito_values(state: Option<&State>) -> (String, BTreeMap<String, Variant>) {
+    let name = state
+        .map(|state| state.profile.clone())
+        .filter(|name| !name.is_empty())
+        .unwrap_or_else(|| DEFAULT_PROFILE.to_string());
+
+    match state {
+        Some(state) if !state.applied.is_empty() => (name, state.applied.clone()),
+        _ => {
+            let layers: Vec<String> = name.split('+').map(String::from).collect();
+            let values = Profile::compose(&layers)
+                .or_else(|_| Profile::resolve(DEFAULT_PROFILE))
+                .and_then(|profile| profile.changes())
+                .unwrap_or_default();
+            (name, values)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::backend::fixtures::{ desktop, string, ICONS, THEME };
+    use std::fs;
+
+    #[test]
+    fn compares_the_live_settings_with_the_incognito_values() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = dir.path().join("current_system_config.toml").to_string_lossy().into_owned();
+        let backend = desktop();
+
+        let snapshot = Snapshot::capture(&backend).unwrap();
+        fs::write(&config, snapshot.to_toml().unwrap()).unwrap();
+        let mut state = State::activated(&snapshot, &config);
+        state.applied = BTreeMap::from([(THEME.to_string(), string("Fluent")), (ICONS.to_string(), string("Yaru"))]);
+        state.save(&state::path_for(&config)).unwrap();
+
+        // The icons are the same on both desktops, so only the theme tells them apart
+        let status = Status::check(&backend, &config).unwrap();
+        assert_eq!(status.disguise, Disguise::Inactive);
+        assert_eq!(status.managed, 1);
+        assert_eq!(status.differing, BTreeMap::from([(THEME.to_string(), Some(string("Yaru")))]));
+        assert!(status.snapshot_error.is_none());
+        assert!(status.snapshot_age.is_some_and(|age| age >= 0));
+
+        backend.write(THEME, &string("Fluent")).unwrap();
+        assert_eq!(Status::check(&backend, &config).unwrap().disguise, Disguise::Active);
+
+        state.applied.insert(ICONS.to_string(), string("Windows-Eleven"));
+        state.save(&state::path_for(&config)).unwrap();
+        let status = Status::check(&backend, &config).unwrap();
+        assert_eq!(status.disguise, Disguise::Partial);
+        assert_eq!(status.differing, BTreeMap::from([(ICONS.to_string(), Some(string("Yaru")))]));
+
+        fs::remove_file(&config).unwrap();
+        assert!(Status::check(&backend, &config).unwrap().snapshot_error.is_some());
+    }
+}